@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{
+        canvas::{Canvas, Rectangle},
+        Block, List, ListItem, ListState, Paragraph, Wrap,
+    },
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    complete::HeadIdentity,
+    formats::ConnectedHead,
+    scale::logical_size,
+    serde::{SavedConfiguration, Transform},
+};
+
+/// How far (in logical pixels) the arrow keys move a head, and how far they move it with shift.
+const STEP: i32 = 10;
+const BIG_STEP: i32 = 100;
+/// How much `+` and `-` change the scale by, and the range they stay in.
+const SCALE_STEP: f64 = 0.25;
+const SCALE_RANGE: (f64, f64) = (0.5, 4.0);
+
+/// The keys, shown below the heads.
+const HELP: &str = concat!(
+    "tab: next head  arrows/hjkl: move (shift: faster)  s: snap  e: enable/disable  r: rotate  ",
+    "+/-: scale  enter: apply and save  q: quit",
+);
+
+/// A head being arranged. Positions are signed while arranging, and are shifted so the top-left
+/// head is at the origin once done.
+struct ArrangedHead {
+    identity: HeadIdentity,
+    configuration: SavedConfiguration,
+    position: (i32, i32),
+    enabled: bool,
+}
+
+impl ArrangedHead {
+    /// The size of the head in logical pixels, or [`None`] if it has no mode.
+    fn size(&self) -> Option<(i32, i32)> {
+        logical_size(&self.configuration)
+            .map(|(width, height)| (width.round() as i32, height.round() as i32))
+    }
+
+    /// The position and size of the head if it is enabled and has a mode.
+    fn rect(&self) -> Option<(i32, i32, i32, i32)> {
+        if !self.enabled {
+            return None;
+        }
+        let (width, height) = self.size()?;
+        Some((self.position.0, self.position.1, width, height))
+    }
+}
+
+/// Lets the user arrange `heads` in the terminal. Returns the arranged configuration of each head,
+/// or [`None`] if the user quit without accepting it.
+pub fn run(
+    heads: Vec<ConnectedHead>,
+) -> Result<Option<HashMap<HeadIdentity, Option<SavedConfiguration>>>, std::io::Error> {
+    let mut heads = heads
+        .into_iter()
+        .map(|head| {
+            let mut configuration = head.base_configuration();
+            // Every property is chosen here, so none are left to the compositor.
+            configuration.dont_care = Default::default();
+            ArrangedHead {
+                position: (
+                    configuration.position.0 as i32,
+                    configuration.position.1 as i32,
+                ),
+                enabled: head.configuration.is_some(),
+                identity: head.identity,
+                configuration,
+            }
+        })
+        .collect::<Vec<_>>();
+    heads.sort_by(|a, b| a.identity.name.cmp(&b.identity.name));
+
+    let mut terminal = ratatui::try_init()?;
+    let result = run_loop(&mut terminal, &mut heads);
+    ratatui::restore();
+    if !result? {
+        return Ok(None);
+    }
+
+    let min_x = heads
+        .iter()
+        .filter_map(ArrangedHead::rect)
+        .map(|rect| rect.0);
+    let min_y = heads
+        .iter()
+        .filter_map(ArrangedHead::rect)
+        .map(|rect| rect.1);
+    let offset = (min_x.min().unwrap_or(0), min_y.min().unwrap_or(0));
+    Ok(Some(
+        heads
+            .into_iter()
+            .map(|mut head| {
+                if !head.enabled {
+                    return (head.identity, None);
+                }
+                head.configuration.position = (
+                    (head.position.0 - offset.0) as u32,
+                    (head.position.1 - offset.1) as u32,
+                );
+                (head.identity, Some(head.configuration))
+            })
+            .collect(),
+    ))
+}
+
+/// Draws the heads and handles keys until the user accepts (returning true) or quits.
+fn run_loop(
+    terminal: &mut DefaultTerminal,
+    heads: &mut [ArrangedHead],
+) -> Result<bool, std::io::Error> {
+    let mut selected = 0;
+    loop {
+        terminal.draw(|frame| draw(frame, heads, selected))?;
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event::read()?
+        else {
+            continue;
+        };
+        let step = if modifiers.contains(KeyModifiers::SHIFT) {
+            BIG_STEP
+        } else {
+            STEP
+        };
+        let head = &mut heads[selected];
+        match code {
+            KeyCode::Enter => return Ok(true),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            KeyCode::Tab => selected = (selected + 1) % heads.len(),
+            KeyCode::BackTab => selected = (selected + heads.len() - 1) % heads.len(),
+            KeyCode::Left | KeyCode::Char('h') => head.position.0 -= step,
+            KeyCode::Char('H') => head.position.0 -= BIG_STEP,
+            KeyCode::Right | KeyCode::Char('l') => head.position.0 += step,
+            KeyCode::Char('L') => head.position.0 += BIG_STEP,
+            KeyCode::Up | KeyCode::Char('k') => head.position.1 -= step,
+            KeyCode::Char('K') => head.position.1 -= BIG_STEP,
+            KeyCode::Down | KeyCode::Char('j') => head.position.1 += step,
+            KeyCode::Char('J') => head.position.1 += BIG_STEP,
+            KeyCode::Char('s') => snap(heads, selected),
+            KeyCode::Char('e') => {
+                head.enabled = !head.enabled;
+                if head.enabled {
+                    // Start to the right of the other heads, so it doesn't overlap them.
+                    let right = heads
+                        .iter()
+                        .enumerate()
+                        .filter(|(index, _)| *index != selected)
+                        .filter_map(|(_, head)| head.rect())
+                        .map(|(x, _, width, _)| x + width)
+                        .max()
+                        .unwrap_or(0);
+                    heads[selected].position = (right, 0);
+                }
+            }
+            KeyCode::Char('r') => {
+                head.configuration.transform = rotate(head.configuration.transform);
+            }
+            KeyCode::Char('+' | '=') => {
+                head.configuration.scale =
+                    (head.configuration.scale + SCALE_STEP).min(SCALE_RANGE.1);
+            }
+            KeyCode::Char('-') => {
+                head.configuration.scale =
+                    (head.configuration.scale - SCALE_STEP).max(SCALE_RANGE.0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Rotates `transform` by 90 degrees clockwise, keeping whether it is flipped.
+fn rotate(transform: Transform) -> Transform {
+    match transform {
+        Transform::Normal => Transform::_90,
+        Transform::_90 => Transform::_180,
+        Transform::_180 => Transform::_270,
+        Transform::_270 => Transform::Normal,
+        Transform::Flipped => Transform::Flipped90,
+        Transform::Flipped90 => Transform::Flipped180,
+        Transform::Flipped180 => Transform::Flipped270,
+        Transform::Flipped270 => Transform::Flipped,
+    }
+}
+
+/// Moves the head at `selected` to the closest position where it touches an edge of another
+/// enabled head, aligned with that head's top or left edge.
+fn snap(heads: &mut [ArrangedHead], selected: usize) {
+    let Some((x, y, width, height)) = heads[selected].rect() else {
+        return;
+    };
+    let closest = heads
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != selected)
+        .filter_map(|(_, head)| head.rect())
+        .flat_map(|(other_x, other_y, other_width, other_height)| {
+            [
+                (other_x + other_width, other_y),
+                (other_x - width, other_y),
+                (other_x, other_y + other_height),
+                (other_x, other_y - height),
+            ]
+        })
+        .min_by_key(|(candidate_x, candidate_y)| {
+            (*candidate_x - x).pow(2) as i64 + (*candidate_y - y).pow(2) as i64
+        });
+    if let Some(position) = closest {
+        heads[selected].position = position;
+    }
+}
+
+fn draw(frame: &mut Frame, heads: &[ArrangedHead], selected: usize) {
+    let [canvas_area, list_area, help_area] = Layout::vertical([
+        Constraint::Min(8),
+        Constraint::Length(heads.len() as u16 + 2),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+
+    // Fit every enabled head (with some margin) in the canvas. The canvas' y axis points up, so
+    // heads are drawn upside down from their positions.
+    let rects = heads.iter().map(ArrangedHead::rect).collect::<Vec<_>>();
+    let (mut min, mut max) = ((0.0f64, 0.0f64), (1.0f64, 1.0f64));
+    for &(x, y, width, height) in rects.iter().flatten() {
+        min = (min.0.min(x as f64), min.1.min(y as f64));
+        max = (
+            max.0.max((x + width) as f64),
+            max.1.max((y + height) as f64),
+        );
+    }
+    let margin = ((max.0 - min.0).max(max.1 - min.1)) * 0.05;
+    let canvas = Canvas::default()
+        .block(Block::bordered().title(" wl-distore arrange "))
+        .x_bounds([min.0 - margin, max.0 + margin])
+        .y_bounds([-max.1 - margin, -min.1 + margin])
+        .paint(|context| {
+            for (index, rect) in rects.iter().enumerate() {
+                let Some((x, y, width, height)) = *rect else {
+                    continue;
+                };
+                let color = if index == selected {
+                    Color::Yellow
+                } else {
+                    Color::Cyan
+                };
+                context.draw(&Rectangle {
+                    x: x as f64,
+                    y: -(y + height) as f64,
+                    width: width as f64,
+                    height: height as f64,
+                    color,
+                });
+                context.print(
+                    x as f64 + width as f64 * 0.05,
+                    -y as f64 - height as f64 * 0.1,
+                    Line::styled(heads[index].identity.name.clone(), color),
+                );
+            }
+        });
+    frame.render_widget(canvas, canvas_area);
+
+    let items = heads.iter().map(|head| {
+        let configuration = &head.configuration;
+        let details = if head.enabled {
+            format!(
+                "{} at {},{} scale {} transform {}",
+                configuration
+                    .mode
+                    .map(|mode| mode.to_string())
+                    .unwrap_or_else(|| "no mode".into()),
+                head.position.0,
+                head.position.1,
+                configuration.scale,
+                configuration.transform.name()
+            )
+        } else {
+            "disabled".to_string()
+        };
+        ListItem::new(format!(
+            "{} \"{}\": {details}",
+            head.identity.name, head.identity.description
+        ))
+    });
+    let list = List::new(items)
+        .block(Block::bordered().title(" Heads "))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(
+        list,
+        list_area,
+        &mut ListState::default().with_selected(Some(selected)),
+    );
+
+    frame.render_widget(Paragraph::new(HELP).wrap(Wrap { trim: true }), help_area);
+}
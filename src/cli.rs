@@ -0,0 +1,980 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Read, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    complete::{HeadIdentity, Mode},
+    config::{Args, CommandLine},
+    formats::{self, ExportFormat},
+    history,
+    ipc::{self, IpcError, Request, Response},
+    output::{self, OutputFormat},
+    persist::write_atomically,
+    serde::{
+        format_version, validate, Layout, LayoutData, LayoutMatchScore, Recovery,
+        SavedConfiguration, LAYOUTS_VERSION,
+    },
+    state,
+};
+
+/// A summary of a saved layout, as printed by `list`.
+#[derive(Serialize)]
+struct LayoutSummary<'a> {
+    index: usize,
+    name: Option<&'a str>,
+    profile: &'a str,
+    auto_apply: bool,
+    read_only: bool,
+    pinned: bool,
+    priority: i32,
+    comment: Option<&'a str>,
+    heads: Vec<&'a HeadIdentity>,
+}
+
+/// A saved layout and its head configurations, as printed by `show`.
+#[derive(Serialize)]
+struct LayoutDetails<'a> {
+    index: usize,
+    name: Option<&'a str>,
+    profile: &'a str,
+    auto_apply: bool,
+    read_only: bool,
+    pinned: bool,
+    comment: Option<&'a str>,
+    heads: Vec<HeadDetails<'a>>,
+}
+
+#[derive(Serialize)]
+struct HeadDetails<'a> {
+    identity: &'a HeadIdentity,
+    configuration: Option<&'a SavedConfiguration>,
+    comment: Option<&'a str>,
+}
+
+/// The problems found in a layouts file, as printed by `validate`.
+#[derive(Serialize)]
+struct ValidationResult<'a> {
+    path: &'a Path,
+    problems: Vec<String>,
+}
+
+/// The layout matching the current heads, as printed by `which`.
+#[derive(Serialize)]
+struct MatchDetails<'a> {
+    index: usize,
+    name: Option<&'a str>,
+    /// Whether every head matched exactly. Otherwise, some heads of the layout were matched to
+    /// current heads leniently (e.g., a monitor that moved to another port).
+    exact: bool,
+    /// The names of the layout's heads that were matched to differently named current heads, and
+    /// the names of those current heads.
+    remapped: Vec<(&'a str, &'a str)>,
+}
+
+/// A property of a head that differs between a saved layout and the current configuration, as
+/// printed by `diff`.
+#[derive(Serialize)]
+struct HeadDifference<'a> {
+    /// The name of the current head (which may differ from the name in the layout).
+    head: &'a str,
+    property: &'static str,
+    saved: String,
+    current: String,
+}
+
+/// Loads the layouts referred to by `args`, exiting if they can't be read.
+pub fn load_layouts(args: &Args) -> LayoutData {
+    match LayoutData::load_with_read_only(
+        &args.seed_layouts,
+        args.layouts_ro.as_deref(),
+        &args.layouts,
+        args.layouts_dir.as_deref(),
+        Recovery::InPlace,
+    ) {
+        Ok(layout_data) => layout_data,
+        Err(err) => {
+            eprintln!("Failed to load layouts: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Saves `layout_data` to the layouts file referred to by `args`, along with its metadata and a
+/// snapshot. Nothing is written in a dry run.
+pub fn write_layouts(args: &Args, layout_data: &mut LayoutData) -> Result<(), std::io::Error> {
+    if args.dry_run {
+        info!("Not saving the layouts since this is a dry run");
+        return Ok(());
+    }
+    layout_data.generation += 1;
+    layout_data.save_to(&args.layouts, args.layouts_dir.as_deref())?;
+    write_atomically(
+        &state::state_path(&args.layouts),
+        &state::to_json(layout_data)?,
+    )?;
+    if let Ok(contents) = layout_data.to_json() {
+        history::record(&args.layouts, &contents, args.history_size);
+    }
+    Ok(())
+}
+
+/// Like [`write_layouts`], but exits if the layouts can't be saved.
+fn save_layouts(args: &Args, layout_data: &mut LayoutData) {
+    if let Err(err) = write_layouts(args, layout_data) {
+        eprintln!("Failed to save layouts: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Prints a summary of every saved layout.
+pub fn list_layouts(args: &Args) {
+    let layout_data = load_layouts(args);
+    let summaries = layout_data
+        .layouts
+        .iter()
+        .enumerate()
+        .map(|(index, layout)| {
+            let mut heads = layout.heads.keys().collect::<Vec<_>>();
+            heads.sort_by(|a, b| a.name.cmp(&b.name));
+            LayoutSummary {
+                index,
+                name: layout.name.as_deref(),
+                profile: layout.profile(),
+                auto_apply: layout.auto_apply,
+                read_only: layout.read_only,
+                pinned: layout.pinned,
+                priority: layout.priority,
+                comment: layout.comment.as_deref(),
+                heads,
+            }
+        })
+        .collect::<Vec<_>>();
+    let rows = summaries
+        .iter()
+        .map(|summary| {
+            vec![
+                summary.index.to_string(),
+                summary.name.unwrap_or("-").to_string(),
+                summary.profile.to_string(),
+                summary.auto_apply.to_string(),
+                summary.read_only.to_string(),
+                summary.pinned.to_string(),
+                summary.priority.to_string(),
+                summary
+                    .heads
+                    .iter()
+                    .map(|head| head.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                summary.comment.unwrap_or("-").to_string(),
+            ]
+        })
+        .collect();
+    output::print(
+        args.format,
+        &summaries,
+        &[
+            "INDEX",
+            "NAME",
+            "PROFILE",
+            "AUTO_APPLY",
+            "READ_ONLY",
+            "PINNED",
+            "PRIORITY",
+            "HEADS",
+            "COMMENT",
+        ],
+        rows,
+    );
+}
+
+/// Lets the user pick a layout with the `picker` command for `apply --interactive`, and returns
+/// its index. Each layout is given to the picker as a line starting with its index, followed by
+/// its name and heads. Exits if nothing is picked.
+pub fn pick_layout(args: &Args) -> String {
+    let layout_data = load_layouts(args);
+    if layout_data.layouts.is_empty() {
+        eprintln!("There are no layouts to pick from");
+        std::process::exit(1);
+    }
+    let lines = layout_data
+        .layouts
+        .iter()
+        .enumerate()
+        .map(|(index, layout)| {
+            let mut heads = layout
+                .heads
+                .keys()
+                .map(|head| head.description.as_str())
+                .collect::<Vec<_>>();
+            heads.sort();
+            format!(
+                "{index}\t{}\t{}\n",
+                layout.name.as_deref().unwrap_or("-"),
+                heads.join(", ")
+            )
+        })
+        .collect::<String>();
+
+    let picked = CommandLine::Shell(args.picker.clone())
+        .to_command(&args.command_shell)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            // Dropping stdin closes it, so the picker knows there are no more lines.
+            child
+                .stdin
+                .take()
+                .expect("stdin is piped")
+                .write_all(lines.as_bytes())?;
+            child.wait_with_output()
+        });
+    let output = match picked {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("Failed to run the picker \"{}\": {err}", args.picker);
+            std::process::exit(1);
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let index = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split('\t').next())
+        .map(str::trim)
+        .filter(|index| !index.is_empty());
+    match index {
+        Some(index) if output.status.success() => index.to_string(),
+        _ => {
+            eprintln!("No layout was picked");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the layout at `index` as the match for the current heads, where `score` and
+/// `layout_head_to_query_head` are returned by [`LayoutData::find_layout_match`].
+pub fn print_match(
+    format: OutputFormat,
+    layout_data: &LayoutData,
+    index: usize,
+    score: LayoutMatchScore,
+    layout_head_to_query_head: &HashMap<HeadIdentity, HeadIdentity>,
+) {
+    let mut remapped = layout_head_to_query_head
+        .iter()
+        .filter(|(layout_head, query_head)| layout_head.name != query_head.name)
+        .map(|(layout_head, query_head)| (layout_head.name.as_str(), query_head.name.as_str()))
+        .collect::<Vec<_>>();
+    remapped.sort();
+    let details = MatchDetails {
+        index,
+        name: layout_data.layouts[index].name.as_deref(),
+        exact: score == LayoutMatchScore::Exact,
+        remapped,
+    };
+    let row = vec![
+        details.index.to_string(),
+        details.name.unwrap_or("-").to_string(),
+        if details.exact { "exact" } else { "lenient" }.to_string(),
+        if details.remapped.is_empty() {
+            "-".to_string()
+        } else {
+            details
+                .remapped
+                .iter()
+                .map(|(layout_head, query_head)| format!("{layout_head}->{query_head}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        },
+    ];
+    output::print(
+        format,
+        &details,
+        &["INDEX", "NAME", "MATCH", "REMAPPED"],
+        vec![row],
+    );
+}
+
+/// Prints the properties of the heads in `layout` that differ from `current_layout`, where
+/// `layout_head_to_query_head` is the mapping returned by [`LayoutData::find_layout_match`].
+/// Properties the layout doesn't care about are skipped.
+pub fn print_diff(
+    format: OutputFormat,
+    layout: &Layout,
+    layout_head_to_query_head: &HashMap<HeadIdentity, HeadIdentity>,
+    current_layout: &HashMap<HeadIdentity, Option<SavedConfiguration>>,
+) {
+    let mut differences = vec![];
+    for (identity, configuration) in layout.heads.iter() {
+        let identity = layout_head_to_query_head.get(identity).unwrap_or(identity);
+        let Some(current) = current_layout.get(identity) else {
+            differences.push(HeadDifference {
+                head: &identity.name,
+                property: "enabled",
+                saved: configuration.is_some().to_string(),
+                current: "-".to_string(),
+            });
+            continue;
+        };
+        let (Some(configuration), Some(current)) = (configuration, current) else {
+            if configuration.is_some() != current.is_some() {
+                differences.push(HeadDifference {
+                    head: &identity.name,
+                    property: "enabled",
+                    saved: configuration.is_some().to_string(),
+                    current: current.is_some().to_string(),
+                });
+            }
+            continue;
+        };
+        let saved_properties = property_values(configuration);
+        let current_properties = property_values(current);
+        for ((property, saved), (_, current)) in
+            saved_properties.into_iter().zip(current_properties)
+        {
+            // Properties that are None in the layout are left as the compositor chose them.
+            let Some(saved) = saved else {
+                continue;
+            };
+            let current = current.unwrap_or("-".to_string());
+            if saved != current {
+                differences.push(HeadDifference {
+                    head: &identity.name,
+                    property,
+                    saved,
+                    current,
+                });
+            }
+        }
+    }
+    differences.sort_by(|a, b| a.head.cmp(b.head));
+    let rows = differences
+        .iter()
+        .map(|difference| {
+            vec![
+                difference.head.to_string(),
+                difference.property.to_string(),
+                difference.saved.clone(),
+                difference.current.clone(),
+            ]
+        })
+        .collect();
+    output::print(
+        format,
+        &differences,
+        &["HEAD", "PROPERTY", "SAVED", "CURRENT"],
+        rows,
+    );
+}
+
+/// Returns the name and value of each property of `configuration`, where properties that are left
+/// as the compositor chose them are [`None`].
+fn property_values(configuration: &SavedConfiguration) -> [(&'static str, Option<String>); 5] {
+    let dont_care = configuration.dont_care;
+    [
+        ("mode", configuration.mode.as_ref().map(Mode::to_string)),
+        (
+            "position",
+            (!dont_care.position)
+                .then(|| format!("{},{}", configuration.position.0, configuration.position.1)),
+        ),
+        (
+            "scale",
+            (!dont_care.scale).then(|| configuration.scale.to_string()),
+        ),
+        (
+            "transform",
+            (!dont_care.transform).then(|| configuration.transform.name().to_string()),
+        ),
+        (
+            "adaptive_sync",
+            configuration
+                .adaptive_sync
+                .map(|enabled| enabled.to_string()),
+        ),
+    ]
+}
+
+/// Prints the head configurations of the layout selected by `selector` (a name or index).
+pub fn show_layout(args: &Args, selector: &str) {
+    let layout_data = load_layouts(args);
+    let Some(index) = layout_data.find_layout(selector) else {
+        eprintln!("No layout named \"{selector}\"");
+        std::process::exit(1);
+    };
+    let layout = &layout_data.layouts[index];
+    let mut heads = layout
+        .heads
+        .iter()
+        .map(|(identity, configuration)| HeadDetails {
+            identity,
+            configuration: configuration.as_ref(),
+            comment: layout.head_comments.get(&identity.name).map(String::as_str),
+        })
+        .collect::<Vec<_>>();
+    heads.sort_by(|a, b| a.identity.name.cmp(&b.identity.name));
+    let rows = heads
+        .iter()
+        .map(|head| {
+            let mut row = vec![
+                head.identity.name.clone(),
+                head.identity.description.clone(),
+            ];
+            row.extend(describe_configuration(head.configuration));
+            row.push(head.comment.unwrap_or("-").to_string());
+            row
+        })
+        .collect();
+    let details = LayoutDetails {
+        index,
+        name: layout.name.as_deref(),
+        profile: layout.profile(),
+        auto_apply: layout.auto_apply,
+        read_only: layout.read_only,
+        pinned: layout.pinned,
+        comment: layout.comment.as_deref(),
+        heads,
+    };
+    output::print(
+        args.format,
+        &details,
+        &[
+            "HEAD",
+            "DESCRIPTION",
+            "MODE",
+            "POSITION",
+            "SCALE",
+            "TRANSFORM",
+            "COMMENT",
+        ],
+        rows,
+    );
+}
+
+/// Describes the mode, position, scale and transform of `configuration` for `show`. Properties
+/// left to the compositor are shown as "-".
+pub fn describe_configuration(configuration: Option<&SavedConfiguration>) -> [String; 4] {
+    let Some(configuration) = configuration else {
+        return ["disabled".into(), "-".into(), "-".into(), "-".into()];
+    };
+    [
+        configuration
+            .mode
+            .as_ref()
+            .map_or("-".to_string(), Mode::to_string),
+        if configuration.dont_care.position {
+            "-".to_string()
+        } else {
+            format!("{},{}", configuration.position.0, configuration.position.1)
+        },
+        if configuration.dont_care.scale {
+            "-".to_string()
+        } else {
+            configuration.scale.to_string()
+        },
+        if configuration.dont_care.transform {
+            "-".to_string()
+        } else {
+            configuration.transform.name().to_string()
+        },
+    ]
+}
+
+/// Prints the layout selected by `selector` (a name or index) in `format`.
+pub fn export_layout(args: &Args, format: ExportFormat, selector: &str) {
+    let layout_data = load_layouts(args);
+    let Some(index) = layout_data.find_layout(selector) else {
+        eprintln!("No layout named \"{selector}\"");
+        std::process::exit(1);
+    };
+    print_export(format, &layout_data, index);
+}
+
+/// Prints the layout at `index` in `format`.
+pub fn print_export(format: ExportFormat, layout_data: &LayoutData, index: usize) {
+    match formats::export(format, &layout_data.layouts[index]) {
+        Ok(contents) => print!("{contents}"),
+        Err(err) => {
+            eprintln!("Failed to export layout {index}: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Deletes the layout selected by `selector` (a name or index), along with its metadata.
+pub fn delete_layout(args: &Args, selector: &str) {
+    let mut layout_data = load_layouts(args);
+    let Some(index) = layout_data.find_layout(selector) else {
+        eprintln!(
+            "No layout is named \"{selector}\", and it is not a valid index (there are {} layouts)",
+            layout_data.layouts.len()
+        );
+        std::process::exit(1);
+    };
+    if layout_data.layouts[index].read_only {
+        eprintln!("Layout {index} is read-only, so it can't be deleted");
+        std::process::exit(1);
+    }
+    let layout = layout_data.layouts.remove(index);
+    save_layouts(args, &mut layout_data);
+    match layout.name {
+        Some(name) => println!("Deleted layout {index} (\"{name}\")"),
+        None => println!("Deleted layout {index}"),
+    }
+}
+
+/// Renames the layout selected by `selector` (a name or index) to `name`.
+pub fn rename_layout(args: &Args, selector: &str, name: &str) {
+    let mut layout_data = load_layouts(args);
+    let Some(index) = layout_data.find_layout(selector) else {
+        eprintln!(
+            "No layout is named \"{selector}\", and it is not a valid index (there are {} layouts)",
+            layout_data.layouts.len()
+        );
+        std::process::exit(1);
+    };
+    if layout_data.layouts[index].read_only {
+        eprintln!("Layout {index} is read-only, so it can't be renamed");
+        std::process::exit(1);
+    }
+    if let Err(err) = check_name(&layout_data, index, name) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+    layout_data.layouts[index].name = Some(name.to_string());
+    // The metadata in the state file is found by name, so it must be saved with the new name.
+    save_layouts(args, &mut layout_data);
+    println!("Renamed layout {index} to \"{name}\"");
+}
+
+/// Checks that the layout at `index` can be named `name`.
+pub fn check_name(layout_data: &LayoutData, index: usize, name: &str) -> Result<(), String> {
+    if name.is_empty() || name.parse::<usize>().is_ok() {
+        // Names take precedence over indices, so a numeric name would hide a layout's index.
+        return Err(format!(
+            "\"{name}\" is not a valid name, since it is empty or a number"
+        ));
+    }
+    if let Some(other) = layout_data
+        .find_layout_by_name(name)
+        .filter(|other| *other != index)
+    {
+        return Err(format!("Layout {other} is already named \"{name}\""));
+    }
+    Ok(())
+}
+
+/// Deletes the layouts that haven't been saved, applied or matched in `unused_for`. Layouts that
+/// have never been used (e.g., because the state file was deleted) are kept, since their age is
+/// unknown.
+pub fn prune_layouts(args: &Args, unused_for: Duration) {
+    let mut layout_data = load_layouts(args);
+    let cutoff = state::unix_now().saturating_sub(unused_for.as_secs());
+    let mut pruned = vec![];
+    let mut index = 0;
+    layout_data.layouts.retain(|layout| {
+        let unused = !layout.read_only
+            && !layout.pinned
+            && !layout.template
+            && layout
+                .metadata
+                .last_used()
+                .is_some_and(|last_used| last_used < cutoff);
+        if unused {
+            pruned.push(match layout.name.as_deref() {
+                Some(name) => format!("{index} (\"{name}\")"),
+                None => index.to_string(),
+            });
+        }
+        index += 1;
+        !unused
+    });
+    if pruned.is_empty() {
+        println!("There are no unused layouts to delete");
+        return;
+    }
+    save_layouts(args, &mut layout_data);
+    for layout in pruned {
+        println!("Deleted layout {layout}");
+    }
+}
+
+/// Merges equivalent layouts (see [`LayoutData::dedupe`]) and prints what was merged.
+pub fn dedupe_layouts(args: &Args) {
+    let mut layout_data = load_layouts(args);
+    let merges = layout_data.dedupe();
+    if merges.is_empty() {
+        println!("There are no equivalent layouts to merge");
+        return;
+    }
+    save_layouts(args, &mut layout_data);
+    for (index, merged) in merges {
+        let merged = merged
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        match layout_data.layouts[index].name.as_deref() {
+            Some(name) => println!("Merged layouts {merged} into layout {index} (\"{name}\")"),
+            None => println!("Merged layouts {merged} into layout {index}"),
+        }
+    }
+}
+
+/// Upgrades the layouts file to [`LAYOUTS_VERSION`]. The original file is copied next to it first
+/// (e.g., to `layouts.v0.json`).
+pub fn migrate_layouts(args: &Args) {
+    let contents = match std::fs::read(&args.layouts) {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("Failed to read {}: {err}", args.layouts.display());
+            std::process::exit(1);
+        }
+    };
+    let version = match serde_json::from_slice(&contents) {
+        Ok(value) => format_version(&value),
+        Err(err) => {
+            eprintln!("Failed to parse {}: {err}", args.layouts.display());
+            std::process::exit(1);
+        }
+    };
+    if version == LAYOUTS_VERSION {
+        println!("The layouts file is already in format version {LAYOUTS_VERSION}");
+        return;
+    }
+    // Loading refuses newer versions, so this can't downgrade a file.
+    let mut layout_data = load_layouts(args);
+    let original_path = args.layouts.with_extension(format!("v{version}.json"));
+    if let Err(err) = write_atomically(&original_path, &contents) {
+        eprintln!(
+            "Failed to copy the layouts file to {}: {err}",
+            original_path.display()
+        );
+        std::process::exit(1);
+    }
+    save_layouts(args, &mut layout_data);
+    println!(
+        "Upgraded the layouts file from format version {version} to {LAYOUTS_VERSION}. The \
+         original was copied to {}",
+        original_path.display()
+    );
+}
+
+/// A snapshot of the layouts file, as printed by `rollback --list`.
+#[derive(Serialize)]
+struct SnapshotSummary {
+    /// When the snapshot was taken, in seconds since the Unix epoch.
+    timestamp: u64,
+    /// The number of layouts in the snapshot, or [`None`] if it can't be loaded.
+    layouts: Option<usize>,
+}
+
+/// Returns the snapshots of the layouts file, exiting if they can't be read.
+fn load_snapshots(args: &Args) -> Vec<(u64, PathBuf)> {
+    match history::snapshots(&args.layouts) {
+        Ok(snapshots) => snapshots,
+        Err(err) => {
+            eprintln!(
+                "Failed to read the snapshots in {}: {err}",
+                history::history_dir(&args.layouts).display()
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the snapshots of the layouts file, oldest first.
+pub fn list_snapshots(args: &Args) {
+    let summaries = load_snapshots(args)
+        .into_iter()
+        .map(|(timestamp, path)| SnapshotSummary {
+            timestamp,
+            layouts: LayoutData::load(&path)
+                .ok()
+                .map(|layout_data| layout_data.layouts.len()),
+        })
+        .collect::<Vec<_>>();
+    let rows = summaries
+        .iter()
+        .map(|summary| {
+            vec![
+                summary.timestamp.to_string(),
+                summary
+                    .layouts
+                    .map_or_else(|| "invalid".to_string(), |layouts| layouts.to_string()),
+            ]
+        })
+        .collect();
+    output::print(args.format, &summaries, &["TIMESTAMP", "LAYOUTS"], rows);
+}
+
+/// Restores the latest snapshot taken at or before `to`, or if [`None`], the latest snapshot that
+/// differs from the layouts file. The metadata of the restored layouts is kept where they still
+/// exist.
+pub fn rollback_layouts(args: &Args, to: Option<u64>) {
+    let snapshots = load_snapshots(args);
+    let snapshot = match to {
+        Some(to) => snapshots
+            .iter()
+            .rev()
+            .find(|(timestamp, _)| *timestamp <= to),
+        None => {
+            let current = std::fs::read(&args.layouts).ok();
+            snapshots
+                .iter()
+                .rev()
+                .find(|(_, path)| current.is_none() || std::fs::read(path).ok() != current)
+        }
+    };
+    let Some((timestamp, path)) = snapshot else {
+        match to {
+            Some(to) => eprintln!("There is no snapshot of the layouts file from before {to}"),
+            None => eprintln!("There is no earlier snapshot of the layouts file"),
+        }
+        std::process::exit(1);
+    };
+    let result = LayoutData::load_with_read_only(
+        &args.seed_layouts,
+        args.layouts_ro.as_deref(),
+        path,
+        None,
+        Recovery::Never,
+    )
+    .and_then(|mut layout_data| {
+        state::load(&state::state_path(&args.layouts), &mut layout_data)?;
+        Ok(layout_data)
+    });
+    let mut layout_data = match result {
+        Ok(layout_data) => layout_data,
+        Err(err) => {
+            eprintln!("Failed to load the snapshot {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    };
+    save_layouts(args, &mut layout_data);
+    println!("Restored the layouts file from the snapshot taken at {timestamp}");
+}
+
+/// Checks the layouts file at `path` (or the configured layouts files) and prints any problems.
+/// Exits with status 1 if there are any.
+pub fn validate_layouts(args: &Args, path: Option<&Path>) {
+    let paths = match path {
+        Some(path) => vec![path],
+        None => args
+            .layouts_ro
+            .as_deref()
+            .into_iter()
+            .chain([args.layouts.as_path()])
+            .collect(),
+    };
+    let results = paths
+        .into_iter()
+        .map(|path| ValidationResult {
+            path,
+            problems: match std::fs::read(path) {
+                Ok(contents) => validate(&contents),
+                Err(err) => vec![err.to_string()],
+            },
+        })
+        .collect::<Vec<_>>();
+    let valid = results.iter().all(|result| result.problems.is_empty());
+    if args.format == OutputFormat::Json {
+        output::print(args.format, &results, &[], vec![]);
+    } else {
+        for result in results {
+            if result.problems.is_empty() {
+                println!("{}: OK", result.path.display());
+            }
+            for problem in result.problems {
+                println!("{}: {problem}", result.path.display());
+            }
+        }
+    }
+    if !valid {
+        std::process::exit(1);
+    }
+}
+
+/// Reads a single layout from the file at `path` (or stdin if it is "-") for the `apply-file`
+/// subcommand. Exits if it can't be read.
+pub fn read_layout_file(path: &Path) -> Layout {
+    let contents = if path == Path::new("-") {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .map(|_| contents)
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let layout = contents
+        .map_err(|err| err.to_string())
+        .and_then(|contents| Layout::from_json(&contents).map_err(|err| err.to_string()));
+    match layout {
+        Ok(layout) => layout,
+        Err(err) => {
+            eprintln!("Failed to read a layout from {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Queries the running daemon for its statistics and prints them.
+pub fn print_stats(format: OutputFormat) {
+    let stats = match ipc::send_request(&Request::Stats) {
+        Ok(Response::Stats(stats)) => stats,
+        Ok(Response::Error(err)) => {
+            eprintln!("The daemon failed to respond: {err}");
+            std::process::exit(1);
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from the daemon: {response:?}");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let rows = [
+        ("saves", stats.saves),
+        ("applies", stats.applies),
+        ("apply_failures", stats.apply_failures),
+        ("fuzzy_matches", stats.fuzzy_matches),
+        ("heads_added", stats.heads_added),
+        ("heads_removed", stats.heads_removed),
+        ("reconnects", stats.reconnects),
+        ("uptime_secs", stats.uptime_secs),
+    ]
+    .into_iter()
+    .map(|(name, value)| vec![name.to_string(), value.to_string()])
+    .collect();
+    output::print(format, &stats, &["STAT", "VALUE"], rows);
+}
+
+/// Queries the running daemon for its state and prints it.
+pub fn print_status(format: OutputFormat) {
+    let status = match ipc::send_request(&Request::Status) {
+        Ok(Response::Status(status)) => status,
+        Ok(Response::Error(err)) => {
+            eprintln!("The daemon failed to respond: {err}");
+            std::process::exit(1);
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from the daemon: {response:?}");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let rows = [
+        ("paused", status.paused.to_string()),
+        (
+            "active_profile",
+            status.active_profile.clone().unwrap_or_default(),
+        ),
+        (
+            "layout",
+            status
+                .layout
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| vec![name.to_string(), value])
+    .collect();
+    output::print(format, &status, &["FIELD", "VALUE"], rows);
+}
+
+/// Sends `request` to the running daemon and prints the message it responds with.
+pub fn send_ctl(request: Request) {
+    match ipc::send_request(&request) {
+        Ok(Response::Ok(message)) => println!("{message}"),
+        Ok(Response::Error(err)) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from the daemon: {response:?}");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Asks the running daemon to switch to `profile`, or to the next profile of the current heads if
+/// [`None`], and prints the result. Returns the error if the daemon isn't running, and exits on any
+/// other error.
+pub fn request_switch(profile: Option<String>) -> Result<(), IpcError> {
+    let switched = match ipc::send_request(&Request::Switch { profile }) {
+        Ok(Response::Switched(switched)) => switched,
+        Ok(Response::Error(err)) => {
+            eprintln!("Failed to switch profiles: {err}");
+            std::process::exit(1);
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from the daemon: {response:?}");
+            std::process::exit(1);
+        }
+        Err(err @ IpcError::CouldNotConnect(..)) => return Err(err),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    if switched.created {
+        println!(
+            "Switched to profile \"{}\", saving the current configuration as its layout {}",
+            switched.profile, switched.layout
+        );
+    } else {
+        println!(
+            "Switched to profile \"{}\" (layout {})",
+            switched.profile, switched.layout
+        );
+    }
+    Ok(())
+}
+
+/// Prints the running daemon's events (one JSON object per line) until it exits.
+pub fn subscribe() {
+    let events = match ipc::subscribe() {
+        Ok(events) => events,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    for line in events.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Failed to read from the daemon: {err}");
+                std::process::exit(1);
+            }
+        };
+        // Flush every line, since this is usually piped into another program.
+        if writeln!(stdout, "{line}")
+            .and_then(|()| stdout.flush())
+            .is_err()
+        {
+            // Whatever we were piped into is gone.
+            return;
+        }
+    }
+}
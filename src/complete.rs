@@ -1,5 +1,10 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use wayland_client::backend::ObjectId;
@@ -8,6 +13,7 @@ use wayland_protocols_wlr::output_management::v1::client::{
 };
 
 use crate::{
+    edid,
     partial::{
         ConfigurationProperty, ImmutableProperty, PartialHead, PartialHeadState, PartialMode,
         PartialModeState,
@@ -27,13 +33,51 @@ pub struct Head {
     pub configuration: Option<HeadConfiguration>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// The identity of a head. Heads are equal if they have the same name, description, make, model
+/// and serial number. The EDID and physical size aren't part of equality, since not every head
+/// (nor every saved layout) has them, so they are only compared when matching asks for them (see
+/// [`crate::serde::MatchFields`]).
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct HeadIdentity {
     pub name: String,
     pub description: String,
     pub make: Option<String>,
     pub model: Option<String>,
     pub serial_number: Option<String>,
+    /// A hash of the monitor's EDID, read from the DRM connector with the same name. This still
+    /// identifies the monitor if the compositor names its connector differently.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edid: Option<String>,
+    /// The physical size of the head in millimeters, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub physical_size: Option<(u32, u32)>,
+}
+
+impl HeadIdentity {
+    /// The fields that are part of equality.
+    fn key(&self) -> impl Eq + Hash + '_ {
+        (
+            &self.name,
+            &self.description,
+            &self.make,
+            &self.model,
+            &self.serial_number,
+        )
+    }
+}
+
+impl PartialEq for HeadIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for HeadIdentity {}
+
+impl Hash for HeadIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -58,9 +102,12 @@ impl Default for HeadConfiguration {
 }
 
 impl Head {
+    /// Creates a head from its first complete set of properties. The EDID of its connector is only
+    /// read from sysfs if `read_edid` is set, i.e. heads are matched by it.
     fn create_from_partial(
         mut value: PartialHead,
         id_to_mode: &HashMap<ObjectId, ModeState>,
+        read_edid: bool,
     ) -> Result<Self, CreateHeadError> {
         let Some(name) = std::mem::take(&mut value.name) else {
             return Err(CreateHeadError::MissingName);
@@ -73,6 +120,11 @@ impl Head {
             return Err(CreateHeadError::MissingEnabled);
         }
 
+        let edid = if read_edid {
+            edid::read_connector_hash(&name)
+        } else {
+            None
+        };
         let mut head = Self {
             identity: HeadIdentity {
                 name,
@@ -80,6 +132,8 @@ impl Head {
                 make: std::mem::take(&mut value.make),
                 model: std::mem::take(&mut value.model),
                 serial_number: std::mem::take(&mut value.serial_number),
+                edid,
+                physical_size: std::mem::take(&mut value.physical_size),
             },
             mode_to_id: Default::default(),
             configuration: None,
@@ -118,7 +172,7 @@ impl Head {
                 // missing modes. https://github.com/swaywm/sway/issues/8420
                 id_to_mode
                     .get(id)
-                    .map(|mode_state| (mode_state.mode.clone(), id.clone()))
+                    .map(|mode_state| (mode_state.mode, id.clone()))
             }));
 
         if let Some(enabled) = partial.enabled {
@@ -172,10 +226,11 @@ impl HeadState {
     pub fn create_from_partial(
         value: PartialHeadState,
         id_to_mode: &HashMap<ObjectId, ModeState>,
+        read_edid: bool,
     ) -> Result<Self, CreateHeadError> {
         Ok(Self {
             proxy: value.proxy,
-            head: Head::create_from_partial(value.head, id_to_mode)?,
+            head: Head::create_from_partial(value.head, id_to_mode, read_edid)?,
         })
     }
 }
@@ -203,14 +258,28 @@ pub enum ApplyPartialHeadError {
 pub struct ModeState {
     pub proxy: ZwlrOutputModeV1,
     pub mode: Mode,
+    /// Whether the compositor advertised this mode as preferred.
+    pub preferred: bool,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct Mode {
     pub size: (u32, u32),
+    /// The refresh rate in mHz.
     pub refresh: Option<u32>,
 }
 
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.size.0, self.size.1)?;
+        if let Some(refresh) = self.refresh {
+            // Refresh rates are reported in mHz.
+            write!(f, "@{}.{:03}Hz", refresh / 1000, refresh % 1000)?;
+        }
+        Ok(())
+    }
+}
+
 impl TryFrom<PartialMode> for Mode {
     type Error = CreateModeError;
 
@@ -232,6 +301,7 @@ impl TryFrom<PartialModeState> for ModeState {
         Ok(Self {
             proxy: value.proxy,
             mode: value.mode.try_into()?,
+            preferred: value.mode.preferred,
         })
     }
 }
@@ -241,3 +311,37 @@ pub enum CreateModeError {
     #[error("Missing required Size property for new mode.")]
     MissingSize,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, RandomState};
+
+    use super::*;
+
+    #[test]
+    fn head_identity_equality_ignores_edid_and_physical_size() {
+        let identity = HeadIdentity {
+            name: "DP-1".into(),
+            description: "Acme A (DP-1)".into(),
+            make: Some("Acme".into()),
+            model: Some("A".into()),
+            serial_number: Some("1".into()),
+            edid: None,
+            physical_size: None,
+        };
+        let detailed = HeadIdentity {
+            edid: Some("0123456789abcdef".into()),
+            physical_size: Some((600, 340)),
+            ..identity.clone()
+        };
+        let hasher = RandomState::new();
+        assert_eq!(identity, detailed);
+        assert_eq!(hasher.hash_one(&identity), hasher.hash_one(&detailed));
+
+        let other = HeadIdentity {
+            serial_number: Some("2".into()),
+            ..identity.clone()
+        };
+        assert_ne!(identity, other);
+    }
+}
@@ -0,0 +1,30 @@
+/// Compositors that can be told apart by the globals only they advertise, keyed by the prefix of
+/// those globals' interfaces. The names match what the compositors put in `XDG_CURRENT_DESKTOP`.
+const KNOWN_GLOBALS: &[(&str, &str)] = &[
+    ("hyprland_", "Hyprland"),
+    ("river_", "river"),
+    ("zcosmic_", "COSMIC"),
+    ("treeland_", "Treeland"),
+    ("zwf_", "Wayfire"),
+    ("org_kde_kwin_", "KDE"),
+];
+
+/// Returns the name of the current compositor from `XDG_CURRENT_DESKTOP`, if it is set. Only the
+/// first of the desktops it lists is used.
+pub fn from_env() -> Option<String> {
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").ok()?;
+    desktop
+        .split(':')
+        .next()
+        .filter(|desktop| !desktop.is_empty())
+        .map(str::to_string)
+}
+
+/// Returns the name of the compositor that advertises a global with `interface`, if only one
+/// compositor we know about advertises it.
+pub fn from_global(interface: &str) -> Option<&'static str> {
+    KNOWN_GLOBALS
+        .iter()
+        .find(|(prefix, _)| interface.starts_with(prefix))
+        .map(|(_, name)| *name)
+}
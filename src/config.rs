@@ -1,44 +1,163 @@
 use std::{
+    collections::HashMap,
     io::ErrorKind,
     path::{Path, PathBuf},
-    sync::Arc,
+    time::Duration,
 };
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use serde::Deserialize;
 use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    complete::HeadIdentity,
+    formats::{ExportFormat, ImportFormat},
+    hooks::{Hooks, HOOK_ENV_VAR_PREFIX},
+    matcher::Matcher,
+    output::OutputFormat,
+    serde::{deserialize_seed_layouts, IdentityField, Layout, MatchFields, Property},
+};
 
 pub struct Args {
+    pub config_path: PathBuf,
     pub layouts: PathBuf,
-    pub apply_command: Option<Arc<str>>,
-    pub save_and_exit: bool,
+    /// A layouts file which is only read from, e.g. one managed by a package manager.
+    pub layouts_ro: Option<PathBuf>,
+    /// A directory to store the layouts in one file per layout, instead of in `layouts`.
+    pub layouts_dir: Option<PathBuf>,
+    /// Read-only layouts declared in the config, which are matched before all others.
+    pub seed_layouts: Vec<Layout>,
+    /// The shell (and its arguments) that commands given as a string are passed to.
+    pub command_shell: Vec<String>,
+    pub command: Option<Command>,
+    /// How read-only subcommands print their results.
+    pub format: OutputFormat,
+    pub enforce: bool,
+    /// Whether configurations that look like the compositor reset the heads to its defaults are
+    /// reverted by re-applying the matching layout, instead of being saved.
+    pub detect_resets: bool,
+    pub snap_scales: bool,
+    pub mode_policy: ModePolicy,
+    /// Per-head settings, keyed by the head's name or description.
+    pub heads: HashMap<String, HeadConfig>,
+    /// How to configure heads that no layout has seen. If [`None`], they are left to the
+    /// compositor.
+    pub new_heads: Option<NewHeadDefaults>,
+    /// How often to log the number of live Wayland objects, to debug leaks. Disabled if [`None`].
+    pub object_report_interval: Option<Duration>,
+    /// The properties recorded when saving a layout. Others are left to the compositor.
+    pub capture: Vec<Property>,
+    /// The properties wl-distore reads and sets at all. Others are never saved or applied.
+    pub managed_properties: Vec<Property>,
+    /// A file descriptor to notify once the displays have been configured on startup.
+    pub ready_fd: Option<i32>,
+    /// The shell command used to pick a layout for `apply --interactive`.
+    pub picker: String,
+    /// Whether to log the configurations that would be applied instead of applying them. Implies
+    /// `read_only`.
+    pub dry_run: bool,
+    /// How many snapshots of the layouts file to keep. Disabled if 0.
+    pub history_size: usize,
+    /// The most layouts to keep. Once there are more, the least recently used are deleted.
+    pub max_layouts: Option<usize>,
+    /// The host new layouts are saved for, and whose layouts are matched, if layouts are scoped to
+    /// hosts.
+    pub host: Option<String>,
+    /// Whether layouts saved under other compositors are never matched.
+    pub scope_to_compositor: bool,
+    /// Whether a layout is applied to the heads it has when more heads are connected.
+    pub match_supersets: bool,
+    /// Whether a layout is applied to the heads that are connected when some of its heads are
+    /// missing.
+    pub match_subsets: bool,
+    /// Whether the laptop's lid is watched through UPower (or systemd-logind), so layouts can be
+    /// kept for when it is open or closed.
+    pub lid_switch: bool,
+    /// Whether the power source is watched through UPower (or sysfs), so layouts can be kept for
+    /// when the machine is on AC or on battery.
+    pub watch_power_source: bool,
+    /// The fields of each head compared when matching layouts.
+    pub match_fields: MatchFields,
+    /// How long the daemon waits for the layouts to stop changing before writing them.
+    pub save_delay: Duration,
+    /// The least time between two writes of the layouts, however often they change.
+    pub min_save_interval: Duration,
+    /// Whether the daemon only applies layouts, and never saves them.
+    pub read_only: bool,
+    /// Whether the daemon applies layouts when the heads change. If false, it only saves them.
+    pub apply: bool,
+    /// How many times to retry applying a layout that failed or was cancelled.
+    pub apply_retries: u32,
+    /// How long to wait before the first retry. Each later retry waits twice as long.
+    pub apply_retry_delay: Duration,
+    /// How long the heads must stop changing for before their layout is applied.
+    pub apply_settle: Duration,
+    /// Whether the layout is applied again after the machine resumes from suspend (as reported by
+    /// systemd-logind), since some compositors scramble the configuration while suspended.
+    pub reapply_after_resume: bool,
+    /// How long to wait after resuming before applying the layout again.
+    pub resume_delay: Duration,
+    /// How long after sway reloads its config that changes to the configuration are reverted
+    /// instead of saved, since sway resets the heads to its config when it reloads. Zero disables
+    /// this.
+    pub sway_reload_grace: Duration,
+    /// The commands to run when things happen in the daemon.
+    pub hooks: Hooks,
+    /// The command that picks between the layouts matching the current heads.
+    pub matcher: Option<CommandLine>,
+    /// The default log filter (e.g. `debug`), which `RUST_LOG` overrides.
+    pub log_level: Option<String>,
+    /// The file to append logs to, instead of stderr.
+    pub log_file: Option<PathBuf>,
+    pub log_format: LogFormat,
+    /// Patterns (with `*` and `?` wildcards) for the names or descriptions of heads that are left
+    /// out of layouts and matching.
+    pub ignore_heads: Vec<String>,
+    /// Patterns for the names or descriptions of the only heads that are managed. If [`None`],
+    /// every head that isn't ignored is managed.
+    pub manage_only: Option<Vec<String>>,
+    /// Whether to ask an already running daemon to exit instead of refusing to start.
+    pub replace: bool,
+    /// Whether `--apply-and-exit` was passed, which runs as `apply` but exits successfully when no
+    /// layout matches.
+    pub apply_and_exit: bool,
 }
 
 impl Args {
     /// Collects the arguments to the binary using flags and config files.
     pub fn collect() -> Result<Self, CollectArgsError> {
         let mut flags = Flags::parse();
+        if flags.apply_and_exit && flags.command.is_some() {
+            Flags::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--apply-and-exit cannot be used with a subcommand",
+                )
+                .exit();
+        }
         let flag_config = Config::take_from_flags(&mut flags);
 
         let config_path = flags
             .config
-            .as_ref()
-            .map(String::as_str)
-            .unwrap_or("~/.config/wl-distore/config.toml");
+            .clone()
+            .or_else(|| std::env::var(CONFIG_ENV_VAR).ok())
+            .unwrap_or_else(|| xdg_path("XDG_CONFIG_HOME", "~/.config", "config.toml"));
 
         let config_path = match expanduser::expanduser(&config_path) {
             Ok(path) => path,
             Err(err) => {
-                return Err(CollectArgsError::CouldNotExpandUser(
-                    config_path.to_string(),
-                    err,
-                ));
+                return Err(CollectArgsError::CouldNotExpandUser(config_path, err));
             }
         };
+        let system_config = load_config_from_file(Path::new(SYSTEM_CONFIG_PATH))?;
         let file_config = load_config_from_file(&config_path)?;
+        let env_config = load_config_from_env(std::env::vars())?;
 
         let mut config = Config::create_default();
+        config.override_with(system_config);
         config.override_with(file_config);
+        config.override_with(env_config);
         config.override_with(flag_config);
 
         let layouts = config.layouts.unwrap();
@@ -52,60 +171,691 @@ impl Args {
                 return Err(CollectArgsError::CouldNotExpandUser(layouts, err));
             }
         };
+        let layouts_ro = match config.layouts_ro {
+            None => None,
+            Some(layouts_ro) => match expanduser::expanduser(&layouts_ro) {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    return Err(CollectArgsError::CouldNotExpandUser(layouts_ro, err));
+                }
+            },
+        };
+        if config.command_shell.as_ref().is_some_and(Vec::is_empty) {
+            return Err(CollectArgsError::EmptyCommand("command_shell"));
+        }
+        let hooks = config.hooks.as_ref().unwrap();
+        for (option, command) in [
+            ("apply_command", &config.apply_command),
+            ("apply_failed_command", &config.apply_failed_command),
+            ("matcher", &config.matcher),
+            ("hooks.on_save", &hooks.on_save),
+            ("hooks.on_apply_success", &hooks.on_apply_success),
+            ("hooks.on_apply_failed", &hooks.on_apply_failed),
+            ("hooks.on_head_added", &hooks.on_head_added),
+            ("hooks.on_head_removed", &hooks.on_head_removed),
+        ] {
+            if command.as_ref().is_some_and(CommandLine::is_empty) {
+                return Err(CollectArgsError::EmptyCommand(option));
+            }
+        }
+        if config
+            .capture
+            .as_ref()
+            .is_some_and(|capture| capture.contains(&Property::Enabled))
+        {
+            return Err(CollectArgsError::CaptureEnabled);
+        }
+        // `apply_command` and `apply_failed_command` are aliases of these hooks, which take
+        // precedence if both are set.
+        let mut hooks = config.hooks.unwrap();
+        hooks.on_apply_success = hooks.on_apply_success.or(config.apply_command);
+        hooks.on_apply_failed = hooks.on_apply_failed.or(config.apply_failed_command);
+        let log = config.log.unwrap();
+        if let Some(level) = &log.level {
+            if let Err(err) = EnvFilter::try_new(level) {
+                return Err(CollectArgsError::InvalidLogLevel(level.clone(), err));
+            }
+        }
+        let log_file = match log.file {
+            None => None,
+            Some(log_file) => match expanduser::expanduser(&log_file) {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    return Err(CollectArgsError::CouldNotExpandUser(log_file, err));
+                }
+            },
+        };
+        let layouts_dir = match config.layouts_dir {
+            None => None,
+            Some(layouts_dir) => match expanduser::expanduser(&layouts_dir) {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    return Err(CollectArgsError::CouldNotExpandUser(layouts_dir, err));
+                }
+            },
+        };
         Ok(Args {
+            config_path,
             layouts,
-            apply_command: config.apply_command.map(|s| s.into()),
-            save_and_exit: matches!(flags.command, Some(Command::SaveCurrent)),
+            layouts_ro,
+            layouts_dir,
+            seed_layouts: config.seed_layouts.unwrap(),
+            command_shell: config.command_shell.unwrap(),
+            command: if flags.apply_and_exit {
+                Some(Command::Apply {
+                    layout: None,
+                    interactive: false,
+                    only: None,
+                })
+            } else {
+                flags.command
+            },
+            format: if flags.json {
+                OutputFormat::Json
+            } else {
+                flags.format
+            },
+            enforce: config.enforce.unwrap(),
+            detect_resets: config.detect_resets.unwrap(),
+            snap_scales: config.snap_scales.unwrap(),
+            mode_policy: config.mode_policy.unwrap(),
+            heads: config.heads.unwrap(),
+            new_heads: config.new_heads,
+            object_report_interval: config
+                .object_report_interval
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            capture: config.capture.unwrap(),
+            managed_properties: config.managed_properties.unwrap(),
+            ready_fd: flags.ready_fd,
+            picker: config.picker.unwrap(),
+            dry_run: flags.dry_run,
+            history_size: config.history_size.unwrap(),
+            max_layouts: config.max_layouts,
+            host: config.scope_to_host.unwrap().then(hostname),
+            scope_to_compositor: config.scope_to_compositor.unwrap(),
+            match_supersets: config.match_supersets.unwrap(),
+            match_subsets: config.match_subsets.unwrap(),
+            lid_switch: config.lid_switch.unwrap(),
+            watch_power_source: config.watch_power_source.unwrap(),
+            match_fields: MatchFields(config.match_fields.unwrap()),
+            save_delay: Duration::from_millis(config.save_delay_ms.unwrap()),
+            min_save_interval: Duration::from_millis(config.min_save_interval_ms.unwrap()),
+            read_only: config.read_only.unwrap() || flags.dry_run,
+            apply: config.apply.unwrap(),
+            apply_retries: config.apply_retries.unwrap(),
+            apply_retry_delay: Duration::from_millis(config.apply_retry_delay_ms.unwrap()),
+            apply_settle: Duration::from_millis(config.apply_settle_ms.unwrap()),
+            reapply_after_resume: config.reapply_after_resume.unwrap(),
+            resume_delay: Duration::from_millis(config.resume_delay_ms.unwrap()),
+            sway_reload_grace: Duration::from_millis(config.sway_reload_grace_ms.unwrap()),
+            hooks,
+            matcher: config.matcher,
+            log_level: log.level,
+            log_file,
+            log_format: log.format,
+            ignore_heads: config.ignore_heads.unwrap(),
+            manage_only: config.manage_only,
+            replace: flags.replace,
+            apply_and_exit: flags.apply_and_exit,
         })
     }
+
+    /// Finds the per-head settings for `identity`. Settings keyed by name take precedence over
+    /// settings keyed by description.
+    pub fn head_config(&self, identity: &HeadIdentity) -> Option<&HeadConfig> {
+        self.heads
+            .get(&identity.name)
+            .or_else(|| self.heads.get(&identity.description))
+    }
+
+    /// Returns the [`Matcher`] to pick between matching layouts with, if there is one.
+    pub fn matcher(&self) -> Option<Matcher> {
+        self.matcher.clone().map(|command| Matcher {
+            command,
+            shell: self.command_shell.clone(),
+        })
+    }
+
+    /// Whether `identity` is ignored since its name or description matches an `ignore_heads`
+    /// pattern, or doesn't match any `manage_only` pattern.
+    pub fn is_ignored(&self, identity: &HeadIdentity) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns.iter().any(|pattern| {
+                glob_matches(pattern, &identity.name)
+                    || glob_matches(pattern, &identity.description)
+            })
+        };
+        matches(&self.ignore_heads)
+            || self
+                .manage_only
+                .as_ref()
+                .is_some_and(|manage_only| !matches(manage_only))
+    }
+
+    /// Whether wl-distore saves and applies `property`.
+    pub fn manages(&self, property: Property) -> bool {
+        self.managed_properties.contains(&property)
+    }
+
+    /// How to pick modes for `identity`.
+    pub fn mode_preference_for(&self, identity: &HeadIdentity) -> ModePreference {
+        let head_config = self.head_config(identity);
+        ModePreference {
+            policy: head_config
+                .and_then(|head_config| head_config.mode_policy)
+                .unwrap_or(self.mode_policy),
+            preferred_refresh: head_config
+                .and_then(|head_config| head_config.preferred_refresh)
+                .map(|refresh| (refresh * 1000.0).round() as u32),
+        }
+    }
+}
+
+/// How to pick modes for a head.
+#[derive(Debug, Clone, Copy)]
+pub struct ModePreference {
+    pub policy: ModePolicy,
+    /// The refresh rate (in mHz) to prefer over the policy, if any.
+    pub preferred_refresh: Option<u32>,
+}
+
+/// A command to run, given either as a command line for the shell or as a program and its
+/// arguments.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// A command line run by the `command_shell`.
+    Shell(String),
+    /// A program followed by its arguments, run without a shell.
+    Argv(Vec<String>),
+}
+
+impl CommandLine {
+    /// Whether this names no program to run.
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Shell(_) => false,
+            Self::Argv(argv) => argv.is_empty(),
+        }
+    }
+
+    /// Creates the process to run this command, passing command lines to `shell` (a program
+    /// followed by its arguments, e.g. `["sh", "-c"]`).
+    pub fn to_command(&self, shell: &[String]) -> std::process::Command {
+        let (program, args) = match self {
+            Self::Shell(_) => (&shell[0], &shell[1..]),
+            Self::Argv(argv) => (&argv[0], &argv[1..]),
+        };
+        let mut command = std::process::Command::new(program);
+        command.args(args);
+        if let Self::Shell(command_line) = self {
+            command.arg(command_line);
+        }
+        command
+    }
+}
+
+impl std::fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shell(command_line) => f.write_str(command_line),
+            Self::Argv(argv) => write!(f, "{argv:?}"),
+        }
+    }
+}
+
+/// How to log. Every option is optional.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct LogConfig {
+    /// The default log filter (e.g. `debug` or `wl_distore=trace`), which `RUST_LOG` overrides.
+    level: Option<String>,
+    /// The file to append logs to, instead of stderr.
+    file: Option<String>,
+    format: LogFormat,
+}
+
+/// How log lines are formatted.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// A line per event, with its spans and fields.
+    #[default]
+    Full,
+    /// Like [`LogFormat::Full`], but shorter.
+    Compact,
+    /// Several lines per event, for reading by humans.
+    Pretty,
+}
+
+/// How to pick between several modes that fit (e.g., several modes with the same size).
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModePolicy {
+    /// Pick the mode whose refresh rate is closest to the desired one.
+    ClosestRefresh,
+    /// Pick the mode with the highest refresh rate.
+    HighestRefresh,
+    /// Pick the mode with the lowest refresh rate (e.g., to save power).
+    LowestRefresh,
+    /// Pick the mode the compositor advertises as preferred, falling back to the highest refresh
+    /// rate.
+    Preferred,
+}
+
+/// Settings that apply to a single head.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct HeadConfig {
+    /// Overrides the global `mode_policy` for this head.
+    pub mode_policy: Option<ModePolicy>,
+    /// The refresh rate (in Hz) to prefer when picking between modes, or when the compositor
+    /// flip-flops between modes of the same size.
+    pub preferred_refresh: Option<f64>,
+}
+
+/// How to configure heads that no layout has seen, when the other heads have a layout.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NewHeadDefaults {
+    /// Whether to enable the new heads. They are placed to the right of the other heads, at their
+    /// preferred mode.
+    pub enabled: bool,
+    pub scale: f64,
+}
+
+impl Default for NewHeadDefaults {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scale: 1.0,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum CollectArgsError {
-    #[error("Failed to read the config file: {0}")]
-    FailedToReadConfigFile(std::io::Error),
-    #[error("Failed to parse the config file: {0}")]
-    FailedToParseConfigFile(toml::de::Error),
+    #[error("Failed to read the config file {0:?}: {1}")]
+    FailedToReadConfigFile(PathBuf, std::io::Error),
+    #[error("Failed to parse the config file {0:?}: {1}")]
+    FailedToParseConfigFile(PathBuf, toml::de::Error),
+    #[error("Failed to parse the config from the environment: {0}")]
+    FailedToParseConfigEnv(toml::de::Error),
     #[error("The layouts path \"{0}\" ends in a slash, so is interpreted as a directory")]
     LayoutsPathIsDirectory(String),
     #[error("Could not expand the user for path \"{0}\": {1}")]
     CouldNotExpandUser(String, std::io::Error),
+    #[error("The {0} option is empty, but must name a program to run")]
+    EmptyCommand(&'static str),
+    #[error("The log level \"{0}\" is invalid: {1}")]
+    InvalidLogLevel(String, tracing_subscriber::filter::ParseError),
+    #[error("The capture option can't contain \"enabled\", use managed_properties instead")]
+    CaptureEnabled,
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Flags {
-    /// The config file to read from. [default=~/.config/wl-distore/config.toml]
+    /// The config file to read from. [default=$XDG_CONFIG_HOME/wl-distore/config.toml]
     #[arg(long)]
     config: Option<String>,
-    /// The file to save and load layout data to/from. [default=~/.local/state/wl-distore/layouts.json]
+    /// The file to save and load layout data to/from.
+    /// [default=$XDG_STATE_HOME/wl-distore/layouts.json]
     #[arg(long)]
     layouts: Option<String>,
+    /// How read-only subcommands (e.g. `list`, `show`, `which`, `status`) print their results.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
+    /// Print the results of read-only subcommands as JSON. Short for `--format json`.
+    #[arg(long, global = true, conflicts_with = "format")]
+    json: bool,
+    /// Log the number of live Wayland objects every this many seconds, to debug leaks.
+    #[arg(long, value_name = "SECONDS")]
+    object_report_interval: Option<u64>,
+    /// Once the displays are first configured, write a newline to this file descriptor and close
+    /// it, so supervisors (e.g. s6 or runit) can start things that depend on the displays.
+    #[arg(long, value_name = "FD")]
+    ready_fd: Option<i32>,
+    /// Log every request that applying a layout would make (and the commands it would run)
+    /// instead of applying it, and never save the layouts. Logs at the info level unless RUST_LOG
+    /// says otherwise.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// If wl-distore is already running on this display, ask it to exit and take its place
+    /// instead of refusing to start.
+    #[arg(long)]
+    replace: bool,
+    /// Apply the layout matching the current heads (if any) and exit, instead of running as a
+    /// daemon. This is like `apply`, but exits successfully when no layout matches, e.g. for a
+    /// compositor's startup config or a udev hook.
+    #[arg(long, conflicts_with_all = ["replace", "ready_fd"])]
+    apply_and_exit: bool,
+    /// Apply layouts when the displays change, but never save changes to the layouts file. This
+    /// overrides `read_only` in the config.
+    #[arg(long)]
+    read_only: bool,
     #[command(subcommand)]
     command: Option<Command>,
 }
 
-#[derive(Subcommand, Debug)]
-enum Command {
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
     /// Saves the current layout and exits. This can be used to fix a broken config, or otherwise
     /// adjust configuration without needing to have wl-distore watching.
-    SaveCurrent,
+    SaveCurrent {
+        /// Save into the layout with this name instead of the layout matching the current heads.
+        /// The layout is created if it doesn't exist.
+        #[arg(long)]
+        into: Option<String>,
+    },
+    /// Applies a saved layout to the current heads and exits. Exits with status 1 if applying
+    /// fails, or 2 if no layout was given and none matches the current heads.
+    Apply {
+        /// The name or index of the layout to apply. Defaults to the layout matching the current
+        /// heads, like the daemon would apply.
+        layout: Option<String>,
+        /// Pick the layout to apply with the `picker` command (fzf by default) instead.
+        #[arg(long, short, conflicts_with = "layout")]
+        interactive: bool,
+        /// Only configure these heads (by name, e.g. "DP-1,HDMI-A-1"). All other heads are left
+        /// as-is.
+        #[arg(long, value_delimiter = ',')]
+        only: Option<Vec<String>>,
+    },
+    /// Switches to another profile's layout for the current heads. If wl-distore is running, it
+    /// switches (see `ctl switch`). Otherwise the profile's layout is applied once, and this exits
+    /// with status 2 if the profile has no layout for the current heads.
+    Switch {
+        /// The profile to switch to, e.g. "gaming".
+        profile: String,
+    },
+    /// Sends a command to the running wl-distore.
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Applies a single layout from a file in the saved format (without matching it against the
+    /// saved layouts) and exits.
+    ApplyFile {
+        /// The file containing the layout, or "-" to read it from stdin.
+        path: PathBuf,
+    },
+    /// Prints the current configuration of the heads as a layout, in the format of the layouts
+    /// file, and exits.
+    Current,
+    /// Prints which saved layout matches the current heads (the one that would be applied), and
+    /// whether it matches exactly. Exits with status 2 if no layout matches.
+    Which,
+    /// Deletes the saved layout that matches the current heads, so their next configuration is
+    /// saved as a new layout. Exits with status 2 if no layout matches.
+    Forget,
+    /// Prints how the current configuration of the heads differs from the saved layout that matches
+    /// them. Exits with status 2 if no layout matches.
+    Diff,
+    /// Arranges the heads in a terminal UI: move them with the keyboard, snap them together, and
+    /// enable, rotate or scale them. The result is applied, then saved as the layout for the
+    /// current heads.
+    Arrange,
+    /// Walks through setting up wl-distore: saving the current heads as a layout and writing a
+    /// config file.
+    Init,
+    /// Imports layouts from another tool's configuration file. Some formats (e.g. way-displays)
+    /// describe rules rather than positions, so they are resolved against the current heads.
+    Import {
+        /// The format of the file.
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        from: ImportFormat,
+        /// The file to import.
+        path: PathBuf,
+        /// The name to give the imported layout. If the file contains several layouts, they are
+        /// numbered, e.g. "desk-1". A layout with the same name is replaced.
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Prints a saved layout in another tool's configuration format.
+    Export {
+        /// The format to export to.
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        to: ExportFormat,
+        /// The name or index of the layout to export. Defaults to the layout matching the current
+        /// heads.
+        layout: Option<String>,
+    },
+    /// Lists the saved layouts.
+    List,
+    /// Prints the head configurations of a saved layout.
+    Show {
+        /// The name or index of the layout to show.
+        layout: String,
+    },
+    /// Deletes a saved layout.
+    Delete {
+        /// The name or index of the layout to delete.
+        layout: String,
+    },
+    /// Renames a saved layout.
+    Rename {
+        /// The name or index of the layout to rename.
+        layout: String,
+        /// The new name of the layout.
+        name: String,
+    },
+    /// Deletes the saved layouts that haven't been saved, applied or matched recently.
+    Prune {
+        /// How long a layout must have gone unused to be deleted, e.g. "180d". Supports the units
+        /// s, m, h, d and w.
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        unused_for: Duration,
+    },
+    /// Merges saved layouts with the same heads and configurations, keeping the most recently used.
+    Dedupe,
+    /// Upgrades the layouts file to the current format version, keeping a copy of the original.
+    Migrate,
+    /// Prints the JSON Schema of the layouts file.
+    Schema,
+    /// Checks the layouts files for errors, and exits with status 1 if there are any.
+    Validate {
+        /// The layouts file to check, instead of the configured ones.
+        path: Option<PathBuf>,
+    },
+    /// Restores a snapshot of the layouts file. By default, this restores the latest snapshot that
+    /// differs from the layouts file, so running it again undoes the rollback.
+    Rollback {
+        /// Restore the latest snapshot taken at or before this time, in seconds since the Unix
+        /// epoch (as printed by `--list`).
+        #[arg(long, value_name = "TIMESTAMP")]
+        to: Option<u64>,
+        /// List the snapshots instead of restoring one.
+        #[arg(long, conflicts_with_all = ["to", "apply"])]
+        list: bool,
+        /// Apply the layout matching the current heads once the snapshot is restored.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Browses the saved layouts in a terminal UI, to see their heads, rename, delete, pin or apply
+    /// them.
+    Ui,
+    /// Prints statistics from the running daemon.
+    Stats,
+    /// Prints the state of the running daemon: whether saving is paused, the active profile, and
+    /// the layout matching the current heads.
+    Status,
+    /// Prints a line whenever heads appear, change, or disappear. Nothing is saved or applied.
+    Watch,
+    /// Prints a JSON object per line for everything the running daemon sees and does: heads being
+    /// added, removed or changed, and layouts being saved or applied. Exits when the daemon does.
+    Subscribe,
+}
+
+/// Commands for the running wl-distore.
+#[derive(Subcommand, Debug, Clone)]
+pub enum CtlCommand {
+    /// Switches to another profile's layout for the current heads, and prefers that profile from
+    /// now on. If the profile has no layout for the current heads, the current configuration is
+    /// saved as one.
+    Switch {
+        /// The profile to switch to. Defaults to the next profile with a layout for the current
+        /// heads, to cycle between them.
+        profile: Option<String>,
+    },
+    /// Saves the current configuration into the layout matching the current heads (or as a new
+    /// layout), like `save-current`. This saves even while saving is paused.
+    Save,
+    /// Applies the layout matching the current heads again right away, e.g. to undo changes made
+    /// while saving was paused.
+    Reapply,
+    /// Stops saving (and enforcing) changes to the configuration, e.g. to experiment with
+    /// wlr-randr. Layouts are still applied when heads are connected or disconnected.
+    Pause,
+    /// Resumes saving changes to the configuration after `ctl pause`.
+    Resume,
 }
 
 #[derive(Deserialize, Default)]
 struct Config {
     /// The file to save and load layout data to/from.
     layouts: Option<String>,
+    /// A layouts file which is only read from. Its layouts are matched and applied, but changes
+    /// and new layouts are saved to `layouts`.
+    layouts_ro: Option<String>,
+    /// A directory to store the layouts in one file per layout, instead of in `layouts`.
+    layouts_dir: Option<String>,
+    /// Read-only layouts, in the format of the entries of the layouts file.
+    #[serde(default, deserialize_with = "deserialize_seed_layouts")]
+    seed_layouts: Option<Vec<Layout>>,
     /// The command to run after applying a layout.
-    apply_command: Option<String>,
+    apply_command: Option<CommandLine>,
+    /// The shell (and its arguments) that commands given as a string are passed to.
+    command_shell: Option<Vec<String>>,
+    /// Whether to re-apply the matching layout when the current configuration drifts from it,
+    /// instead of saving the drifted configuration.
+    enforce: Option<bool>,
+    /// Whether to re-apply the matching layout when the current configuration looks like the
+    /// compositor's defaults (see [`crate::reset::is_reset`]), instead of saving it.
+    detect_resets: Option<bool>,
+    /// Whether to replace scales that give fractional logical sizes with the nearest scale that
+    /// gives an integer logical size.
+    snap_scales: Option<bool>,
+    /// How to pick between several modes that fit.
+    mode_policy: Option<ModePolicy>,
+    /// Per-head settings, keyed by the head's name or description.
+    heads: Option<HashMap<String, HeadConfig>>,
+    /// How to configure heads that no layout has seen.
+    new_heads: Option<NewHeadDefaults>,
+    /// How often (in seconds) to log the number of live Wayland objects.
+    object_report_interval: Option<u64>,
+    /// The properties to record when saving a layout.
+    capture: Option<Vec<Property>>,
+    /// The properties to save and apply at all.
+    managed_properties: Option<Vec<Property>>,
+    /// The shell command used to pick a layout for `apply --interactive`.
+    picker: Option<String>,
+    /// How many snapshots of the layouts file to keep.
+    history_size: Option<usize>,
+    /// The most layouts to keep.
+    max_layouts: Option<usize>,
+    /// Whether layouts are scoped to the host they were saved on.
+    scope_to_host: Option<bool>,
+    /// Whether layouts saved under other compositors are never matched.
+    scope_to_compositor: Option<bool>,
+    /// Whether a layout is applied to the heads it has when more heads are connected.
+    match_supersets: Option<bool>,
+    /// Whether a layout is applied to the heads that are connected when some of its heads are
+    /// missing.
+    match_subsets: Option<bool>,
+    /// Whether the laptop's lid is watched through UPower (or systemd-logind).
+    lid_switch: Option<bool>,
+    /// Whether the power source is watched through UPower (or sysfs).
+    watch_power_source: Option<bool>,
+    /// The fields of each head compared when matching layouts.
+    match_fields: Option<Vec<IdentityField>>,
+    /// How long (in milliseconds) to wait for the layouts to stop changing before writing them.
+    save_delay_ms: Option<u64>,
+    /// The least time (in milliseconds) between two writes of the layouts.
+    min_save_interval_ms: Option<u64>,
+    /// Whether the daemon only applies layouts, and never saves them.
+    #[serde(alias = "readonly")]
+    read_only: Option<bool>,
+    /// Whether the daemon applies layouts when the heads change.
+    apply: Option<bool>,
+    /// How many times to retry applying a layout that failed or was cancelled.
+    apply_retries: Option<u32>,
+    /// How long (in milliseconds) to wait before the first retry.
+    apply_retry_delay_ms: Option<u64>,
+    /// How long (in milliseconds) the heads must stop changing for before their layout is applied.
+    apply_settle_ms: Option<u64>,
+    /// Whether the layout is applied again after the machine resumes from suspend.
+    reapply_after_resume: Option<bool>,
+    /// How long (in milliseconds) to wait after resuming before applying the layout again.
+    resume_delay_ms: Option<u64>,
+    /// How long (in milliseconds) after sway reloads its config that changes to the configuration
+    /// are reverted instead of saved.
+    sway_reload_grace_ms: Option<u64>,
+    /// The command to run after giving up on applying a layout.
+    apply_failed_command: Option<CommandLine>,
+    /// The commands to run when things happen in the daemon.
+    hooks: Option<Hooks>,
+    /// The command that picks between the layouts matching the current heads.
+    matcher: Option<CommandLine>,
+    /// How to log.
+    log: Option<LogConfig>,
+    /// Patterns for the names or descriptions of heads to ignore.
+    ignore_heads: Option<Vec<String>>,
+    /// Patterns for the names or descriptions of the only heads to manage.
+    manage_only: Option<Vec<String>>,
 }
 
 impl Config {
     /// Creates a default config which all fields fall back to.
     fn create_default() -> Self {
         Self {
-            layouts: Some("~/.local/state/wl-distore/layouts.json".into()),
+            layouts: Some(xdg_path("XDG_STATE_HOME", "~/.local/state", "layouts.json")),
+            layouts_ro: None,
+            layouts_dir: None,
+            seed_layouts: Some(vec![]),
             apply_command: None,
+            command_shell: Some(vec!["sh".into(), "-c".into()]),
+            enforce: Some(false),
+            detect_resets: Some(false),
+            snap_scales: Some(false),
+            mode_policy: Some(ModePolicy::ClosestRefresh),
+            heads: Some(HashMap::new()),
+            new_heads: None,
+            object_report_interval: None,
+            capture: Some(vec![
+                Property::Mode,
+                Property::Position,
+                Property::Transform,
+                Property::Scale,
+                Property::AdaptiveSync,
+            ]),
+            managed_properties: Some(Property::ALL.to_vec()),
+            picker: Some("fzf".into()),
+            history_size: Some(20),
+            max_layouts: None,
+            scope_to_host: Some(false),
+            scope_to_compositor: Some(false),
+            match_supersets: Some(true),
+            match_subsets: Some(false),
+            lid_switch: Some(false),
+            watch_power_source: Some(false),
+            match_fields: Some(MatchFields::default().0),
+            save_delay_ms: Some(0),
+            min_save_interval_ms: Some(0),
+            read_only: Some(false),
+            apply: Some(true),
+            apply_retries: Some(2),
+            apply_retry_delay_ms: Some(200),
+            apply_settle_ms: Some(0),
+            reapply_after_resume: Some(false),
+            resume_delay_ms: Some(2000),
+            sway_reload_grace_ms: Some(3000),
+            apply_failed_command: None,
+            hooks: Some(Hooks::default()),
+            matcher: None,
+            log: Some(LogConfig::default()),
+            ignore_heads: Some(vec![]),
+            manage_only: None,
         }
     }
 
@@ -113,24 +863,417 @@ impl Config {
     fn take_from_flags(flags: &mut Flags) -> Self {
         Self {
             layouts: flags.layouts.take(),
+            layouts_ro: None,
+            layouts_dir: None,
+            seed_layouts: None,
             apply_command: None,
+            command_shell: None,
+            enforce: None,
+            detect_resets: None,
+            snap_scales: None,
+            mode_policy: None,
+            heads: None,
+            new_heads: None,
+            object_report_interval: flags.object_report_interval.take(),
+            capture: None,
+            managed_properties: None,
+            picker: None,
+            history_size: None,
+            max_layouts: None,
+            scope_to_host: None,
+            scope_to_compositor: None,
+            match_supersets: None,
+            match_subsets: None,
+            lid_switch: None,
+            watch_power_source: None,
+            match_fields: None,
+            save_delay_ms: None,
+            min_save_interval_ms: None,
+            read_only: flags.read_only.then_some(true),
+            apply: None,
+            apply_retries: None,
+            apply_retry_delay_ms: None,
+            apply_settle_ms: None,
+            reapply_after_resume: None,
+            resume_delay_ms: None,
+            sway_reload_grace_ms: None,
+            apply_failed_command: None,
+            hooks: None,
+            matcher: None,
+            log: None,
+            ignore_heads: None,
+            manage_only: None,
         }
     }
 
     /// Overrides any fields in `self` with any non-[`None`] values in `overrides`.
     fn override_with(&mut self, overrides: Self) {
         self.layouts = overrides.layouts.or(self.layouts.take());
+        self.layouts_ro = overrides.layouts_ro.or(self.layouts_ro.take());
+        self.layouts_dir = overrides.layouts_dir.or(self.layouts_dir.take());
+        self.seed_layouts = overrides.seed_layouts.or(self.seed_layouts.take());
         self.apply_command = overrides.apply_command.or(self.apply_command.take());
+        self.command_shell = overrides.command_shell.or(self.command_shell.take());
+        self.enforce = overrides.enforce.or(self.enforce);
+        self.detect_resets = overrides.detect_resets.or(self.detect_resets);
+        self.snap_scales = overrides.snap_scales.or(self.snap_scales);
+        self.mode_policy = overrides.mode_policy.or(self.mode_policy);
+        self.heads = overrides.heads.or(self.heads.take());
+        self.new_heads = overrides.new_heads.or(self.new_heads.take());
+        self.object_report_interval = overrides
+            .object_report_interval
+            .or(self.object_report_interval);
+        self.capture = overrides.capture.or(self.capture.take());
+        self.managed_properties = overrides
+            .managed_properties
+            .or(self.managed_properties.take());
+        self.picker = overrides.picker.or(self.picker.take());
+        self.history_size = overrides.history_size.or(self.history_size);
+        self.max_layouts = overrides.max_layouts.or(self.max_layouts);
+        self.scope_to_host = overrides.scope_to_host.or(self.scope_to_host);
+        self.scope_to_compositor = overrides.scope_to_compositor.or(self.scope_to_compositor);
+        self.match_supersets = overrides.match_supersets.or(self.match_supersets);
+        self.match_subsets = overrides.match_subsets.or(self.match_subsets);
+        self.lid_switch = overrides.lid_switch.or(self.lid_switch);
+        self.watch_power_source = overrides.watch_power_source.or(self.watch_power_source);
+        self.match_fields = overrides.match_fields.or(self.match_fields.take());
+        self.save_delay_ms = overrides.save_delay_ms.or(self.save_delay_ms);
+        self.min_save_interval_ms = overrides.min_save_interval_ms.or(self.min_save_interval_ms);
+        self.read_only = overrides.read_only.or(self.read_only);
+        self.apply = overrides.apply.or(self.apply);
+        self.apply_retries = overrides.apply_retries.or(self.apply_retries);
+        self.apply_retry_delay_ms = overrides.apply_retry_delay_ms.or(self.apply_retry_delay_ms);
+        self.apply_settle_ms = overrides.apply_settle_ms.or(self.apply_settle_ms);
+        self.reapply_after_resume = overrides.reapply_after_resume.or(self.reapply_after_resume);
+        self.resume_delay_ms = overrides.resume_delay_ms.or(self.resume_delay_ms);
+        self.sway_reload_grace_ms = overrides.sway_reload_grace_ms.or(self.sway_reload_grace_ms);
+        self.apply_failed_command = overrides
+            .apply_failed_command
+            .or(self.apply_failed_command.take());
+        self.hooks = overrides.hooks.or(self.hooks.take());
+        self.matcher = overrides.matcher.or(self.matcher.take());
+        self.log = overrides.log.or(self.log.take());
+        self.ignore_heads = overrides.ignore_heads.or(self.ignore_heads.take());
+        self.manage_only = overrides.manage_only.or(self.manage_only.take());
     }
 }
 
+/// Returns whether `text` matches `pattern`, where `*` matches any run of characters and `?`
+/// matches any single character.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match: the pattern after it, and the
+    // text it should swallow one more character of.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 /// Loads a config from `path`.
 fn load_config_from_file(path: &Path) -> Result<Config, CollectArgsError> {
     let config = match std::fs::read_to_string(path) {
         Ok(config) => config,
         Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Config::default()),
-        Err(err) => return Err(CollectArgsError::FailedToReadConfigFile(err)),
+        Err(err) => {
+            return Err(CollectArgsError::FailedToReadConfigFile(
+                path.to_path_buf(),
+                err,
+            ))
+        }
     };
 
-    toml::from_str(&config).map_err(|err| CollectArgsError::FailedToParseConfigFile(err))
+    toml::from_str(&config)
+        .map_err(|err| CollectArgsError::FailedToParseConfigFile(path.to_path_buf(), err))
+}
+
+/// Returns the path of `file` in wl-distore's directory under the XDG base directory in the
+/// environment variable `var`, or under `fallback` if it is unset. Like the spec says, relative
+/// paths in `var` are ignored.
+fn xdg_path(var: &str, fallback: &str, file: &str) -> String {
+    let base = std::env::var(var)
+        .ok()
+        .filter(|base| base.starts_with('/'))
+        .unwrap_or_else(|| fallback.to_string());
+    format!("{}/wl-distore/{file}", base.trim_end_matches('/'))
+}
+
+/// Returns the hostname of this machine.
+fn hostname() -> String {
+    rustix::system::uname()
+        .nodename()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The config file shipped by distributions or admins, which the user's config file overrides.
+const SYSTEM_CONFIG_PATH: &str = "/etc/wl-distore/config.toml";
+
+/// The prefix of environment variables that override config options, e.g. `WL_DISTORE_LAYOUTS`
+/// overrides `layouts`.
+const ENV_VAR_PREFIX: &str = "WL_DISTORE_";
+/// The environment variable that overrides the path of the config file.
+const CONFIG_ENV_VAR: &str = "WL_DISTORE_CONFIG";
+
+/// How the value of an environment variable for a config option is read (see
+/// [`load_config_from_env`]).
+#[derive(Clone, Copy)]
+enum EnvValue {
+    /// The value is taken as it is.
+    String,
+    /// A command line: an array of arguments if the value is a TOML array, and otherwise the value
+    /// as it is (even if it is valid TOML, like `1`).
+    Command,
+    /// The value is parsed as a TOML value, or taken as a string if it isn't one.
+    Toml,
+}
+
+/// The config options that can be set with environment variables (see [`ENV_VAR_PREFIX`]), and
+/// how their values are read. Table options (e.g. `heads` and `hooks`) are left out, since they
+/// can't be written as a single value.
+const ENV_OPTIONS: &[(&str, EnvValue)] = &[
+    ("layouts", EnvValue::String),
+    ("layouts_ro", EnvValue::String),
+    ("layouts_dir", EnvValue::String),
+    ("apply_command", EnvValue::Command),
+    ("command_shell", EnvValue::Toml),
+    ("enforce", EnvValue::Toml),
+    ("detect_resets", EnvValue::Toml),
+    ("snap_scales", EnvValue::Toml),
+    ("mode_policy", EnvValue::Toml),
+    ("object_report_interval", EnvValue::Toml),
+    ("capture", EnvValue::Toml),
+    ("managed_properties", EnvValue::Toml),
+    ("picker", EnvValue::String),
+    ("history_size", EnvValue::Toml),
+    ("max_layouts", EnvValue::Toml),
+    ("scope_to_host", EnvValue::Toml),
+    ("scope_to_compositor", EnvValue::Toml),
+    ("match_supersets", EnvValue::Toml),
+    ("match_subsets", EnvValue::Toml),
+    ("lid_switch", EnvValue::Toml),
+    ("watch_power_source", EnvValue::Toml),
+    ("match_fields", EnvValue::Toml),
+    ("save_delay_ms", EnvValue::Toml),
+    ("min_save_interval_ms", EnvValue::Toml),
+    ("read_only", EnvValue::Toml),
+    ("apply", EnvValue::Toml),
+    ("apply_retries", EnvValue::Toml),
+    ("apply_retry_delay_ms", EnvValue::Toml),
+    ("apply_settle_ms", EnvValue::Toml),
+    ("reapply_after_resume", EnvValue::Toml),
+    ("resume_delay_ms", EnvValue::Toml),
+    ("sway_reload_grace_ms", EnvValue::Toml),
+    ("apply_failed_command", EnvValue::Command),
+    ("matcher", EnvValue::Command),
+    ("ignore_heads", EnvValue::Toml),
+    ("manage_only", EnvValue::Toml),
+];
+
+/// Loads a config from the environment variables in `vars` that start with [`ENV_VAR_PREFIX`] and
+/// name one of the [`ENV_OPTIONS`]. Values of string options are taken as they are, and values of
+/// command options too unless they are a TOML array. Other values are parsed as a TOML value
+/// (e.g. `true` or `["HEADLESS-*"]`), or taken as a string if they aren't one. Other variables with the prefix (besides the config path and the ones passed to
+/// hooks) are warned about, since they are probably typos.
+fn load_config_from_env(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Config, CollectArgsError> {
+    let mut table = toml::Table::new();
+    for (name, value) in vars {
+        if name == CONFIG_ENV_VAR || name.starts_with(HOOK_ENV_VAR_PREFIX) {
+            continue;
+        }
+        let Some(key) = name.strip_prefix(ENV_VAR_PREFIX) else {
+            continue;
+        };
+        let key = key.to_lowercase();
+        let Some((_, kind)) = ENV_OPTIONS.iter().find(|(option, _)| *option == key) else {
+            eprintln!("Ignoring the environment variable {name}, since it isn't a config option");
+            continue;
+        };
+        let parsed = match kind {
+            EnvValue::String => None,
+            EnvValue::Command | EnvValue::Toml => {
+                toml::from_str::<toml::Table>(&format!("value = {value}"))
+                    .ok()
+                    .and_then(|mut parsed| parsed.remove("value"))
+            }
+        };
+        let value = match (kind, parsed) {
+            (EnvValue::Command, Some(parsed)) if !parsed.is_array() => toml::Value::String(value),
+            (_, Some(parsed)) => parsed,
+            (_, None) => toml::Value::String(value),
+        };
+        table.insert(key, value);
+    }
+    table
+        .try_into()
+        .map_err(CollectArgsError::FailedToParseConfigEnv)
+}
+
+/// Parses an age like "180d" for `prune --unused-for`.
+fn parse_age(age: &str) -> Result<Duration, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (amount, unit) = age.split_at(split);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| format!("\"{age}\" doesn't start with a number"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("\"{unit}\" is not a unit (use s, m, h, d or w)")),
+    };
+    Ok(Duration::from_secs(amount.saturating_mul(unit_secs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads a config from the environment variables `vars`.
+    fn env_config(vars: &[(&str, &str)]) -> Result<Config, CollectArgsError> {
+        load_config_from_env(
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+    }
+
+    #[test]
+    fn env_parses_scalars_as_toml() {
+        let config = env_config(&[
+            ("WL_DISTORE_READ_ONLY", "true"),
+            ("WL_DISTORE_APPLY_RETRIES", "5"),
+            ("WL_DISTORE_COMMAND_SHELL", r#"["bash", "-c"]"#),
+            ("WL_DISTORE_CAPTURE", r#"["mode", "position"]"#),
+        ])
+        .unwrap();
+        assert_eq!(config.read_only, Some(true));
+        assert_eq!(config.apply_retries, Some(5));
+        assert_eq!(
+            config.command_shell,
+            Some(vec!["bash".to_string(), "-c".to_string()])
+        );
+        assert_eq!(
+            config.capture,
+            Some(vec![Property::Mode, Property::Position])
+        );
+    }
+
+    #[test]
+    fn env_takes_strings_as_they_are() {
+        let config = env_config(&[
+            ("WL_DISTORE_LAYOUTS", "~/layouts.json"),
+            ("WL_DISTORE_PICKER", r#"fzf --prompt "layout> ""#),
+            // Not valid TOML, so taken as a command line.
+            ("WL_DISTORE_APPLY_COMMAND", "notify-send 'Layout applied'"),
+        ])
+        .unwrap();
+        assert_eq!(config.layouts.as_deref(), Some("~/layouts.json"));
+        assert_eq!(config.picker.as_deref(), Some(r#"fzf --prompt "layout> ""#));
+        assert_eq!(
+            config.apply_command,
+            Some(CommandLine::Shell("notify-send 'Layout applied'".into()))
+        );
+    }
+
+    #[test]
+    fn env_takes_commands_that_look_like_toml_as_they_are() {
+        let config = env_config(&[
+            ("WL_DISTORE_APPLY_COMMAND", "1"),
+            ("WL_DISTORE_APPLY_FAILED_COMMAND", "true"),
+            ("WL_DISTORE_MATCHER", r#"["my-matcher", "--json"]"#),
+        ])
+        .unwrap();
+        assert_eq!(config.apply_command, Some(CommandLine::Shell("1".into())));
+        assert_eq!(
+            config.apply_failed_command,
+            Some(CommandLine::Shell("true".into()))
+        );
+        assert_eq!(
+            config.matcher,
+            Some(CommandLine::Argv(vec![
+                "my-matcher".into(),
+                "--json".into()
+            ]))
+        );
+    }
+
+    #[test]
+    fn env_ignores_tables_and_other_variables() {
+        let config = env_config(&[
+            ("WL_DISTORE_HEADS", r#"{ "DP-1" = { scale = 2.0 } }"#),
+            ("WL_DISTORE_HOOKS", r#"{ on_save = "true" }"#),
+            ("WL_DISTORE_CONFIG", "/tmp/config.toml"),
+            ("WL_DISTORE_HOOK_EVENT", "applied"),
+            ("HOME", "/home/user"),
+        ])
+        .unwrap();
+        assert!(config.heads.is_none());
+        assert!(config.hooks.is_none());
+        assert!(config.layouts.is_none());
+    }
+
+    #[test]
+    fn env_rejects_values_of_the_wrong_type() {
+        assert!(matches!(
+            env_config(&[("WL_DISTORE_READ_ONLY", "maybe")]),
+            Err(CollectArgsError::FailedToParseConfigEnv(_))
+        ));
+        assert!(matches!(
+            env_config(&[("WL_DISTORE_APPLY_RETRIES", "-1")]),
+            Err(CollectArgsError::FailedToParseConfigEnv(_))
+        ));
+    }
+
+    #[test]
+    fn glob_matches_literals_and_wildcards() {
+        assert!(glob_matches("DP-1", "DP-1"));
+        assert!(!glob_matches("DP-1", "DP-10"));
+        assert!(glob_matches("DP-?", "DP-1"));
+        assert!(!glob_matches("DP-?", "DP-"));
+        assert!(glob_matches("HEADLESS-*", "HEADLESS-1"));
+        assert!(glob_matches("*Dell*", "Dell Inc. DELL U2720Q"));
+        assert!(!glob_matches("eDP-*", "DP-1"));
+    }
+
+    #[test]
+    fn glob_star_matches_empty_and_backtracks() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("DP-*", "DP-"));
+        assert!(glob_matches("**", "anything"));
+        assert!(glob_matches("a*b*c", "abxbc"));
+        assert!(glob_matches("*ab", "aab"));
+        assert!(!glob_matches("a*b", "abc"));
+        assert!(!glob_matches("", "a"));
+        assert!(glob_matches("", ""));
+    }
+
+    #[test]
+    fn glob_matches_unicode_characters() {
+        assert!(glob_matches("Bildschirm ?", "Bildschirm ä"));
+        assert!(glob_matches("*ü*", "Größe über"));
+    }
 }
@@ -0,0 +1,53 @@
+use tracing::info;
+
+use crate::{
+    complete::{Mode, ModeState},
+    serde::{ConfigurationHead, Transform},
+};
+
+/// Logs the requests that would configure a head, for `--dry-run`.
+pub struct DryRunHead<'a> {
+    /// The name of the head, e.g. "DP-1".
+    pub name: &'a str,
+}
+
+impl ConfigurationHead for DryRunHead<'_> {
+    fn set_mode(&mut self, mode_state: &ModeState) {
+        info!(
+            "Would set the mode of \"{}\" to {}",
+            self.name, mode_state.mode
+        );
+    }
+
+    fn set_custom_mode(&mut self, width: i32, height: i32, refresh: i32) {
+        let mode = Mode {
+            size: (width as u32, height as u32),
+            refresh: (refresh > 0).then_some(refresh as u32),
+        };
+        info!("Would set the custom mode of \"{}\" to {mode}", self.name);
+    }
+
+    fn set_position(&mut self, x: i32, y: i32) {
+        info!("Would set the position of \"{}\" to {x},{y}", self.name);
+    }
+
+    fn set_scale(&mut self, scale: f64) {
+        info!("Would set the scale of \"{}\" to {scale}", self.name);
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        info!(
+            "Would set the transform of \"{}\" to {}",
+            self.name,
+            transform.name()
+        );
+    }
+
+    fn set_adaptive_sync(&mut self, adaptive_sync: bool) {
+        info!(
+            "Would {} adaptive sync for \"{}\"",
+            if adaptive_sync { "enable" } else { "disable" },
+            self.name
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use sha2::{Digest, Sha256};
+
+/// The parts of an EDID blob that identify a monitor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edid {
+    /// The three-letter PNP ID of the manufacturer, e.g. "DEL".
+    pub manufacturer: String,
+    pub product_code: u16,
+    pub serial_number: u32,
+    /// The monitor name descriptor, e.g. "DELL U2720Q".
+    pub name: Option<String>,
+    /// The serial number descriptor, which is usually more useful than `serial_number`.
+    pub serial_string: Option<String>,
+}
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+impl Edid {
+    /// Parses the base block of `bytes`. Returns [`None`] if it isn't an EDID.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 128 || bytes[..8] != HEADER {
+            return None;
+        }
+        let manufacturer_id = u16::from_be_bytes([bytes[8], bytes[9]]);
+        let manufacturer = [10, 5, 0]
+            .into_iter()
+            .map(|shift| (b'A' - 1 + ((manufacturer_id >> shift) & 0x1f) as u8) as char)
+            .collect();
+        let mut edid = Self {
+            manufacturer,
+            product_code: u16::from_le_bytes([bytes[10], bytes[11]]),
+            serial_number: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            name: None,
+            serial_string: None,
+        };
+        // The four 18-byte descriptors. Display descriptors start with three zero bytes, followed
+        // by their type.
+        for descriptor in bytes[54..126].chunks(18) {
+            if descriptor[..3] != [0, 0, 0] {
+                continue;
+            }
+            let text = || {
+                let text = String::from_utf8_lossy(&descriptor[5..18]);
+                let text = text.split('\n').next().unwrap_or_default().trim();
+                (!text.is_empty()).then(|| text.to_string())
+            };
+            match descriptor[3] {
+                0xfc => edid.name = text(),
+                0xff => edid.serial_string = text(),
+                _ => {}
+            }
+        }
+        Some(edid)
+    }
+
+    /// Parses an EDID from a hex string, as written by tools like autorandr.
+    pub fn parse_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Self::parse(&bytes)
+    }
+
+    /// The model as wlroots reports it: the monitor name, or the product code if there is none.
+    pub fn model(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("0x{:04X}", self.product_code))
+    }
+
+    /// The serial number as wlroots reports it: the serial number descriptor, or the numeric
+    /// serial number if there is none.
+    pub fn serial(&self) -> Option<String> {
+        self.serial_string
+            .clone()
+            .or_else(|| (self.serial_number != 0).then(|| format!("0x{:08X}", self.serial_number)))
+    }
+}
+
+/// The directory the kernel lists DRM connectors in, as `card<N>-<connector>`.
+const DRM_CLASS_DIR: &str = "/sys/class/drm";
+
+/// Reads the EDID of `connector` (e.g. "DP-2") from the DRM connector of the same name, and
+/// returns a short hash of it. Returns [`None`] if there is no such connector or it has no EDID
+/// (e.g. it isn't plugged in).
+pub fn read_connector_hash(connector: &str) -> Option<String> {
+    std::fs::read_dir(DRM_CLASS_DIR)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let file_name = entry.file_name();
+            file_name
+                .to_string_lossy()
+                .strip_prefix("card")
+                .and_then(|name| name.split_once('-'))
+                .is_some_and(|(_, name)| name == connector)
+        })
+        .filter_map(|entry| std::fs::read(entry.path().join("edid")).ok())
+        .find(|bytes| Edid::parse(bytes).is_some())
+        .map(|bytes| hash(&bytes))
+}
+
+/// A short hash of the EDID blob `bytes`, used to identify a monitor.
+pub fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an EDID base block for "DEL" product 0xA0C1 with serial number 0x12345678, and the
+    /// display descriptors `descriptors` (pairs of a type and its text).
+    fn edid_bytes(descriptors: &[(u8, &str)]) -> Vec<u8> {
+        let mut bytes = HEADER.to_vec();
+        bytes.extend_from_slice(&0x10acu16.to_be_bytes());
+        bytes.extend_from_slice(&0xa0c1u16.to_le_bytes());
+        bytes.extend_from_slice(&0x12345678u32.to_le_bytes());
+        bytes.resize(54, 0);
+        for (descriptor_type, text) in descriptors {
+            let mut descriptor = vec![0, 0, 0, *descriptor_type, 0];
+            descriptor.extend_from_slice(text.as_bytes());
+            descriptor.push(b'\n');
+            descriptor.resize(18, b' ');
+            bytes.extend_from_slice(&descriptor);
+        }
+        // A detailed timing descriptor, which doesn't start with zeros.
+        bytes.resize(126, 1);
+        bytes.resize(128, 0);
+        bytes
+    }
+
+    #[test]
+    fn parses_identity_and_descriptors() {
+        let edid = Edid::parse(&edid_bytes(&[(0xfc, "DELL U2720Q"), (0xff, "ABC123")])).unwrap();
+        assert_eq!(
+            edid,
+            Edid {
+                manufacturer: "DEL".into(),
+                product_code: 0xa0c1,
+                serial_number: 0x12345678,
+                name: Some("DELL U2720Q".into()),
+                serial_string: Some("ABC123".into()),
+            }
+        );
+        assert_eq!(edid.model(), "DELL U2720Q");
+        assert_eq!(edid.serial().as_deref(), Some("ABC123"));
+    }
+
+    #[test]
+    fn falls_back_to_numbers_without_descriptors() {
+        let edid = Edid::parse(&edid_bytes(&[])).unwrap();
+        assert_eq!(edid.name, None);
+        assert_eq!(edid.serial_string, None);
+        assert_eq!(edid.model(), "0xA0C1");
+        assert_eq!(edid.serial().as_deref(), Some("0x12345678"));
+
+        let mut bytes = edid_bytes(&[]);
+        bytes[12..16].fill(0);
+        assert_eq!(Edid::parse(&bytes).unwrap().serial(), None);
+    }
+
+    #[test]
+    fn ignores_other_and_empty_descriptors() {
+        let edid = Edid::parse(&edid_bytes(&[(0xfe, "unspecified"), (0xfc, "")])).unwrap();
+        assert_eq!(edid.name, None);
+        assert_eq!(edid.serial_string, None);
+    }
+
+    #[test]
+    fn rejects_bad_headers_and_short_blobs() {
+        let mut bytes = edid_bytes(&[]);
+        bytes[0] = 0xff;
+        assert_eq!(Edid::parse(&bytes), None);
+        assert_eq!(Edid::parse(&edid_bytes(&[])[..127]), None);
+    }
+
+    #[test]
+    fn parses_hex() {
+        let bytes = edid_bytes(&[(0xfc, "DELL U2720Q")]);
+        let hex = bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        assert_eq!(Edid::parse_hex(&format!("{hex}\n")), Edid::parse(&bytes));
+        assert_eq!(Edid::parse_hex(&hex[1..]), None);
+        assert_eq!(Edid::parse_hex(&hex.replacen("00", "zz", 1)), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    complete::HeadIdentity,
+    edid::Edid,
+    serde::{Layout, SavedConfiguration, Transform},
+};
+
+use super::{normalize_positions, resolve_head, resolve_mode, ConnectedHead, ImportError};
+
+/// An output in an autorandr `config` file.
+#[derive(Default)]
+struct Output {
+    off: bool,
+    mode: Option<(u32, u32)>,
+    /// The refresh rate in Hz.
+    rate: Option<f64>,
+    position: (i32, i32),
+    /// The rotation in degrees counter-clockwise.
+    rotation: u32,
+    flipped: bool,
+    scale: Option<f64>,
+}
+
+/// Imports the autorandr profile in the directory at `path`, or every profile in it if `path` is
+/// autorandr's config directory (e.g. `~/.config/autorandr`). Layouts are named after their
+/// profiles.
+pub fn import(path: &Path, connected_heads: &[ConnectedHead]) -> Result<Vec<Layout>, ImportError> {
+    if path.join("config").is_file() {
+        return Ok(vec![import_profile(path, connected_heads)?]);
+    }
+    let mut profiles = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref()
+                .map_or(true, |path| path.join("config").is_file())
+        })
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    profiles.sort();
+    profiles
+        .iter()
+        .map(|profile| import_profile(profile, connected_heads))
+        .collect()
+}
+
+/// Converts the autorandr profile in the directory `profile` into a layout. The `setup` file gives
+/// the EDID of each output, which is used to identify the monitor.
+fn import_profile(
+    profile: &Path,
+    connected_heads: &[ConnectedHead],
+) -> Result<Layout, ImportError> {
+    let config = std::fs::read_to_string(profile.join("config"))?;
+    let setup = match std::fs::read_to_string(profile.join("setup")) {
+        Ok(setup) => setup,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let edids = setup
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .filter_map(|(output, edid)| Some((output, Edid::parse_hex(edid)?)))
+        .collect::<HashMap<_, _>>();
+    let identify = |connector: &str| -> (HeadIdentity, Option<&ConnectedHead>) {
+        let edid = edids.get(connector);
+        resolve_head(
+            connected_heads,
+            connector,
+            edid.map(|edid| edid.manufacturer.as_str()),
+            edid.map(Edid::model).as_deref(),
+            edid.and_then(Edid::serial).as_deref(),
+        )
+    };
+
+    let mut enabled = vec![];
+    let mut heads = HashMap::new();
+    for (connector, output) in parse_config(&config) {
+        let (identity, connected_head) = identify(connector);
+        if output.off {
+            heads.insert(identity, None);
+            continue;
+        }
+        let mode = output.mode.map(|size| {
+            resolve_mode(
+                connected_head,
+                size,
+                output.rate.map(|rate| (rate * 1000.0).round() as u32),
+            )
+        });
+        let transform = match (output.rotation % 360, output.flipped) {
+            (90, false) => Transform::_90,
+            (180, false) => Transform::_180,
+            (270, false) => Transform::_270,
+            (_, false) => Transform::Normal,
+            (90, true) => Transform::Flipped90,
+            (180, true) => Transform::Flipped180,
+            (270, true) => Transform::Flipped270,
+            (_, true) => Transform::Flipped,
+        };
+        enabled.push((
+            identity,
+            output.position,
+            SavedConfiguration {
+                mode,
+                position: (0, 0),
+                transform,
+                scale: output.scale.unwrap_or(1.0),
+                adaptive_sync: None,
+                dont_care: Default::default(),
+            },
+        ));
+    }
+    normalize_positions(&mut enabled);
+    heads.extend(
+        enabled
+            .into_iter()
+            .map(|(identity, _, configuration)| (identity, Some(configuration))),
+    );
+    let name = profile
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    Ok(Layout {
+        name,
+        ..Layout::new(heads)
+    })
+}
+
+/// Parses an autorandr `config` file into its outputs, keyed by connector. Unknown settings (e.g.,
+/// `crtc` or `dpi`) are ignored.
+fn parse_config(config: &str) -> Vec<(&str, Output)> {
+    let mut outputs: Vec<(&str, Output)> = vec![];
+    for line in config.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(key), value) = (words.next(), words.next()) else {
+            continue;
+        };
+        if key == "output" {
+            if let Some(connector) = value {
+                outputs.push((connector, Output::default()));
+            }
+            continue;
+        }
+        let Some((_, output)) = outputs.last_mut() else {
+            continue;
+        };
+        match (key, value) {
+            ("off", _) => output.off = true,
+            ("mode", Some(value)) => output.mode = parse_pair(value),
+            ("rate", Some(value)) => output.rate = value.parse().ok(),
+            ("pos", Some(value)) => output.position = parse_pair(value).unwrap_or_default(),
+            ("rotate", Some(value)) => {
+                output.rotation += match value {
+                    "left" => 90,
+                    "inverted" => 180,
+                    "right" => 270,
+                    _ => 0,
+                }
+            }
+            ("reflect", Some(value)) => match value {
+                "x" => output.flipped = !output.flipped,
+                "y" => {
+                    // A vertical reflection is a horizontal one rotated by 180 degrees.
+                    output.flipped = !output.flipped;
+                    output.rotation += 180;
+                }
+                "xy" => output.rotation += 180,
+                _ => {}
+            },
+            ("scale", Some(value)) => {
+                // xrandr scales the framebuffer, so 2x2 shows twice as much, like a Wayland scale
+                // of 0.5.
+                output.scale = value
+                    .split_once('x')
+                    .and_then(|(x, _)| x.parse::<f64>().ok())
+                    .filter(|x| *x > 0.0)
+                    .map(|x| 1.0 / x);
+            }
+            _ => {}
+        }
+    }
+    outputs
+}
+
+/// Parses a pair of numbers separated by an "x", e.g. "1920x1080".
+fn parse_pair<T: FromStr>(value: &str) -> Option<(T, T)> {
+    let (a, b) = value.split_once('x')?;
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::serde::{Layout, SavedConfiguration, Transform};
+
+use super::{normalize_positions, resolve_head, resolve_mode, ConnectedHead, ImportError};
+
+/// GNOME's `monitors.xml`.
+#[derive(Deserialize)]
+struct Monitors {
+    #[serde(rename = "configuration", default)]
+    configurations: Vec<Configuration>,
+}
+
+#[derive(Deserialize)]
+struct Configuration {
+    #[serde(rename = "logicalmonitor", default)]
+    logical_monitors: Vec<LogicalMonitor>,
+    #[serde(default)]
+    disabled: Option<Disabled>,
+}
+
+/// A group of monitors sharing a position (several monitors are mirrored).
+#[derive(Deserialize)]
+struct LogicalMonitor {
+    x: i32,
+    y: i32,
+    #[serde(default = "default_scale")]
+    scale: f64,
+    #[serde(default)]
+    transform: Option<XmlTransform>,
+    #[serde(rename = "monitor", default)]
+    monitors: Vec<Monitor>,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct XmlTransform {
+    #[serde(default)]
+    rotation: Option<String>,
+    #[serde(default)]
+    flipped: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Monitor {
+    monitorspec: MonitorSpec,
+    #[serde(default)]
+    mode: Option<XmlMode>,
+}
+
+#[derive(Deserialize)]
+struct MonitorSpec {
+    connector: String,
+    #[serde(default)]
+    vendor: Option<String>,
+    #[serde(default)]
+    product: Option<String>,
+    #[serde(default)]
+    serial: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct XmlMode {
+    width: u32,
+    height: u32,
+    /// The refresh rate in Hz.
+    rate: f64,
+}
+
+#[derive(Deserialize)]
+struct Disabled {
+    #[serde(rename = "monitorspec", default)]
+    monitor_specs: Vec<MonitorSpec>,
+}
+
+impl XmlTransform {
+    /// Converts to a [`Transform`]. GNOME's rotations are counter-clockwise, like Wayland's.
+    fn to_transform(&self) -> Transform {
+        let flipped = self.flipped.as_deref() == Some("yes");
+        match (self.rotation.as_deref(), flipped) {
+            (Some("left"), false) => Transform::_90,
+            (Some("upside_down"), false) => Transform::_180,
+            (Some("right"), false) => Transform::_270,
+            (_, false) => Transform::Normal,
+            (Some("left"), true) => Transform::Flipped90,
+            (Some("upside_down"), true) => Transform::Flipped180,
+            (Some("right"), true) => Transform::Flipped270,
+            (_, true) => Transform::Flipped,
+        }
+    }
+}
+
+/// Converts each configuration in the `monitors.xml` in `contents` into a layout. GNOME identifies
+/// monitors by connector, vendor, product and serial, which are matched against `connected_heads`
+/// to recover their full identities.
+pub fn import(
+    contents: &str,
+    connected_heads: &[ConnectedHead],
+) -> Result<Vec<Layout>, ImportError> {
+    let monitors: Monitors = quick_xml::de::from_str(contents)?;
+    Ok(monitors
+        .configurations
+        .iter()
+        .map(|configuration| import_configuration(configuration, connected_heads))
+        .collect())
+}
+
+fn import_configuration(
+    configuration: &Configuration,
+    connected_heads: &[ConnectedHead],
+) -> Layout {
+    let resolve = |spec: &MonitorSpec| {
+        resolve_head(
+            connected_heads,
+            &spec.connector,
+            spec.vendor.as_deref(),
+            spec.product.as_deref(),
+            spec.serial.as_deref(),
+        )
+    };
+
+    let mut enabled = vec![];
+    for logical_monitor in configuration.logical_monitors.iter() {
+        let transform = logical_monitor
+            .transform
+            .as_ref()
+            .map_or(Transform::Normal, XmlTransform::to_transform);
+        for monitor in logical_monitor.monitors.iter() {
+            let (identity, connected_head) = resolve(&monitor.monitorspec);
+            let mode = monitor.mode.as_ref().map(|mode| {
+                resolve_mode(
+                    connected_head,
+                    (mode.width, mode.height),
+                    Some((mode.rate * 1000.0).round() as u32),
+                )
+            });
+            enabled.push((
+                identity,
+                (logical_monitor.x, logical_monitor.y),
+                SavedConfiguration {
+                    mode,
+                    position: (0, 0),
+                    transform,
+                    scale: logical_monitor.scale,
+                    adaptive_sync: None,
+                    dont_care: Default::default(),
+                },
+            ));
+        }
+    }
+    normalize_positions(&mut enabled);
+
+    let mut heads = enabled
+        .into_iter()
+        .map(|(identity, _, configuration)| (identity, Some(configuration)))
+        .collect::<HashMap<_, _>>();
+    for spec in configuration
+        .disabled
+        .iter()
+        .flat_map(|disabled| disabled.monitor_specs.iter())
+    {
+        heads.insert(resolve(spec).0, None);
+    }
+    Layout::new(heads)
+}
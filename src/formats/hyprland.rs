@@ -0,0 +1,110 @@
+use crate::serde::Layout;
+
+/// Converts `layout` into Hyprland `monitor=` lines. Properties the layout doesn't care about are
+/// left for Hyprland to pick (e.g., `preferred` for the mode, `auto` for the position).
+pub fn export(layout: &Layout) -> String {
+    let mut heads = layout.heads.iter().collect::<Vec<_>>();
+    heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let mut lines = String::from("# Generated by wl-distore.\n");
+    if let Some(name) = layout.name.as_deref() {
+        lines.push_str(&format!("# Layout: {name}\n"));
+    }
+    for (identity, configuration) in heads {
+        let Some(configuration) = configuration else {
+            lines.push_str(&format!("monitor={},disable\n", identity.name));
+            continue;
+        };
+        let mode = match configuration.mode {
+            Some(mode) => match mode.refresh {
+                Some(refresh) => format!(
+                    "{}x{}@{}.{:03}",
+                    mode.size.0,
+                    mode.size.1,
+                    refresh / 1000,
+                    refresh % 1000
+                ),
+                None => format!("{}x{}", mode.size.0, mode.size.1),
+            },
+            None => "preferred".to_string(),
+        };
+        let position = if configuration.dont_care.position {
+            "auto".to_string()
+        } else {
+            format!("{}x{}", configuration.position.0, configuration.position.1)
+        };
+        let scale = if configuration.dont_care.scale {
+            "auto".to_string()
+        } else {
+            configuration.scale.to_string()
+        };
+        lines.push_str(&format!(
+            "monitor={},{mode},{position},{scale}",
+            identity.name
+        ));
+        if !configuration.dont_care.transform {
+            // Hyprland numbers transforms like wl_output, which is the order of the variants.
+            lines.push_str(&format!(",transform,{}", configuration.transform as u32));
+        }
+        if let Some(adaptive_sync) = configuration.adaptive_sync {
+            lines.push_str(&format!(",vrr,{}", u32::from(adaptive_sync)));
+        }
+        lines.push('\n');
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        complete::Mode,
+        formats::{test_identity, test_layout},
+        serde::{DontCare, SavedConfiguration, Transform},
+    };
+
+    #[test]
+    fn exports_monitor_lines() {
+        assert_eq!(
+            export(&test_layout()),
+            "# Generated by wl-distore.\n\
+             # Layout: desk\n\
+             monitor=DP-1,3840x2160@59.997,0x0,1.5,transform,0,vrr,1\n\
+             monitor=HDMI-A-1,disable\n\
+             monitor=eDP-1,1920x1200@60.000,2560x0,auto,transform,1\n"
+        );
+    }
+
+    #[test]
+    fn leaves_unset_properties_to_hyprland() {
+        let export_head = |mode| {
+            let configuration = SavedConfiguration {
+                mode,
+                position: (0, 0),
+                transform: Transform::Flipped270,
+                scale: 1.0,
+                adaptive_sync: Some(false),
+                dont_care: DontCare {
+                    position: true,
+                    transform: true,
+                    scale: true,
+                },
+            };
+            export(&Layout::new(
+                [(test_identity("DP-2", None), Some(configuration))].into(),
+            ))
+        };
+        let mode = Mode {
+            size: (1920, 1080),
+            refresh: None,
+        };
+        assert_eq!(
+            export_head(Some(mode)),
+            "# Generated by wl-distore.\nmonitor=DP-2,1920x1080,auto,auto,vrr,0\n"
+        );
+        assert_eq!(
+            export_head(None),
+            "# Generated by wl-distore.\nmonitor=DP-2,preferred,auto,auto,vrr,0\n"
+        );
+    }
+}
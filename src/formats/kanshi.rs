@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use crate::serde::{DontCare, Layout, SavedConfiguration, Transform};
+
+use super::{
+    normalize_positions, output_criteria, parse_mode, resolve_criteria, resolve_mode, tokenize,
+    ConnectedHead, ImportError, Token,
+};
+
+/// The directives of an `output` in a kanshi config. Directives that aren't given are [`None`].
+#[derive(Default, Clone)]
+struct Output {
+    enabled: Option<bool>,
+    /// The size and refresh rate (in Hz) of the mode.
+    mode: Option<((u32, u32), Option<f64>)>,
+    position: Option<(i32, i32)>,
+    scale: Option<f64>,
+    transform: Option<Transform>,
+    adaptive_sync: Option<bool>,
+    alias: Option<String>,
+}
+
+impl Output {
+    /// Fills in the directives that aren't given with those in `defaults`.
+    fn merge(&mut self, defaults: &Output) {
+        self.enabled = self.enabled.or(defaults.enabled);
+        self.mode = self.mode.or(defaults.mode);
+        self.position = self.position.or(defaults.position);
+        self.scale = self.scale.or(defaults.scale);
+        self.transform = self.transform.or(defaults.transform);
+        self.adaptive_sync = self.adaptive_sync.or(defaults.adaptive_sync);
+    }
+}
+
+/// A profile in a kanshi config.
+struct Profile {
+    name: Option<String>,
+    /// The outputs of the profile, keyed by their criteria.
+    outputs: Vec<(String, Output)>,
+}
+
+/// A parsed kanshi config.
+struct Config {
+    profiles: Vec<Profile>,
+    /// The outputs outside of any profile, which give defaults and aliases, keyed by their
+    /// criteria.
+    outputs: Vec<(String, Output)>,
+}
+
+/// Converts each profile in the kanshi config in `contents` into a layout. Outputs are matched
+/// against `connected_heads` by connector or by make, model and serial number. Outputs matching
+/// any head (`*`) and `exec` directives are ignored, as are `include`s.
+pub fn import(
+    contents: &str,
+    connected_heads: &[ConnectedHead],
+) -> Result<Vec<Layout>, ImportError> {
+    let Config {
+        profiles,
+        outputs: global_outputs,
+    } = parse(contents)?;
+    let aliases = global_outputs
+        .iter()
+        .filter_map(|(criteria, output)| Some((output.alias.clone()?, criteria.clone())))
+        .collect::<HashMap<_, _>>();
+
+    let mut layouts = vec![];
+    'profiles: for profile in profiles {
+        let mut enabled = vec![];
+        let mut heads = HashMap::new();
+        for (criteria, mut output) in profile.outputs {
+            let criteria = aliases.get(&criteria).cloned().unwrap_or(criteria);
+            if criteria == "*" {
+                continue;
+            }
+            for (global_criteria, defaults) in global_outputs.iter() {
+                if *global_criteria == criteria {
+                    output.merge(defaults);
+                }
+            }
+            let Some((identity, connected_head)) = resolve_criteria(connected_heads, &criteria)
+            else {
+                eprintln!(
+                    "Warning: skipping profile {} since \"{criteria}\" isn't connected, so it can't be identified",
+                    profile.name.as_deref().unwrap_or("(unnamed)")
+                );
+                continue 'profiles;
+            };
+            if output.enabled == Some(false) {
+                heads.insert(identity, None);
+                continue;
+            }
+            let configuration = SavedConfiguration {
+                mode: output.mode.map(|(size, refresh)| {
+                    resolve_mode(
+                        connected_head,
+                        size,
+                        refresh.map(|refresh| (refresh * 1000.0).round() as u32),
+                    )
+                }),
+                position: (0, 0),
+                transform: output.transform.unwrap_or(Transform::Normal),
+                scale: output.scale.unwrap_or(1.0),
+                adaptive_sync: output.adaptive_sync,
+                // Kanshi leaves anything that isn't given as the compositor chose it.
+                dont_care: DontCare {
+                    position: output.position.is_none(),
+                    transform: output.transform.is_none(),
+                    scale: output.scale.is_none(),
+                },
+            };
+            match output.position {
+                Some(position) => enabled.push((identity, position, configuration)),
+                None => {
+                    heads.insert(identity, Some(configuration));
+                }
+            }
+        }
+        normalize_positions(&mut enabled);
+        heads.extend(
+            enabled
+                .into_iter()
+                .map(|(identity, _, configuration)| (identity, Some(configuration))),
+        );
+        layouts.push(Layout {
+            name: profile.name,
+            ..Layout::new(heads)
+        });
+    }
+    Ok(layouts)
+}
+
+/// Parses a kanshi config. Statements other than `profile`s and `output`s are ignored.
+fn parse(contents: &str) -> Result<Config, ImportError> {
+    let mut profiles = vec![];
+    let mut global_outputs = vec![];
+    let mut profile: Option<Profile> = None;
+    let mut words = vec![];
+    for token in tokenize(contents) {
+        match token {
+            Token::Word(word) => {
+                words.push(word);
+                continue;
+            }
+            // A profile's name may be followed by its "{" on the next line.
+            Token::Newline if words.first().is_some_and(|word| word == "profile") => continue,
+            Token::Newline => {}
+            Token::Open => {
+                if profile.is_some() || words.first().map(String::as_str) != Some("profile") {
+                    return Err(ImportError::Kanshi("unexpected \"{\"".into()));
+                }
+                profile = Some(Profile {
+                    name: words.get(1).cloned(),
+                    outputs: vec![],
+                });
+                words.clear();
+                continue;
+            }
+            Token::Close => {}
+        }
+        if words.first().map(String::as_str) == Some("output") && words.len() > 1 {
+            let output = (words[1].clone(), parse_output(&words[2..]));
+            match profile.as_mut() {
+                Some(profile) => profile.outputs.push(output),
+                None => global_outputs.push(output),
+            }
+        }
+        words.clear();
+        if let Token::Close = token {
+            let Some(profile) = profile.take() else {
+                return Err(ImportError::Kanshi("unexpected \"}\"".into()));
+            };
+            profiles.push(profile);
+        }
+    }
+    if profile.is_some() {
+        return Err(ImportError::Kanshi("a profile is missing its \"}\"".into()));
+    }
+    Ok(Config {
+        profiles,
+        outputs: global_outputs,
+    })
+}
+
+/// Parses the directives of an `output`. Unknown directives are ignored.
+fn parse_output(words: &[String]) -> Output {
+    let mut output = Output::default();
+    let mut words = words.iter().map(String::as_str);
+    while let Some(directive) = words.next() {
+        match directive {
+            "enable" => output.enabled = Some(true),
+            "disable" => output.enabled = Some(false),
+            "mode" => {
+                let mut value = words.next();
+                if value == Some("--custom") {
+                    value = words.next();
+                }
+                output.mode = value.and_then(parse_mode);
+            }
+            "position" => {
+                output.position = words.next().and_then(|value| {
+                    let (x, y) = value.split_once(',')?;
+                    Some((x.parse().ok()?, y.parse().ok()?))
+                });
+            }
+            "scale" => output.scale = words.next().and_then(|value| value.parse().ok()),
+            "transform" => output.transform = words.next().and_then(Transform::from_name),
+            "adaptive_sync" => output.adaptive_sync = words.next().map(|value| value == "on"),
+            "alias" => output.alias = words.next().map(str::to_string),
+            _ => {}
+        }
+    }
+    output
+}
+
+/// Converts `layout` into a kanshi profile. Heads are matched by make, model and serial number
+/// where possible, so the profile keeps working if a monitor moves to another port.
+pub fn export(layout: &Layout) -> String {
+    let mut heads = layout.heads.iter().collect::<Vec<_>>();
+    heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let mut profile = match layout.name.as_deref() {
+        Some(name) => format!("profile {} {{\n", quote(name)),
+        None => "profile {\n".to_string(),
+    };
+    for (identity, configuration) in heads {
+        profile.push_str(&format!("\toutput {}", quote(&output_criteria(identity))));
+        let Some(configuration) = configuration else {
+            profile.push_str(" disable\n");
+            continue;
+        };
+        profile.push_str(" enable");
+        if let Some(mode) = configuration.mode {
+            profile.push_str(&format!(" mode {}x{}", mode.size.0, mode.size.1));
+            if let Some(refresh) = mode.refresh {
+                profile.push_str(&format!("@{}.{:03}Hz", refresh / 1000, refresh % 1000));
+            }
+        }
+        if !configuration.dont_care.position {
+            profile.push_str(&format!(
+                " position {},{}",
+                configuration.position.0, configuration.position.1
+            ));
+        }
+        if !configuration.dont_care.scale {
+            profile.push_str(&format!(" scale {}", configuration.scale));
+        }
+        if !configuration.dont_care.transform {
+            profile.push_str(&format!(" transform {}", configuration.transform.name()));
+        }
+        if let Some(adaptive_sync) = configuration.adaptive_sync {
+            profile.push_str(if adaptive_sync {
+                " adaptive_sync on"
+            } else {
+                " adaptive_sync off"
+            });
+        }
+        profile.push('\n');
+    }
+    profile.push_str("}\n");
+    profile
+}
+
+/// Quotes `value` for a kanshi config if it contains anything but letters, digits, dashes and
+/// underscores.
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{test_connected_heads, test_identity, test_layout};
+
+    #[test]
+    fn exports_a_profile() {
+        assert_eq!(
+            export(&test_layout()),
+            "profile desk {\n\
+             \toutput \"Dell Inc. DELL U2720Q ABC123\" enable mode 3840x2160@59.997Hz position 0,0 \
+             scale 1.5 transform normal adaptive_sync on\n\
+             \toutput HDMI-A-1 disable\n\
+             \toutput eDP-1 enable mode 1920x1200@60.000Hz position 2560,0 transform 90\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn round_trips_through_export_and_import() {
+        let layout = test_layout();
+        let layouts = import(&export(&layout), &test_connected_heads(&layout)).unwrap();
+        assert_eq!(layouts.len(), 1);
+        assert_eq!(layouts[0].name, layout.name);
+        assert_eq!(layouts[0].heads, layout.heads);
+    }
+
+    #[test]
+    fn imports_aliases_defaults_and_negative_positions() {
+        let layout = test_layout();
+        let config = r#"
+            # Outputs outside of profiles give defaults and aliases.
+            output "Dell Inc. DELL U2720Q ABC123" alias $dell scale 2
+            output eDP-1 mode 1920x1200@60Hz
+
+            profile docked
+            {
+                output $dell position -1920,0
+                output eDP-1 position 0,-100 transform flipped-90
+                output * disable
+                exec notify-send docked
+            }
+        "#;
+        let layouts = import(config, &test_connected_heads(&layout)).unwrap();
+        assert_eq!(layouts.len(), 1);
+        assert_eq!(layouts[0].name.as_deref(), Some("docked"));
+        let heads = &layouts[0].heads;
+        assert_eq!(heads.len(), 2);
+
+        let dell = heads[&test_identity("DP-1", Some(("Dell Inc.", "DELL U2720Q", "ABC123")))]
+            .as_ref()
+            .unwrap();
+        assert_eq!(dell.position, (0, 100));
+        assert_eq!(dell.scale, 2.0);
+        assert!(dell.dont_care.transform);
+        assert_eq!(dell.mode, None);
+
+        let laptop = heads[&test_identity("eDP-1", None)].as_ref().unwrap();
+        assert_eq!(laptop.position, (1920, 0));
+        assert_eq!(laptop.transform, Transform::Flipped90);
+        assert!(laptop.dont_care.scale);
+        assert_eq!(
+            laptop.mode.map(|mode| (mode.size, mode.refresh)),
+            Some(((1920, 1200), Some(60000)))
+        );
+    }
+
+    #[test]
+    fn skips_profiles_with_unknown_descriptions() {
+        let layout = test_layout();
+        let config =
+            "profile { output \"Acme Screen 1\" enable }\nprofile laptop { output eDP-1 enable }\n";
+        let layouts = import(config, &test_connected_heads(&layout)).unwrap();
+        assert_eq!(layouts.len(), 1);
+        assert_eq!(layouts[0].name.as_deref(), Some("laptop"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        for config in ["profile {", "}", "output eDP-1 {", "profile { profile {"] {
+            assert!(
+                matches!(import(config, &[]), Err(ImportError::Kanshi(_))),
+                "{config:?} should be rejected"
+            );
+        }
+    }
+}
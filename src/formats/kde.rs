@@ -0,0 +1,164 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::serde::{Layout, SavedConfiguration, Transform};
+
+use super::{normalize_positions, resolve_head, resolve_mode, ConnectedHead, ImportError};
+
+/// An output in a kscreen config file. Each file is a list of these.
+#[derive(Deserialize)]
+struct Output {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    metadata: Option<Metadata>,
+    #[serde(default)]
+    mode: Option<OutputMode>,
+    #[serde(default)]
+    pos: Option<Position>,
+    /// 1 = none, 2 = left, 4 = inverted, 8 = right.
+    #[serde(default)]
+    rotation: Option<u32>,
+    #[serde(default)]
+    scale: Option<f64>,
+    /// 0 = never, 1 = always, 2 = automatic.
+    #[serde(default)]
+    vrrpolicy: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    /// The connector, e.g. "DP-1".
+    name: String,
+    /// The make, model and serial number joined with dashes, e.g. "xrandr-Dell Inc.-DELL
+    /// U2720Q-ABC123".
+    #[serde(default)]
+    fullname: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OutputMode {
+    size: Size,
+    /// The refresh rate in Hz.
+    #[serde(default)]
+    refresh: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct Size {
+    width: u32,
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct Position {
+    x: i32,
+    y: i32,
+}
+
+/// Imports the kscreen config file at `path`, or every config file in `path` if it is a directory.
+/// The `outputs` subdirectory kscreen keeps per-output settings in is skipped.
+pub fn import(path: &Path, connected_heads: &[ConnectedHead]) -> Result<Vec<Layout>, ImportError> {
+    if !path.is_dir() {
+        return Ok(vec![import_file(
+            &std::fs::read_to_string(path)?,
+            connected_heads,
+        )?]);
+    }
+    let mut paths = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    let mut layouts = vec![];
+    for path in paths {
+        if path.is_dir() {
+            continue;
+        }
+        match import_file(&std::fs::read_to_string(&path)?, connected_heads) {
+            Ok(layout) => layouts.push(layout),
+            // kscreen keeps other files around, so skip anything that isn't a config.
+            Err(ImportError::Json(err)) => {
+                eprintln!("Warning: skipping {}: {err}", path.display());
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(layouts)
+}
+
+/// Converts the outputs in the kscreen config `contents` into a layout.
+fn import_file(contents: &str, connected_heads: &[ConnectedHead]) -> Result<Layout, ImportError> {
+    let outputs: Vec<Output> = serde_json::from_str(contents)?;
+    let mut enabled = vec![];
+    let mut heads = HashMap::new();
+    for output in outputs {
+        let Some(metadata) = output.metadata.as_ref() else {
+            continue;
+        };
+        let (make, model, serial_number) = split_fullname(metadata.fullname.as_deref());
+        let (identity, connected_head) =
+            resolve_head(connected_heads, &metadata.name, make, model, serial_number);
+        if !output.enabled {
+            heads.insert(identity, None);
+            continue;
+        }
+        let mode = output.mode.as_ref().map(|mode| {
+            resolve_mode(
+                connected_head,
+                (mode.size.width, mode.size.height),
+                mode.refresh
+                    .map(|refresh| (refresh * 1000.0).round() as u32),
+            )
+        });
+        let transform = match output.rotation {
+            Some(2) => Transform::_90,
+            Some(4) => Transform::_180,
+            Some(8) => Transform::_270,
+            _ => Transform::Normal,
+        };
+        let position = output
+            .pos
+            .as_ref()
+            .map_or((0, 0), |position| (position.x, position.y));
+        enabled.push((
+            identity,
+            position,
+            SavedConfiguration {
+                mode,
+                position: (0, 0),
+                transform,
+                scale: output.scale.unwrap_or(1.0),
+                adaptive_sync: output.vrrpolicy.map(|policy| policy != 0),
+                dont_care: Default::default(),
+            },
+        ));
+    }
+    normalize_positions(&mut enabled);
+    heads.extend(
+        enabled
+            .into_iter()
+            .map(|(identity, _, configuration)| (identity, Some(configuration))),
+    );
+    Ok(Layout::new(heads))
+}
+
+/// Splits a kscreen "fullname" into the make, model and serial number. The make and serial number
+/// are assumed not to contain dashes, so any dashes belong to the model.
+fn split_fullname(fullname: Option<&str>) -> (Option<&str>, Option<&str>, Option<&str>) {
+    let Some(fullname) = fullname else {
+        return (None, None, None);
+    };
+    let fullname = fullname.strip_prefix("xrandr-").unwrap_or(fullname);
+    let Some((make, rest)) = fullname.split_once('-') else {
+        return (Some(fullname), None, None);
+    };
+    match rest.rsplit_once('-') {
+        Some((model, serial_number)) => (
+            Some(make),
+            Some(model),
+            (!serial_number.is_empty()).then_some(serial_number),
+        ),
+        None => (Some(make), Some(rest), None),
+    }
+}
@@ -0,0 +1,395 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use thiserror::Error;
+
+use crate::{
+    complete::{HeadIdentity, Mode},
+    serde::{Layout, SavedConfiguration, Transform},
+};
+
+mod autorandr;
+mod gnome;
+mod hyprland;
+mod kanshi;
+mod kde;
+mod nwg_displays;
+mod way_displays;
+mod wlr_randr;
+mod xrandr;
+
+/// A format that layouts can be imported from.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// A way-displays `cfg.yaml`.
+    WayDisplays,
+    /// GNOME's `~/.config/monitors.xml`.
+    Gnome,
+    /// A KDE kscreen config file, or a directory of them (e.g. `~/.local/share/kscreen`).
+    Kde,
+    /// An autorandr profile directory, or autorandr's config directory (e.g.
+    /// `~/.config/autorandr`).
+    Autorandr,
+    /// A kanshi config (e.g. `~/.config/kanshi/config`).
+    Kanshi,
+    /// The outputs file written by nwg-displays (e.g. `~/.config/sway/outputs` or
+    /// `~/.config/hypr/monitors.conf`).
+    NwgDisplays,
+}
+
+/// A format that layouts can be exported to.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A way-displays `cfg.yaml`.
+    WayDisplays,
+    /// A shell script that runs xrandr, for X11 sessions.
+    Xrandr,
+    /// A kanshi profile.
+    Kanshi,
+    /// Hyprland `monitor=` lines.
+    Hyprland,
+    /// A wlr-randr command.
+    WlrRandr,
+}
+
+/// A head that is currently connected. Some formats describe rules rather than concrete
+/// configurations, so they can only be imported by resolving those rules against real heads.
+pub struct ConnectedHead {
+    pub identity: HeadIdentity,
+    pub configuration: Option<SavedConfiguration>,
+    pub modes: Vec<Mode>,
+    pub preferred_mode: Option<Mode>,
+}
+
+impl ConnectedHead {
+    /// The configuration to start from when a format enables this head: its current
+    /// configuration, or its preferred mode at the origin if it's disabled.
+    pub fn base_configuration(&self) -> SavedConfiguration {
+        self.configuration
+            .clone()
+            .unwrap_or_else(|| SavedConfiguration {
+                mode: self.preferred_mode.or_else(|| self.largest_mode()),
+                position: (0, 0),
+                transform: Transform::Normal,
+                scale: 1.0,
+                adaptive_sync: None,
+                dont_care: Default::default(),
+            })
+    }
+
+    /// The mode with the largest area, and then the highest refresh rate.
+    fn largest_mode(&self) -> Option<Mode> {
+        self.modes
+            .iter()
+            .max_by_key(|mode| (mode.size.0 as u64 * mode.size.1 as u64, mode.refresh))
+            .copied()
+    }
+
+    /// The mode of `size` with the refresh rate (in mHz) closest to `refresh`, or the highest
+    /// refresh rate if `refresh` is [`None`].
+    fn closest_mode(&self, size: (u32, u32), refresh: Option<u32>) -> Option<Mode> {
+        let same_size = self.modes.iter().filter(|mode| mode.size == size);
+        match refresh {
+            Some(refresh) => {
+                same_size.min_by_key(|mode| mode.refresh.unwrap_or_default().abs_diff(refresh))
+            }
+            None => same_size.max_by_key(|mode| mode.refresh),
+        }
+        .copied()
+    }
+}
+
+/// Finds the connected head for a head described by another tool, preferring the same monitor
+/// (by model and serial number) over the same connector. If none is connected, an identity is
+/// made up from the description, which only matches the real head by make, model and serial.
+fn resolve_head<'a>(
+    connected_heads: &'a [ConnectedHead],
+    connector: &str,
+    make: Option<&str>,
+    model: Option<&str>,
+    serial_number: Option<&str>,
+) -> (HeadIdentity, Option<&'a ConnectedHead>) {
+    let same_monitor = |head: &&ConnectedHead| {
+        model.is_some()
+            && head.identity.model.as_deref() == model
+            && head.identity.serial_number.as_deref() == serial_number
+    };
+    let same_connector = |head: &&ConnectedHead| {
+        head.identity.name == connector
+            && (model.is_none()
+                || head.identity.model.is_none()
+                || head.identity.model.as_deref() == model)
+    };
+    let connected_head = connected_heads
+        .iter()
+        .find(same_monitor)
+        .or_else(|| connected_heads.iter().find(same_connector));
+    if let Some(connected_head) = connected_head {
+        return (connected_head.identity.clone(), Some(connected_head));
+    }
+    let description = [make, model, serial_number]
+        .into_iter()
+        .flatten()
+        .chain([format!("({connector})").as_str()])
+        .collect::<Vec<_>>()
+        .join(" ");
+    let identity = HeadIdentity {
+        name: connector.to_string(),
+        description,
+        make: make.map(str::to_string),
+        model: model.map(str::to_string),
+        serial_number: serial_number.map(str::to_string),
+        edid: None,
+        physical_size: None,
+    };
+    (identity, None)
+}
+
+/// The mode of `size` at `refresh` (in mHz), snapped to a mode that `connected_head` advertises
+/// if there is one.
+fn resolve_mode(
+    connected_head: Option<&ConnectedHead>,
+    size: (u32, u32),
+    refresh: Option<u32>,
+) -> Mode {
+    connected_head
+        .and_then(|head| head.closest_mode(size, refresh))
+        .unwrap_or(Mode { size, refresh })
+}
+
+/// Moves every head in `heads` so the top-left-most position is the origin. Other tools allow
+/// negative positions, which wlroots doesn't.
+fn normalize_positions(heads: &mut [(HeadIdentity, (i32, i32), SavedConfiguration)]) {
+    let min_x = heads.iter().map(|(_, position, _)| position.0).min();
+    let min_y = heads.iter().map(|(_, position, _)| position.1).min();
+    let (Some(min_x), Some(min_y)) = (min_x, min_y) else {
+        return;
+    };
+    for (_, position, configuration) in heads.iter_mut() {
+        configuration.position = ((position.0 - min_x) as u32, (position.1 - min_y) as u32);
+    }
+}
+
+/// A token of a kanshi or sway config.
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    Newline,
+}
+
+/// Finds the head matching the output `criteria` of a kanshi or sway config, which is either a
+/// connector or a description of the form "make model serial". Returns [`None`] for a description
+/// that doesn't match a connected head, since it can't be split back into its parts.
+fn resolve_criteria<'a>(
+    connected_heads: &'a [ConnectedHead],
+    criteria: &str,
+) -> Option<(HeadIdentity, Option<&'a ConnectedHead>)> {
+    let connected_head = connected_heads
+        .iter()
+        .find(|head| head.identity.name == criteria || output_criteria(&head.identity) == criteria);
+    if let Some(connected_head) = connected_head {
+        return Some((connected_head.identity.clone(), Some(connected_head)));
+    }
+    if criteria.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(resolve_head(connected_heads, criteria, None, None, None))
+}
+
+/// Splits a kanshi or sway config into words (unquoting quoted ones), braces and newlines. Comments
+/// are skipped.
+fn tokenize(contents: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => tokens.push(Token::Newline),
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '"' => {
+                let mut word = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => word.extend(chars.next()),
+                        c => word.push(c),
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{}\"#".contains(*c)) {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    tokens
+}
+
+/// Parses a mode in a kanshi or sway config, e.g. "1920x1080" or "1920x1080@60Hz".
+fn parse_mode(value: &str) -> Option<((u32, u32), Option<f64>)> {
+    let (size, refresh) = match value.split_once('@') {
+        Some((size, refresh)) => (size, Some(refresh.trim_end_matches("Hz").parse().ok()?)),
+        None => (value, None),
+    };
+    let (width, height) = size.split_once('x')?;
+    Some(((width.parse().ok()?, height.parse().ok()?), refresh))
+}
+
+/// The output criteria for `identity` in kanshi or sway configs: its make, model and serial number
+/// if known (which is how they match descriptions), or otherwise its connector.
+fn output_criteria(identity: &HeadIdentity) -> String {
+    match (identity.make.as_deref(), identity.model.as_deref()) {
+        (Some(make), Some(model)) => format!(
+            "{make} {model} {}",
+            identity.serial_number.as_deref().unwrap_or("Unknown")
+        ),
+        _ => identity.name.clone(),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("Failed to read the file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse the YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("Failed to parse the JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to parse the XML: {0}")]
+    Xml(#[from] quick_xml::DeError),
+    #[error("Failed to parse the kanshi config: {0}")]
+    Kanshi(String),
+    #[error("The display \"{0}\" isn't connected, so it can't be identified")]
+    UnknownHead(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Failed to write the YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+/// Imports the layouts in the file (or directory, for formats that use one) at `path`, resolving
+/// any rules against `connected_heads`.
+pub fn import(
+    format: ImportFormat,
+    path: &Path,
+    connected_heads: &[ConnectedHead],
+) -> Result<Vec<Layout>, ImportError> {
+    match format {
+        ImportFormat::WayDisplays => Ok(vec![way_displays::import(
+            &std::fs::read_to_string(path)?,
+            connected_heads,
+        )?]),
+        ImportFormat::Gnome => gnome::import(&std::fs::read_to_string(path)?, connected_heads),
+        ImportFormat::Kde => kde::import(path, connected_heads),
+        ImportFormat::Autorandr => autorandr::import(path, connected_heads),
+        ImportFormat::Kanshi => kanshi::import(&std::fs::read_to_string(path)?, connected_heads),
+        ImportFormat::NwgDisplays => Ok(vec![nwg_displays::import(
+            &std::fs::read_to_string(path)?,
+            connected_heads,
+        )?]),
+    }
+}
+
+/// Exports `layout`, returning the contents of the file in `format`.
+pub fn export(format: ExportFormat, layout: &Layout) -> Result<String, ExportError> {
+    match format {
+        ExportFormat::WayDisplays => way_displays::export(layout),
+        ExportFormat::Xrandr => Ok(xrandr::export(layout)),
+        ExportFormat::Kanshi => Ok(kanshi::export(layout)),
+        ExportFormat::Hyprland => Ok(hyprland::export(layout)),
+        ExportFormat::WlrRandr => Ok(wlr_randr::export(layout)),
+    }
+}
+
+/// Makes up the identity of a head on `connector`, identified by `monitor` (its make, model and
+/// serial number) if given.
+#[cfg(test)]
+fn test_identity(connector: &str, monitor: Option<(&str, &str, &str)>) -> HeadIdentity {
+    let description = match monitor {
+        Some((make, model, serial_number)) => {
+            format!("{make} {model} {serial_number} ({connector})")
+        }
+        None => format!("Unknown ({connector})"),
+    };
+    HeadIdentity {
+        name: connector.to_string(),
+        description,
+        make: monitor.map(|(make, _, _)| make.to_string()),
+        model: monitor.map(|(_, model, _)| model.to_string()),
+        serial_number: monitor.map(|(_, _, serial_number)| serial_number.to_string()),
+        edid: None,
+        physical_size: None,
+    }
+}
+
+/// A layout named "desk" with a monitor (identified by make, model and serial number) next to a
+/// rotated laptop display that leaves its scale to the compositor, and a disabled head.
+#[cfg(test)]
+fn test_layout() -> Layout {
+    let monitor = SavedConfiguration {
+        mode: Some(Mode {
+            size: (3840, 2160),
+            refresh: Some(59997),
+        }),
+        position: (0, 0),
+        transform: Transform::Normal,
+        scale: 1.5,
+        adaptive_sync: Some(true),
+        dont_care: Default::default(),
+    };
+    let laptop = SavedConfiguration {
+        mode: Some(Mode {
+            size: (1920, 1200),
+            refresh: Some(60000),
+        }),
+        position: (2560, 0),
+        transform: Transform::_90,
+        scale: 1.0,
+        adaptive_sync: None,
+        dont_care: crate::serde::DontCare {
+            scale: true,
+            ..Default::default()
+        },
+    };
+    Layout {
+        name: Some("desk".into()),
+        ..Layout::new(
+            [
+                (
+                    test_identity("DP-1", Some(("Dell Inc.", "DELL U2720Q", "ABC123"))),
+                    Some(monitor),
+                ),
+                (test_identity("eDP-1", None), Some(laptop)),
+                (test_identity("HDMI-A-1", None), None),
+            ]
+            .into(),
+        )
+    }
+}
+
+/// The heads of `layout` as connected heads, offering the modes they use.
+#[cfg(test)]
+fn test_connected_heads(layout: &Layout) -> Vec<ConnectedHead> {
+    layout
+        .heads
+        .iter()
+        .map(|(identity, configuration)| ConnectedHead {
+            identity: identity.clone(),
+            configuration: configuration.clone(),
+            modes: configuration
+                .iter()
+                .filter_map(|configuration| configuration.mode)
+                .collect(),
+            preferred_mode: None,
+        })
+        .collect()
+}
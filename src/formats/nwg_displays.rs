@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use crate::serde::{DontCare, Layout, SavedConfiguration, Transform};
+
+use super::{
+    normalize_positions, parse_mode, resolve_criteria, resolve_mode, tokenize, ConnectedHead,
+    ImportError, Token,
+};
+
+/// The settings of an output in a file written by nwg-displays. Settings that aren't given are
+/// [`None`].
+#[derive(Default)]
+struct Output {
+    enabled: Option<bool>,
+    /// The size and refresh rate (in Hz) of the mode.
+    mode: Option<((u32, u32), Option<f64>)>,
+    position: Option<(i32, i32)>,
+    scale: Option<f64>,
+    transform: Option<Transform>,
+    adaptive_sync: Option<bool>,
+}
+
+/// Converts the outputs file written by nwg-displays in `contents` into a layout. This is either a
+/// sway config (usually `~/.config/sway/outputs`) or Hyprland `monitor=` lines (usually
+/// `~/.config/hypr/monitors.conf`). Outputs are matched against `connected_heads` by connector or
+/// by description.
+pub fn import(contents: &str, connected_heads: &[ConnectedHead]) -> Result<Layout, ImportError> {
+    let is_hyprland = contents
+        .lines()
+        .any(|line| line.trim_start().starts_with("monitor"));
+    let outputs = if is_hyprland {
+        parse_hyprland(contents)
+    } else {
+        parse_sway(contents)
+    };
+
+    let mut enabled = vec![];
+    let mut heads = HashMap::new();
+    for (criteria, output) in outputs {
+        if criteria == "*" {
+            continue;
+        }
+        let Some((identity, connected_head)) = resolve_criteria(connected_heads, &criteria) else {
+            return Err(ImportError::UnknownHead(criteria));
+        };
+        if output.enabled == Some(false) {
+            heads.insert(identity, None);
+            continue;
+        }
+        let configuration = SavedConfiguration {
+            mode: output.mode.map(|(size, refresh)| {
+                resolve_mode(
+                    connected_head,
+                    size,
+                    refresh.map(|refresh| (refresh * 1000.0).round() as u32),
+                )
+            }),
+            position: (0, 0),
+            transform: output.transform.unwrap_or(Transform::Normal),
+            scale: output.scale.unwrap_or(1.0),
+            adaptive_sync: output.adaptive_sync,
+            dont_care: DontCare {
+                position: output.position.is_none(),
+                transform: output.transform.is_none(),
+                scale: output.scale.is_none(),
+            },
+        };
+        match output.position {
+            Some(position) => enabled.push((identity, position, configuration)),
+            None => {
+                heads.insert(identity, Some(configuration));
+            }
+        }
+    }
+    normalize_positions(&mut enabled);
+    heads.extend(
+        enabled
+            .into_iter()
+            .map(|(identity, _, configuration)| (identity, Some(configuration))),
+    );
+    Ok(Layout::new(heads))
+}
+
+/// Parses the `output` commands of a sway config, keyed by their criteria. Settings may be given
+/// in a block or on the same line, and other commands and settings (e.g., `scale_filter`) are
+/// ignored.
+fn parse_sway(contents: &str) -> Vec<(String, Output)> {
+    let mut outputs: Vec<(String, Output)> = vec![];
+    let mut words = vec![];
+    let mut in_block = false;
+    for token in tokenize(contents) {
+        match token {
+            Token::Word(word) => {
+                words.push(word);
+                continue;
+            }
+            Token::Open => {
+                if words.first().map(String::as_str) == Some("output") && words.len() > 1 {
+                    outputs.push((words[1].clone(), Output::default()));
+                    in_block = true;
+                }
+                words.clear();
+                continue;
+            }
+            Token::Newline | Token::Close => {}
+        }
+        if in_block {
+            if let Some((_, output)) = outputs.last_mut() {
+                parse_sway_settings(&words, output);
+            }
+        } else if words.first().map(String::as_str) == Some("output") && words.len() > 1 {
+            let mut output = Output::default();
+            parse_sway_settings(&words[2..], &mut output);
+            outputs.push((words[1].clone(), output));
+        }
+        words.clear();
+        if let Token::Close = token {
+            in_block = false;
+        }
+    }
+    outputs
+}
+
+/// Parses the settings of a sway `output` (e.g., `mode 1920x1080 pos 0 0`) into `output`.
+fn parse_sway_settings(words: &[String], output: &mut Output) {
+    let mut words = words.iter().map(String::as_str).peekable();
+    while let Some(setting) = words.next() {
+        match setting {
+            "enable" => output.enabled = Some(true),
+            "disable" => output.enabled = Some(false),
+            "mode" | "resolution" | "res" => {
+                words.next_if_eq(&"--custom");
+                output.mode = words.next().and_then(parse_mode);
+            }
+            "pos" | "position" => {
+                let (x, y) = (words.next(), words.next());
+                output.position = x
+                    .and_then(|x| x.parse().ok())
+                    .zip(y.and_then(|y| y.parse().ok()));
+            }
+            "scale" => output.scale = words.next().and_then(|scale| scale.parse().ok()),
+            "transform" => {
+                output.transform = words.next().and_then(Transform::from_name);
+                // Transforms relative to the current one (e.g., `90 clockwise`) can't be imported.
+                if words
+                    .next_if(|word| matches!(*word, "clockwise" | "anticlockwise"))
+                    .is_some()
+                {
+                    output.transform = None;
+                }
+            }
+            "adaptive_sync" => {
+                output.adaptive_sync = words
+                    .next()
+                    .map(|adaptive_sync| matches!(adaptive_sync, "on" | "enable" | "yes"));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parses Hyprland `monitor=` lines, keyed by their criteria. Descriptions (`desc:...`) are
+/// unprefixed. Settings Hyprland picks itself (e.g., `preferred` or `auto`) are left as [`None`].
+fn parse_hyprland(contents: &str) -> Vec<(String, Output)> {
+    let mut outputs = vec![];
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim() != "monitor" {
+            continue;
+        }
+        let fields = value.split(',').map(str::trim).collect::<Vec<_>>();
+        let name = fields[0];
+        let criteria = name.strip_prefix("desc:").unwrap_or(name).to_string();
+        let mut output = Output::default();
+        match fields.get(1) {
+            None => continue,
+            Some(&"disable") => {
+                output.enabled = Some(false);
+                outputs.push((criteria, output));
+                continue;
+            }
+            Some(mode) => output.mode = parse_mode(mode),
+        }
+        output.position = fields.get(2).and_then(|position| {
+            let (x, y) = position.split_once('x')?;
+            Some((x.parse().ok()?, y.parse().ok()?))
+        });
+        output.scale = fields.get(3).and_then(|scale| scale.parse().ok());
+        for pair in fields.get(4..).unwrap_or_default().chunks(2) {
+            match pair {
+                ["transform", transform] => {
+                    output.transform = transform.parse::<u32>().ok().and_then(hyprland_transform);
+                }
+                ["vrr", vrr] => output.adaptive_sync = Some(*vrr != "0"),
+                _ => {}
+            }
+        }
+        outputs.push((criteria, output));
+    }
+    outputs
+}
+
+/// Converts a Hyprland transform, which is numbered like wl_output's.
+fn hyprland_transform(transform: u32) -> Option<Transform> {
+    Some(match transform {
+        0 => Transform::Normal,
+        1 => Transform::_90,
+        2 => Transform::_180,
+        3 => Transform::_270,
+        4 => Transform::Flipped,
+        5 => Transform::Flipped90,
+        6 => Transform::Flipped180,
+        7 => Transform::Flipped270,
+        _ => return None,
+    })
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    complete::{HeadIdentity, Mode},
+    scale::logical_size,
+    serde::{Layout, SavedConfiguration, Transform},
+};
+
+use super::{resolve_mode, ConnectedHead, ExportError, ImportError};
+
+/// The parts of a way-displays `cfg.yaml` that describe the layout of heads. Other settings (e.g.,
+/// `AUTO_SCALE` or `CALLBACK_CMD`) are ignored.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct Config {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arrange: Option<Arrange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    align: Option<Align>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    order: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    scale: Vec<ScaleEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mode: Vec<ModeEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transform: Vec<TransformEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    vrr_off: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    disabled: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum Arrange {
+    #[default]
+    Row,
+    Column,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum Align {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct ScaleEntry {
+    name_desc: String,
+    scale: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct ModeEntry {
+    name_desc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hz: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct TransformEntry {
+    name_desc: String,
+    transform: String,
+}
+
+/// Returns whether the way-displays `NAME_DESC` pattern refers to `identity`. Like way-displays,
+/// this matches the name exactly or any part of the description. Regex patterns (starting with
+/// `!`) aren't supported.
+fn matches(name_desc: &str, identity: &HeadIdentity) -> bool {
+    !name_desc.starts_with('!')
+        && (identity.name == name_desc || identity.description.contains(name_desc))
+}
+
+/// Resolves the way-displays config in `contents` against `connected_heads`, producing the layout
+/// way-displays would arrange them in.
+pub fn import(contents: &str, connected_heads: &[ConnectedHead]) -> Result<Layout, ImportError> {
+    let config: Config = serde_yaml::from_str(contents)?;
+    for pattern in config
+        .order
+        .iter()
+        .chain(&config.vrr_off)
+        .chain(&config.disabled)
+    {
+        if pattern.starts_with('!') {
+            eprintln!("Warning: ignoring the regex pattern \"{pattern}\", which isn't supported");
+        }
+    }
+
+    let mut heads = HashMap::new();
+    let mut enabled = vec![];
+    for head in connected_heads {
+        let identity = &head.identity;
+        if config
+            .disabled
+            .iter()
+            .any(|pattern| matches(pattern, identity))
+        {
+            heads.insert(identity.clone(), None);
+            continue;
+        }
+        let mut configuration = head.base_configuration();
+        if let Some(entry) = config
+            .mode
+            .iter()
+            .find(|entry| matches(&entry.name_desc, identity))
+        {
+            configuration.mode = mode_for_entry(entry, head).or(configuration.mode);
+        }
+        if let Some(entry) = config
+            .scale
+            .iter()
+            .find(|entry| matches(&entry.name_desc, identity))
+        {
+            configuration.scale = entry.scale;
+        }
+        if let Some(entry) = config
+            .transform
+            .iter()
+            .find(|entry| matches(&entry.name_desc, identity))
+        {
+            match Transform::from_name(&entry.transform) {
+                Some(transform) => configuration.transform = transform,
+                None => eprintln!(
+                    "Warning: ignoring unknown transform \"{}\" for \"{}\"",
+                    entry.transform, entry.name_desc
+                ),
+            }
+        }
+        if config
+            .vrr_off
+            .iter()
+            .any(|pattern| matches(pattern, identity))
+        {
+            configuration.adaptive_sync = Some(false);
+        }
+        enabled.push((identity.clone(), configuration));
+    }
+
+    // Heads in ORDER come first (in that order), followed by the rest.
+    enabled.sort_by_key(|(identity, _)| {
+        (
+            config
+                .order
+                .iter()
+                .position(|pattern| matches(pattern, identity))
+                .unwrap_or(usize::MAX),
+            identity.name.clone(),
+        )
+    });
+    arrange(
+        &mut enabled,
+        config.arrange.unwrap_or_default(),
+        config.align.unwrap_or_default(),
+    );
+    heads.extend(
+        enabled
+            .into_iter()
+            .map(|(identity, configuration)| (identity, Some(configuration))),
+    );
+    Ok(Layout::new(heads))
+}
+
+/// Finds the mode of `head` that `entry` asks for.
+fn mode_for_entry(entry: &ModeEntry, head: &ConnectedHead) -> Option<Mode> {
+    if entry.max == Some(true) {
+        return head.largest_mode();
+    }
+    let size = (entry.width?, entry.height?);
+    let refresh = entry.hz.map(|hz| (hz * 1000.0).round() as u32);
+    Some(resolve_mode(Some(head), size, refresh))
+}
+
+/// Positions `heads` one after another (in order) like way-displays does.
+fn arrange(heads: &mut [(HeadIdentity, SavedConfiguration)], arrange: Arrange, align: Align) {
+    let sizes = heads
+        .iter()
+        .map(|(_, configuration)| {
+            let (width, height) = logical_size(configuration).unwrap_or_default();
+            (width.round() as u32, height.round() as u32)
+        })
+        .collect::<Vec<_>>();
+    let max_width = sizes.iter().map(|size| size.0).max().unwrap_or_default();
+    let max_height = sizes.iter().map(|size| size.1).max().unwrap_or_default();
+    let mut offset = 0;
+    for ((_, configuration), (width, height)) in heads.iter_mut().zip(sizes) {
+        configuration.position = match arrange {
+            Arrange::Row => {
+                let y = match align {
+                    Align::Middle => (max_height - height) / 2,
+                    Align::Bottom => max_height - height,
+                    _ => 0,
+                };
+                (offset, y)
+            }
+            Arrange::Column => {
+                let x = match align {
+                    Align::Middle => (max_width - width) / 2,
+                    Align::Right => max_width - width,
+                    _ => 0,
+                };
+                (x, offset)
+            }
+        };
+        offset += match arrange {
+            Arrange::Row => width,
+            Arrange::Column => height,
+        };
+    }
+}
+
+/// Converts `layout` into a way-displays config. way-displays places heads next to each other in a
+/// row or column, so layouts with gaps, overlaps, or mixed alignments are approximated.
+pub fn export(layout: &Layout) -> Result<String, ExportError> {
+    let mut config = Config::default();
+    let mut enabled = vec![];
+    let mut identities = layout.heads.iter().collect::<Vec<_>>();
+    identities.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+    for (identity, configuration) in identities {
+        let name_desc = identity.description.clone();
+        let Some(configuration) = configuration else {
+            config.disabled.push(name_desc);
+            continue;
+        };
+        if let Some(mode) = configuration.mode {
+            config.mode.push(ModeEntry {
+                name_desc: name_desc.clone(),
+                width: Some(mode.size.0),
+                height: Some(mode.size.1),
+                hz: mode.refresh.map(|refresh| refresh as f64 / 1000.0),
+                max: None,
+            });
+        }
+        config.scale.push(ScaleEntry {
+            name_desc: name_desc.clone(),
+            scale: configuration.scale,
+        });
+        if configuration.transform != Transform::Normal {
+            config.transform.push(TransformEntry {
+                name_desc: name_desc.clone(),
+                transform: configuration.transform.name().to_uppercase(),
+            });
+        }
+        if configuration.adaptive_sync == Some(false) {
+            config.vrr_off.push(name_desc.clone());
+        }
+        let (width, height) = logical_size(configuration).unwrap_or_default();
+        enabled.push(PlacedHead {
+            name_desc,
+            position: configuration.position,
+            size: (width, height),
+        });
+    }
+
+    let (arrange, align) = detect_arrangement(&mut enabled).unwrap_or_else(|| {
+        eprintln!(
+            "Warning: the heads aren't in a simple row or column, so way-displays will arrange them in a row"
+        );
+        enabled.sort_by_key(|head| head.position);
+        (Arrange::Row, Align::Top)
+    });
+    config.arrange = Some(arrange);
+    config.align = Some(align);
+    config.order = enabled.into_iter().map(|head| head.name_desc).collect();
+    Ok(serde_yaml::to_string(&config)?)
+}
+
+/// An enabled head being exported.
+struct PlacedHead {
+    name_desc: String,
+    position: (u32, u32),
+    /// The logical size of the head.
+    size: (f64, f64),
+}
+
+/// Figures out how way-displays would need to arrange `heads` to reproduce their positions, and
+/// sorts them into that order. Returns `None` if they can't be reproduced.
+fn detect_arrangement(heads: &mut [PlacedHead]) -> Option<(Arrange, Align)> {
+    let close = |a: f64, b: f64| (a - b).abs() < 1.0;
+    // Returns whether each head starts where the previous one ends along the given axis.
+    let is_adjacent = |heads: &[PlacedHead], row: bool| {
+        heads.windows(2).all(|pair| {
+            let (previous, head) = (&pair[0], &pair[1]);
+            if row {
+                close(
+                    previous.position.0 as f64 + previous.size.0,
+                    head.position.0 as f64,
+                )
+            } else {
+                close(
+                    previous.position.1 as f64 + previous.size.1,
+                    head.position.1 as f64,
+                )
+            }
+        })
+    };
+    // Returns which alignment (start, middle, end) all heads share along the cross axis.
+    let alignment = |heads: &[PlacedHead], row: bool| {
+        let starts = heads
+            .iter()
+            .map(|head| {
+                if row {
+                    (head.position.1 as f64, head.size.1)
+                } else {
+                    (head.position.0 as f64, head.size.0)
+                }
+            })
+            .collect::<Vec<_>>();
+        let all_close = |value: &dyn Fn(&(f64, f64)) -> f64| {
+            starts
+                .windows(2)
+                .all(|pair| close(value(&pair[0]), value(&pair[1])))
+        };
+        if all_close(&|(start, _)| *start) {
+            Some(0)
+        } else if all_close(&|(start, size)| start + size / 2.0) {
+            Some(1)
+        } else if all_close(&|(start, size)| start + size) {
+            Some(2)
+        } else {
+            None
+        }
+    };
+
+    heads.sort_by_key(|head| head.position);
+    if is_adjacent(heads, true) {
+        if let Some(alignment) = alignment(heads, true) {
+            return Some((
+                Arrange::Row,
+                [Align::Top, Align::Middle, Align::Bottom][alignment],
+            ));
+        }
+    }
+    heads.sort_by_key(|head| (head.position.1, head.position.0));
+    if is_adjacent(heads, false) {
+        if let Some(alignment) = alignment(heads, false) {
+            return Some((
+                Arrange::Column,
+                [Align::Left, Align::Middle, Align::Right][alignment],
+            ));
+        }
+    }
+    None
+}
@@ -0,0 +1,89 @@
+use crate::serde::Layout;
+
+/// Converts `layout` into a single wlr-randr command that reproduces it. Properties the layout
+/// doesn't care about are left out.
+pub fn export(layout: &Layout) -> String {
+    let mut heads = layout.heads.iter().collect::<Vec<_>>();
+    heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let mut command = String::from("wlr-randr");
+    for (identity, configuration) in heads {
+        command.push_str(&format!(" --output {}", identity.name));
+        let Some(configuration) = configuration else {
+            command.push_str(" --off");
+            continue;
+        };
+        command.push_str(" --on");
+        if let Some(mode) = configuration.mode {
+            command.push_str(&format!(" --mode {}x{}", mode.size.0, mode.size.1));
+            if let Some(refresh) = mode.refresh {
+                command.push_str(&format!("@{}.{:03}Hz", refresh / 1000, refresh % 1000));
+            }
+        }
+        if !configuration.dont_care.position {
+            command.push_str(&format!(
+                " --pos {},{}",
+                configuration.position.0, configuration.position.1
+            ));
+        }
+        if !configuration.dont_care.transform {
+            command.push_str(&format!(" --transform {}", configuration.transform.name()));
+        }
+        if !configuration.dont_care.scale {
+            command.push_str(&format!(" --scale {}", configuration.scale));
+        }
+        if let Some(adaptive_sync) = configuration.adaptive_sync {
+            command.push_str(if adaptive_sync {
+                " --adaptive-sync enabled"
+            } else {
+                " --adaptive-sync disabled"
+            });
+        }
+    }
+    command.push('\n');
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{parse_mode, test_layout};
+
+    #[test]
+    fn exports_a_command() {
+        assert_eq!(
+            export(&test_layout()),
+            "wlr-randr --output DP-1 --on --mode 3840x2160@59.997Hz --pos 0,0 --transform normal \
+             --scale 1.5 --adaptive-sync enabled --output HDMI-A-1 --off --output eDP-1 --on \
+             --mode 1920x1200@60.000Hz --pos 2560,0 --transform 90\n"
+        );
+    }
+
+    #[test]
+    fn exported_modes_parse_back() {
+        let layout = test_layout();
+        let command = export(&layout);
+        let words = command.split_whitespace().collect::<Vec<_>>();
+        let mut parsed = words
+            .windows(2)
+            .filter(|pair| pair[0] == "--mode")
+            .map(|pair| {
+                let (size, refresh) = parse_mode(pair[1]).unwrap();
+                (
+                    size,
+                    refresh.map(|refresh| (refresh * 1000.0).round() as u32),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut expected = layout
+            .heads
+            .values()
+            .flatten()
+            .filter_map(|configuration| configuration.mode)
+            .map(|mode| (mode.size, mode.refresh))
+            .collect::<Vec<_>>();
+        parsed.sort();
+        expected.sort();
+        assert_eq!(parsed, expected);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::serde::{Layout, Transform};
+
+/// Converts `layout` into a shell script that runs xrandr to reproduce it in an X11 session.
+/// Wayland positions are in logical pixels, so scaled heads are given the inverse xrandr scale to
+/// keep the same logical size.
+pub fn export(layout: &Layout) -> String {
+    let mut heads = layout.heads.iter().collect::<Vec<_>>();
+    heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let mut script = String::from(
+        "#!/bin/sh\n\
+         # Generated by wl-distore. X11 may name outputs differently from Wayland (e.g. HDMI-1\n\
+         # instead of HDMI-A-1), so check `xrandr --query` if an output isn't found.\n\
+         xrandr",
+    );
+    for (identity, configuration) in heads {
+        script.push_str(&format!(" \\\n  --output {}", identity.name));
+        let Some(configuration) = configuration else {
+            script.push_str(" --off");
+            continue;
+        };
+        match configuration.mode {
+            Some(mode) => {
+                script.push_str(&format!(" --mode {}x{}", mode.size.0, mode.size.1));
+                if let Some(refresh) = mode.refresh {
+                    script.push_str(&format!(" --rate {:.2}", refresh as f64 / 1000.0));
+                }
+            }
+            None => script.push_str(" --auto"),
+        }
+        script.push_str(&format!(
+            " --pos {}x{}",
+            configuration.position.0, configuration.position.1
+        ));
+        let (rotate, reflect) = match configuration.transform {
+            Transform::Normal => ("normal", "normal"),
+            Transform::_90 => ("left", "normal"),
+            Transform::_180 => ("inverted", "normal"),
+            Transform::_270 => ("right", "normal"),
+            Transform::Flipped => ("normal", "x"),
+            Transform::Flipped90 => ("left", "x"),
+            Transform::Flipped180 => ("inverted", "x"),
+            Transform::Flipped270 => ("right", "x"),
+        };
+        script.push_str(&format!(" --rotate {rotate} --reflect {reflect}"));
+        let scale = 1.0 / configuration.scale;
+        script.push_str(&format!(" --scale {scale}x{scale}"));
+    }
+    script.push('\n');
+    script
+}
@@ -0,0 +1,83 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, error};
+
+use crate::{persist::write_atomically, state::unix_now};
+
+/// Returns the directory holding the snapshots of the layouts file at `layouts_path`, e.g.
+/// `layouts.history` for `layouts.json`.
+pub fn history_dir(layouts_path: &Path) -> PathBuf {
+    layouts_path.with_extension("history")
+}
+
+/// Returns the snapshots of the layouts file at `layouts_path`, as the time they were taken (in
+/// seconds since the Unix epoch) and their paths, oldest first.
+pub fn snapshots(layouts_path: &Path) -> Result<Vec<(u64, PathBuf)>, std::io::Error> {
+    let entries = match std::fs::read_dir(history_dir(layouts_path)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut snapshots = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let Some(timestamp) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        else {
+            continue;
+        };
+        snapshots.push((timestamp, path));
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Records `contents` as a snapshot of the layouts file at `layouts_path`, then deletes the oldest
+/// snapshots so at most `retention` are kept. Nothing is recorded if `retention` is 0, or if
+/// `contents` are the same as the latest snapshot. Errors are logged, since a missing snapshot
+/// shouldn't stop the layouts from being saved.
+pub fn record(layouts_path: &Path, contents: &[u8], retention: usize) {
+    if retention == 0 {
+        return;
+    }
+    if let Err(err) = record_and_prune(layouts_path, contents, retention) {
+        error!(
+            "Failed to record a snapshot of {}: {err}",
+            layouts_path.display()
+        );
+    }
+}
+
+fn record_and_prune(
+    layouts_path: &Path,
+    contents: &[u8],
+    retention: usize,
+) -> Result<(), std::io::Error> {
+    let mut snapshots = snapshots(layouts_path)?;
+    let mut timestamp = unix_now();
+    if let Some((latest_timestamp, latest)) = snapshots.last() {
+        if std::fs::read(latest).is_ok_and(|latest| latest == contents) {
+            return Ok(());
+        }
+        // Several saves in the same second (or a clock that went backwards) mustn't replace
+        // earlier snapshots.
+        timestamp = timestamp.max(latest_timestamp + 1);
+    }
+    let path = history_dir(layouts_path).join(format!("{timestamp}.json"));
+    write_atomically(&path, contents)?;
+    snapshots.push((timestamp, path));
+    let excess = snapshots.len().saturating_sub(retention);
+    for (_, path) in snapshots.drain(..excess) {
+        debug!("Deleting the old snapshot {}", path.display());
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
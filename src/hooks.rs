@@ -0,0 +1,107 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::{config::CommandLine, ipc::Event, serde::Layout};
+
+/// The prefix of the environment variables that tell commands what happened.
+pub const HOOK_ENV_VAR_PREFIX: &str = "WL_DISTORE_HOOK_";
+
+/// Commands to run when things happen in the daemon, e.g. to tell bars or notification daemons.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct Hooks {
+    /// Runs after a layout is saved.
+    pub on_save: Option<CommandLine>,
+    /// Runs after a layout (or the safe-mode fallback) is applied. Also set by `apply_command`.
+    pub on_apply_success: Option<CommandLine>,
+    /// Runs after the compositor rejects or cancels applying a layout. Also set by
+    /// `apply_failed_command`.
+    pub on_apply_failed: Option<CommandLine>,
+    /// Runs after a head is connected.
+    pub on_head_added: Option<CommandLine>,
+    /// Runs after a head is disconnected.
+    pub on_head_removed: Option<CommandLine>,
+}
+
+impl Hooks {
+    /// Returns the hook to run for `event`, if there is one.
+    pub fn for_event(&self, event: &Event) -> Option<&CommandLine> {
+        match event {
+            Event::Saved { .. } => self.on_save.as_ref(),
+            Event::Applied { .. } => self.on_apply_success.as_ref(),
+            Event::ApplyFailed { .. } => self.on_apply_failed.as_ref(),
+            Event::HeadAdded { .. } => self.on_head_added.as_ref(),
+            Event::HeadRemoved { .. } => self.on_head_removed.as_ref(),
+            Event::HeadChanged { .. }
+            | Event::ProfileSwitched { .. }
+            | Event::Paused
+            | Event::Resumed => None,
+        }
+    }
+}
+
+/// What hooks and other commands are told about the event that ran them.
+pub struct HookContext {
+    /// The name of the event, as sent to subscribers (e.g. `applied`).
+    event: &'static str,
+    /// The layout the event is about, or the current configuration if it isn't about a layout.
+    layout: Layout,
+    /// The head that was added or removed.
+    head: Option<String>,
+}
+
+impl HookContext {
+    pub fn new(event: &'static str, layout: Layout, head: Option<String>) -> Self {
+        Self {
+            event,
+            layout,
+            head,
+        }
+    }
+
+    /// Passes the context to `command` through environment variables: `WL_DISTORE_HOOK_EVENT`,
+    /// `WL_DISTORE_HOOK_LAYOUT_NAME` (if the layout has a name), `WL_DISTORE_HOOK_HEADS` (the
+    /// names of the heads in the layout, separated by spaces) and `WL_DISTORE_HOOK_HEAD` (for head
+    /// events). Config options aren't read from these, so commands can run `wl-distore`.
+    pub fn set_env(&self, command: &mut Command) {
+        command.env("WL_DISTORE_HOOK_EVENT", self.event);
+        match &self.layout.name {
+            Some(name) => command.env("WL_DISTORE_HOOK_LAYOUT_NAME", name),
+            None => command.env_remove("WL_DISTORE_HOOK_LAYOUT_NAME"),
+        };
+        let mut heads = self
+            .layout
+            .heads
+            .keys()
+            .map(|identity| identity.name.as_str())
+            .collect::<Vec<_>>();
+        heads.sort();
+        command.env("WL_DISTORE_HOOK_HEADS", heads.join(" "));
+        match &self.head {
+            Some(head) => command.env("WL_DISTORE_HOOK_HEAD", head),
+            None => command.env_remove("WL_DISTORE_HOOK_HEAD"),
+        };
+    }
+
+    /// Returns the layout serialized as JSON, which is written to the command's stdin.
+    pub fn stdin(&self) -> String {
+        self.layout
+            .to_json()
+            .expect("Serializing a layout should not fail")
+    }
+}
+
+/// Returns the name of `event`, as sent to subscribers.
+pub fn event_name(event: &Event) -> &'static str {
+    match event {
+        Event::HeadAdded { .. } => "head-added",
+        Event::HeadRemoved { .. } => "head-removed",
+        Event::HeadChanged { .. } => "head-changed",
+        Event::Saved { .. } => "saved",
+        Event::Applied { .. } => "applied",
+        Event::ApplyFailed { .. } => "apply-failed",
+        Event::ProfileSwitched { .. } => "profile-switched",
+        Event::Paused => "paused",
+        Event::Resumed => "resumed",
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    complete::HeadIdentity,
+    serde::{Layout, LayoutData, SavedConfiguration},
+};
+
+/// The config file written by the wizard.
+#[derive(Serialize)]
+struct StarterConfig {
+    layouts: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    apply_command: Option<String>,
+}
+
+/// Walks the user through setting up wl-distore: saving `current_layout` as a named layout,
+/// writing a config file to `config_path`, and printing a systemd unit.
+pub fn run_wizard(
+    config_path: &Path,
+    default_layouts: &Path,
+    current_layout: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+) -> Result<(), std::io::Error> {
+    println!("Welcome to wl-distore! Detected heads:");
+    let mut heads = current_layout.iter().collect::<Vec<_>>();
+    heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+    for (identity, configuration) in heads {
+        match configuration {
+            None => println!(
+                "  {} \"{}\" (disabled)",
+                identity.name, identity.description
+            ),
+            Some(configuration) => println!(
+                "  {} \"{}\" at {},{} scale {}",
+                identity.name,
+                identity.description,
+                configuration.position.0,
+                configuration.position.1,
+                configuration.scale
+            ),
+        }
+    }
+    println!();
+
+    let layouts = prompt(&format!(
+        "Where should layouts be saved? [{}] ",
+        default_layouts.display()
+    ))?;
+    let layouts = if layouts.is_empty() {
+        default_layouts.to_path_buf()
+    } else {
+        match expanduser::expanduser(&layouts) {
+            Ok(path) => path,
+            Err(_) => PathBuf::from(layouts),
+        }
+    };
+
+    let apply_command = prompt("Command to run after applying a layout (leave empty for none): ")?;
+    let apply_command = (!apply_command.is_empty()).then_some(apply_command);
+
+    let name = prompt("Save the current configuration as a layout named (leave empty to skip): ")?;
+    if !name.is_empty() {
+        let mut layout_data = LayoutData::load(&layouts)?;
+        let layout = Layout {
+            name: Some(name.clone()),
+            ..Layout::new(current_layout)
+        };
+        match layout_data.find_layout_by_name(&name) {
+            Some(index) => layout_data.layouts[index] = layout,
+            None => layout_data.layouts.push(layout),
+        }
+        layout_data.save(&layouts)?;
+        println!("Saved layout \"{name}\" to {}", layouts.display());
+    }
+
+    if config_path.exists() {
+        println!(
+            "Not writing a config file since {} already exists",
+            config_path.display()
+        );
+    } else {
+        let config = StarterConfig {
+            layouts: layouts.display().to_string(),
+            apply_command,
+        };
+        if let Some(parent) = config_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(
+            config_path,
+            toml::to_string(&config).expect("The config can be serialized"),
+        )?;
+        println!("Wrote config file to {}", config_path.display());
+    }
+
+    let exe = std::env::current_exe()
+        .map(|exe| exe.display().to_string())
+        .unwrap_or_else(|_| "%h/.cargo/bin/wl-distore".into());
+    println!(
+        r#"
+To start wl-distore automatically with systemd, save the following to
+~/.config/systemd/user/wl-distore.service and run
+`systemctl --user enable --now wl-distore.service`:
+
+[Unit]
+Description = "wl-distore"
+PartOf=graphical-session.target
+
+[Service]
+Type=simple
+Environment=RUST_LOG=info
+ExecStart={exe}
+
+[Install]
+WantedBy=graphical-session.target"#
+    );
+    Ok(())
+}
+
+/// Prints `message` and reads a trimmed line from stdin.
+fn prompt(message: &str) -> Result<String, std::io::Error> {
+    print!("{message}");
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
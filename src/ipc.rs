@@ -0,0 +1,319 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{complete::HeadIdentity, serde::SavedConfiguration};
+
+/// A request sent from a CLI invocation to the running daemon.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Stats,
+    /// Switch to a profile, or to the next profile of the current heads if [`None`].
+    Switch {
+        profile: Option<String>,
+    },
+    /// Keep the connection open and stream [`Event`]s over it.
+    Subscribe,
+    /// Stop saving changes to the configuration until [`Request::Resume`].
+    Pause,
+    Resume,
+    Status,
+    /// Apply the layout matching the current heads again.
+    Reapply,
+    /// Save the current configuration into the layout matching the current heads.
+    Save,
+    /// Exit cleanly, e.g. to be replaced by another instance.
+    Quit,
+}
+
+/// The daemon's response to a [`Request`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Stats(Stats),
+    Switched(Switched),
+    Status(Status),
+    /// The request succeeded, with a message describing what the daemon did.
+    Ok(String),
+    Error(String),
+}
+
+/// The result of switching profiles.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Switched {
+    pub profile: String,
+    /// The index of the profile's layout for the current heads.
+    pub layout: usize,
+    /// Whether the profile had no layout for the current heads, so the current configuration was
+    /// saved as one.
+    pub created: bool,
+}
+
+/// Counters describing what the daemon has done since it started.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Stats {
+    /// The number of times layouts were saved.
+    pub saves: u64,
+    /// The number of output configurations that were applied.
+    pub applies: u64,
+    /// The number of output configurations that failed or were cancelled.
+    pub apply_failures: u64,
+    /// The number of layout matches that were not exact (i.e., heads had to be remapped).
+    pub fuzzy_matches: u64,
+    /// The number of heads that were connected.
+    pub heads_added: u64,
+    /// The number of heads that were disconnected.
+    pub heads_removed: u64,
+    /// The number of times a head was connected again after being disconnected.
+    pub reconnects: u64,
+    /// The number of seconds since the daemon started.
+    pub uptime_secs: u64,
+}
+
+/// The state of the daemon.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// Whether saving changes is paused (see `wl-distore ctl pause`).
+    pub paused: bool,
+    /// The profile whose layouts are preferred, or [`None`] if none was switched to.
+    pub active_profile: Option<String>,
+    /// The index of the layout matching the current heads.
+    pub layout: Option<usize>,
+}
+
+/// Something that happened in the daemon, streamed to subscribers as a line of JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// A head was connected. New subscribers are sent one of these for each connected head.
+    HeadAdded {
+        head: HeadIdentity,
+        /// The configuration of the head, or [`None`] if it is disabled.
+        configuration: Option<SavedConfiguration>,
+    },
+    HeadRemoved {
+        head: HeadIdentity,
+    },
+    /// The configuration of a head changed.
+    HeadChanged {
+        head: HeadIdentity,
+        configuration: Option<SavedConfiguration>,
+    },
+    /// A layout was saved.
+    Saved {
+        layout: usize,
+        name: Option<String>,
+        profile: String,
+    },
+    /// A layout was applied. `layout` is [`None`] for the safe-mode fallback.
+    Applied {
+        layout: Option<usize>,
+        name: Option<String>,
+    },
+    /// Applying a layout failed, or was cancelled by the compositor.
+    ApplyFailed {
+        layout: Option<usize>,
+        name: Option<String>,
+        cancelled: bool,
+    },
+    /// The active profile changed.
+    ProfileSwitched {
+        profile: String,
+    },
+    /// Saving changes was paused.
+    Paused,
+    /// Saving changes was resumed.
+    Resumed,
+}
+
+#[derive(Debug, Error)]
+pub enum IpcError {
+    #[error("Could not connect to the wl-distore daemon at \"{0}\" (is it running?): {1}")]
+    CouldNotConnect(PathBuf, std::io::Error),
+    #[error("Failed to communicate with the wl-distore daemon: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse a message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error("The wl-distore daemon didn't exit in time")]
+    StillRunning,
+}
+
+/// How long to wait for the running daemon to exit when replacing it.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The path of the socket the daemon listens on. This is scoped to the Wayland display so each
+/// session gets its own daemon.
+pub fn socket_path() -> PathBuf {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let display = std::env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".into());
+    runtime_dir.join(format!("wl-distore-{display}.sock"))
+}
+
+/// Sends `request` to the running daemon and waits for its response.
+pub fn send_request(request: &Request) -> Result<Response, IpcError> {
+    let path = socket_path();
+    let mut stream =
+        UnixStream::connect(&path).map_err(|err| IpcError::CouldNotConnect(path, err))?;
+    write_message(&mut stream, request)?;
+    read_message(&mut BufReader::new(stream))
+}
+
+/// Asks the running daemon to exit, and waits for it to stop listening. Returns false if no daemon
+/// was running.
+pub fn stop_running_daemon() -> Result<bool, IpcError> {
+    match send_request(&Request::Quit) {
+        Ok(_) => {}
+        Err(IpcError::CouldNotConnect(..)) => return Ok(false),
+        Err(err) => return Err(err),
+    }
+    let path = socket_path();
+    let deadline = Instant::now() + QUIT_TIMEOUT;
+    while UnixStream::connect(&path).is_ok() {
+        if Instant::now() > deadline {
+            return Err(IpcError::StillRunning);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(true)
+}
+
+/// Subscribes to the running daemon's events. Each line read from the returned reader is an
+/// [`Event`] as JSON, until the daemon exits.
+pub fn subscribe() -> Result<BufReader<UnixStream>, IpcError> {
+    let path = socket_path();
+    let mut stream =
+        UnixStream::connect(&path).map_err(|err| IpcError::CouldNotConnect(path, err))?;
+    write_message(&mut stream, &Request::Subscribe)?;
+    Ok(BufReader::new(stream))
+}
+
+/// A connection that subscribed to the daemon's events.
+pub struct Subscriber {
+    stream: UnixStream,
+}
+
+impl Subscriber {
+    /// Sends `event` to the subscriber. An error means the subscriber is gone (or stuck), so it
+    /// should be dropped.
+    pub fn send(&mut self, event: &Event) -> Result<(), IpcError> {
+        write_message(&mut self.stream, event)
+    }
+}
+
+/// Listens for requests from CLI invocations. The socket is removed when this is dropped.
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl IpcServer {
+    /// Binds the daemon's socket. Stale sockets from a previous instance are replaced.
+    pub fn bind() -> Result<Self, std::io::Error> {
+        let path = socket_path();
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) if err.kind() == ErrorKind::AddrInUse => {
+                if UnixStream::connect(&path).is_ok() {
+                    // Someone is actually listening, so don't steal their socket.
+                    return Err(err);
+                }
+                debug!("Removing stale socket at {path:?}");
+                std::fs::remove_file(&path)?;
+                UnixListener::bind(&path)?
+            }
+            Err(err) => return Err(err),
+        };
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, path })
+    }
+
+    /// Accepts all pending connections, calling `handle_request` for each request and sending
+    /// back its response. Returns the connections that subscribed to events.
+    pub fn accept_all(
+        &self,
+        mut handle_request: impl FnMut(Request) -> Response,
+    ) -> Vec<Subscriber> {
+        let mut subscribers = vec![];
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return subscribers,
+                Err(err) => {
+                    warn!("Failed to accept IPC connection: {err}");
+                    return subscribers;
+                }
+            };
+            match Self::handle_stream(stream, &mut handle_request) {
+                Ok(Some(subscriber)) => subscribers.push(subscriber),
+                Ok(None) => {}
+                Err(err) => warn!("Failed to handle IPC request: {err}"),
+            }
+        }
+    }
+
+    fn handle_stream(
+        stream: UnixStream,
+        handle_request: &mut impl FnMut(Request) -> Response,
+    ) -> Result<Option<Subscriber>, IpcError> {
+        // Don't let a misbehaving client stall the daemon.
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+        let mut reader = BufReader::new(stream);
+        let response = match read_message(&mut reader) {
+            Ok(Request::Subscribe) => {
+                debug!("Received IPC subscription");
+                return Ok(Some(Subscriber {
+                    stream: reader.into_inner(),
+                }));
+            }
+            Ok(request) => {
+                debug!("Received IPC request: {request:?}");
+                handle_request(request)
+            }
+            Err(IpcError::InvalidMessage(err)) => {
+                Response::Error(format!("Invalid request: {err}"))
+            }
+            Err(err) => return Err(err),
+        };
+        write_message(reader.get_mut(), &response)?;
+        Ok(None)
+    }
+}
+
+impl std::os::fd::AsFd for IpcServer {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Writes `message` as a single line of JSON.
+fn write_message(stream: &mut UnixStream, message: &impl Serialize) -> Result<(), IpcError> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    Ok(())
+}
+
+/// Reads a single line of JSON.
+fn read_message<T: for<'de> Deserialize<'de>>(
+    reader: &mut BufReader<UnixStream>,
+) -> Result<T, IpcError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
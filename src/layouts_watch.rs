@@ -0,0 +1,131 @@
+use std::{
+    ffi::OsStr,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsFd, BorrowedFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+};
+
+use rustix::{
+    fs::inotify::{self, CreateFlags, WatchFlags},
+    io::Errno,
+};
+use tracing::warn;
+
+/// Watches the layouts files for changes made by other programs (e.g. a text editor), using
+/// inotify.
+pub struct LayoutsWatcher {
+    inotify: OwnedFd,
+    /// The watched files, along with the watch descriptor of their directory.
+    files: Vec<(i32, PathBuf)>,
+    /// The watched layouts directories, along with their watch descriptors.
+    dirs: Vec<(i32, PathBuf)>,
+}
+
+impl LayoutsWatcher {
+    /// Starts watching the files at `paths`, and the layout files in the directories at `dirs`.
+    /// The directories of files are watched rather than the files themselves, since files are
+    /// usually replaced rather than written in place. Paths that can't be watched are skipped with
+    /// a warning.
+    pub fn new<'a>(
+        paths: impl IntoIterator<Item = &'a Path>,
+        dirs: impl IntoIterator<Item = &'a Path>,
+    ) -> Result<Self, std::io::Error> {
+        let inotify = inotify::init(CreateFlags::NONBLOCK | CreateFlags::CLOEXEC)?;
+        let mut files = vec![];
+        for path in paths {
+            let Some(directory) = path.parent() else {
+                continue;
+            };
+            let directory = if directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                directory
+            };
+            // The directory may not exist before the layouts are first saved.
+            if let Err(err) = std::fs::create_dir_all(directory) {
+                warn!("Failed to create {directory:?}, so not watching {path:?}: {err}");
+                continue;
+            }
+            match inotify::add_watch(
+                &inotify,
+                directory,
+                WatchFlags::CLOSE_WRITE | WatchFlags::MOVED_TO,
+            ) {
+                Ok(wd) => files.push((wd, path.to_path_buf())),
+                Err(err) => warn!("Failed to watch {path:?}: {err}"),
+            }
+        }
+        let mut watched_dirs = vec![];
+        for dir in dirs {
+            if let Err(err) = std::fs::create_dir_all(dir) {
+                warn!("Failed to create {dir:?}, so not watching it: {err}");
+                continue;
+            }
+            match inotify::add_watch(
+                &inotify,
+                dir,
+                WatchFlags::CLOSE_WRITE | WatchFlags::MOVED_TO,
+            ) {
+                Ok(wd) => watched_dirs.push((wd, dir.to_path_buf())),
+                Err(err) => warn!("Failed to watch {dir:?}: {err}"),
+            }
+        }
+        Ok(Self {
+            inotify,
+            files,
+            dirs: watched_dirs,
+        })
+    }
+
+    /// Reads the pending events, returning the watched files that changed.
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut buffer = [MaybeUninit::uninit(); 4096];
+        let mut reader = inotify::Reader::new(&self.inotify, &mut buffer);
+        let mut changed: Vec<PathBuf> = vec![];
+        loop {
+            let event = match reader.next() {
+                Ok(event) => event,
+                Err(Errno::AGAIN | Errno::INTR) => break,
+                Err(err) => {
+                    warn!("Failed to read changes to the layouts files: {err}");
+                    break;
+                }
+            };
+            let Some(file_name) = event.file_name() else {
+                continue;
+            };
+            let file_name = OsStr::from_bytes(file_name.to_bytes());
+            let files = self
+                .files
+                .iter()
+                .filter(|(wd, path)| *wd == event.wd() && path.file_name() == Some(file_name))
+                .map(|(_, path)| path.clone());
+            let is_layout_file = matches!(
+                Path::new(file_name)
+                    .extension()
+                    .and_then(|extension| extension.to_str()),
+                Some("json" | "toml")
+            );
+            let dir_files = self
+                .dirs
+                .iter()
+                .filter(|(wd, _)| is_layout_file && *wd == event.wd())
+                .map(|(_, dir)| dir.join(file_name));
+            for path in files.chain(dir_files) {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl AsFd for LayoutsWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inotify.as_fd()
+    }
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, time::Duration};
+
+use calloop::channel::Sender;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use zbus::blocking::{Connection, Proxy};
+
+use crate::{complete::HeadIdentity, serde::SavedConfiguration};
+
+/// The prefixes of the connectors of laptops' built-in displays.
+const INTERNAL_CONNECTORS: &[&str] = &["eDP", "LVDS", "DSI"];
+
+/// The state of the laptop's lid, which layouts can be kept for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LidState {
+    Open,
+    Closed,
+}
+
+impl LidState {
+    fn from_closed(closed: bool) -> Self {
+        if closed {
+            Self::Closed
+        } else {
+            Self::Open
+        }
+    }
+}
+
+/// How often systemd-logind is asked for the state of the lid when UPower isn't available, since
+/// logind doesn't announce changes to it.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reads the state of the laptop's lid over D-Bus: from UPower, which announces changes to it, or
+/// else from systemd-logind, which has to be polled.
+pub struct LidWatcher {
+    proxy: Proxy<'static>,
+    /// The property of the proxy that says whether the lid is closed.
+    property: &'static str,
+    /// Whether the proxy doesn't announce changes to the property.
+    polled: bool,
+}
+
+impl LidWatcher {
+    /// Connects to UPower (if it is running and knows about the lid) or else systemd-logind on the
+    /// system bus.
+    pub fn connect() -> zbus::Result<Self> {
+        let connection = Connection::system()?;
+        let upower = Proxy::new(
+            &connection,
+            "org.freedesktop.UPower",
+            "/org/freedesktop/UPower",
+            "org.freedesktop.UPower",
+        )?;
+        if upower.get_property::<bool>("LidIsPresent").unwrap_or(false) {
+            return Ok(Self {
+                proxy: upower,
+                property: "LidIsClosed",
+                polled: false,
+            });
+        }
+        let logind = Proxy::new(
+            &connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )?;
+        Ok(Self {
+            proxy: logind,
+            property: "LidClosed",
+            polled: true,
+        })
+    }
+
+    /// Returns the current state of the lid.
+    pub fn state(&self) -> zbus::Result<LidState> {
+        self.proxy
+            .get_property(self.property)
+            .map(LidState::from_closed)
+    }
+
+    /// Sends the state of the lid to `sender` every time it changes, from another thread. Stops
+    /// once the receiving end is dropped.
+    pub fn watch(self, sender: Sender<LidState>) {
+        if self.polled {
+            std::thread::spawn(move || self.poll(sender));
+            return;
+        }
+        std::thread::spawn(move || {
+            for change in self.proxy.receive_property_changed::<bool>(self.property) {
+                let state = match change.get() {
+                    Ok(closed) => LidState::from_closed(closed),
+                    Err(err) => {
+                        warn!("Failed to read the state of the lid: {err}");
+                        continue;
+                    }
+                };
+                if sender.send(state).is_err() {
+                    return;
+                }
+            }
+            error!("Stopped receiving changes to the lid from UPower");
+        });
+    }
+
+    /// Reads the state of the lid every [`POLL_INTERVAL`], sending it to `sender` when it changes.
+    fn poll(self, sender: Sender<LidState>) {
+        let mut last_state = None;
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let state = match self.state() {
+                Ok(state) => state,
+                Err(err) => {
+                    warn!("Failed to read the state of the lid: {err}");
+                    continue;
+                }
+            };
+            if last_state == Some(state) {
+                continue;
+            }
+            last_state = Some(state);
+            if sender.send(state).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Disables the laptop's built-in displays in `query_head_to_configuration`, for applying a layout
+/// while the lid is closed. Nothing is disabled unless another head stays enabled.
+pub fn disable_internal_heads(
+    query_head_to_configuration: &mut HashMap<HeadIdentity, Option<SavedConfiguration>>,
+) {
+    let is_internal = |identity: &HeadIdentity| {
+        INTERNAL_CONNECTORS
+            .iter()
+            .any(|prefix| identity.name.starts_with(prefix))
+    };
+    let external_enabled = query_head_to_configuration
+        .iter()
+        .any(|(identity, configuration)| configuration.is_some() && !is_internal(identity));
+    if !external_enabled {
+        return;
+    }
+    for (identity, configuration) in query_head_to_configuration.iter_mut() {
+        if is_internal(identity) && configuration.is_some() {
+            info!(
+                head = identity.name,
+                "Disabling head \"{}\" since the lid is closed", identity.description
+            );
+            *configuration = None;
+        }
+    }
+}
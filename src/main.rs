@@ -1,15 +1,54 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    process::Command,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fs::File,
+    io::Write,
+    os::fd::{BorrowedFd, FromRawFd},
+    path::Path,
+    process::Stdio,
     sync::Arc,
+    thread::JoinHandle,
+    time::Instant,
 };
 
-use complete::{HeadIdentity, HeadState, ModeState};
-use config::{Args, CollectArgsError};
+use calloop::{
+    generic::Generic,
+    signals::{Signal, Signals},
+    timer::{TimeoutAction, Timer},
+    EventLoop, Interest, LoopHandle, PostAction, RegistrationToken,
+};
+use calloop_wayland_source::WaylandSource;
+
+use complete::{Head, HeadIdentity, HeadState, Mode, ModeState};
+use config::{
+    Args, CollectArgsError, Command, CommandLine, CtlCommand, LogFormat, NewHeadDefaults,
+};
+use dry_run::DryRunHead;
+use formats::{ConnectedHead, ImportFormat};
+use hooks::HookContext;
+use ipc::{Event, IpcServer, Request, Response, Stats, Status, Subscriber, Switched};
+use layouts_watch::LayoutsWatcher;
+use lid::{disable_internal_heads, LidState, LidWatcher};
+use matcher::MatcherPick;
 use partial::{PartialHead, PartialHeadState, PartialModeState, PartialObjects};
-use serde::{LayoutData, SavedConfiguration};
-use tracing::{debug, error, info};
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use persist::Persister;
+use power::{read_power_supplies, PowerSource, PowerWatcher, POLL_INTERVAL};
+use scale::{logical_size, validate_scale, ScaleQuirks};
+use serde::{
+    head_set_key, map_layout_heads, pick_mode, IdentityField, Layout, LayoutData, LayoutMatch,
+    LayoutMatchScore, Property, Recovery, SavedConfiguration, Transform, DEFAULT_PROFILE,
+};
+use sleep::{SleepEvent, SleepWatcher};
+use state::{unix_now, MATCH_RESOLUTION_SECS};
+use sway::SwayIpc;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+use watch::WatchedHead;
 use wayland_client::{
     backend::ObjectId,
     event_created_child,
@@ -24,17 +63,35 @@ use wayland_protocols_wlr::output_management::v1::client::{
     zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
 };
 
+mod arrange;
+mod cli;
 mod complete;
+mod compositor;
 mod config;
+mod dry_run;
+mod edid;
+mod formats;
+mod history;
+mod hooks;
+mod init;
+mod ipc;
+mod layouts_watch;
+mod lid;
+mod matcher;
+mod output;
 mod partial;
+mod persist;
+mod power;
+mod reset;
+mod scale;
 mod serde;
+mod sleep;
+mod state;
+mod sway;
+mod ui;
+mod watch;
 
 fn main() {
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
-
     let args = match Args::collect() {
         Ok(args) => args,
         Err(CollectArgsError::LayoutsPathIsDirectory(path)) => {
@@ -44,64 +101,2441 @@ fn main() {
         err => err.expect("Failed to collect arguments"),
     };
 
+    // When systemd connects us to the journal, log to it directly so events keep their structured
+    // fields (e.g. `journalctl -t wl-distore LAYOUT=office`).
+    let journald = std::env::var_os("JOURNAL_STREAM")
+        .and_then(|_| tracing_journald::layer().ok())
+        .map(|layer| {
+            layer
+                .with_field_prefix(None)
+                .with_syslog_identifier("wl-distore".into())
+        });
+    let writer = match &args.log_file {
+        Some(path) => match open_log_file(path) {
+            Ok(file) => Some(BoxMakeWriter::new(Arc::new(file))),
+            Err(err) => {
+                eprintln!("Failed to open the log file {path:?}: {err}");
+                std::process::exit(1);
+            }
+        },
+        None => journald
+            .is_none()
+            .then(|| BoxMakeWriter::new(std::io::stderr)),
+    };
+    let fmt = writer.map(|writer| {
+        let layer = fmt::layer()
+            .with_writer(writer)
+            .with_ansi(args.log_file.is_none());
+        match args.log_format {
+            LogFormat::Full => layer.boxed(),
+            LogFormat::Compact => layer.compact().boxed(),
+            LogFormat::Pretty => layer.pretty().boxed(),
+        }
+    });
+    // `RUST_LOG` takes precedence over the configured level.
+    let filter = match &args.log_level {
+        Some(level) if std::env::var_os(EnvFilter::DEFAULT_ENV).is_none() => EnvFilter::new(level),
+        // A dry run is pointless if what it would do isn't logged.
+        _ => EnvFilter::builder()
+            .with_default_directive(if args.dry_run {
+                LevelFilter::INFO.into()
+            } else {
+                LevelFilter::ERROR.into()
+            })
+            .from_env_lossy(),
+    };
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(journald)
+        .with(filter)
+        .init();
+
     main_with_args(args);
 }
 
-fn main_with_args(args: Args) {
+/// Opens the log file at `path` for appending, creating it and its parent directories.
+fn open_log_file(path: &Path) -> Result<File, std::io::Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    File::options().create(true).append(true).open(path)
+}
+
+fn main_with_args(mut args: Args) {
+    if let Some(Command::Apply {
+        layout: None,
+        interactive: true,
+        ..
+    }) = args.command
+    {
+        // Pick the layout before connecting, since the picker may take a while.
+        let picked = cli::pick_layout(&args);
+        if let Some(Command::Apply { layout, .. }) = &mut args.command {
+            *layout = Some(picked);
+        }
+    }
+    if let Some(Command::Rollback { to, list, apply }) = args.command {
+        if list {
+            cli::list_snapshots(&args);
+            return;
+        }
+        cli::rollback_layouts(&args, to);
+        if !apply {
+            return;
+        }
+        // Continue as `apply` to apply the restored layout matching the current heads.
+        args.command = Some(Command::Apply {
+            layout: None,
+            interactive: false,
+            only: None,
+        });
+    }
+    match &args.command {
+        Some(Command::List) => {
+            cli::list_layouts(&args);
+            return;
+        }
+        Some(Command::Show { layout }) => {
+            cli::show_layout(&args, layout);
+            return;
+        }
+        Some(Command::Export {
+            to,
+            layout: Some(layout),
+        }) => {
+            cli::export_layout(&args, *to, layout);
+            return;
+        }
+        Some(Command::Delete { layout }) => {
+            cli::delete_layout(&args, layout);
+            return;
+        }
+        Some(Command::Rename { layout, name }) => {
+            cli::rename_layout(&args, layout, name);
+            return;
+        }
+        Some(Command::Prune { unused_for }) => {
+            cli::prune_layouts(&args, *unused_for);
+            return;
+        }
+        Some(Command::Dedupe) => {
+            cli::dedupe_layouts(&args);
+            return;
+        }
+        Some(Command::Schema) => {
+            println!("{}", serde::json_schema());
+            return;
+        }
+        Some(Command::Migrate) => {
+            cli::migrate_layouts(&args);
+            return;
+        }
+        Some(Command::Validate { path }) => {
+            cli::validate_layouts(&args, path.as_deref());
+            return;
+        }
+        Some(Command::Ui) => {
+            ui::run(&args);
+            return;
+        }
+        Some(Command::Stats) => {
+            cli::print_stats(args.format);
+            return;
+        }
+        Some(Command::Status) => {
+            cli::print_status(args.format);
+            return;
+        }
+        Some(Command::Subscribe) => {
+            cli::subscribe();
+            return;
+        }
+        Some(Command::Ctl {
+            command: CtlCommand::Switch { profile },
+        }) => {
+            if let Err(err) = cli::request_switch(profile.clone()) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Ctl {
+            command: CtlCommand::Save,
+        }) => {
+            cli::send_ctl(Request::Save);
+            return;
+        }
+        Some(Command::Ctl {
+            command: CtlCommand::Reapply,
+        }) => {
+            cli::send_ctl(Request::Reapply);
+            return;
+        }
+        Some(Command::Ctl {
+            command: CtlCommand::Pause,
+        }) => {
+            cli::send_ctl(Request::Pause);
+            return;
+        }
+        Some(Command::Ctl {
+            command: CtlCommand::Resume,
+        }) => {
+            cli::send_ctl(Request::Resume);
+            return;
+        }
+        // Without a running daemon (or for a dry run), the profile's layout is applied once
+        // instead.
+        Some(Command::Switch { profile })
+            if !args.dry_run && cli::request_switch(Some(profile.clone())).is_ok() =>
+        {
+            return;
+        }
+        _ => {}
+    }
+
+    // Only the daemon signals readiness. Check its file descriptor up front, since taking
+    // ownership of one that isn't open would close whatever file gets that number later.
+    if let (Some(fd), None) = (args.ready_fd, &args.command) {
+        // SAFETY: The file descriptor is only borrowed for the check, which fails with `EBADF`
+        // if it isn't open.
+        let result = if fd >= 0 {
+            rustix::io::fcntl_getfd(unsafe { BorrowedFd::borrow_raw(fd) })
+        } else {
+            Err(rustix::io::Errno::BADF)
+        };
+        if let Err(err) = result {
+            eprintln!("The --ready-fd {fd} isn't an open file descriptor: {err}");
+            std::process::exit(1);
+        }
+    }
+
+    // Only the daemon accepts requests. One-shot subcommands just do their thing and exit. This
+    // happens before loading the layouts, so a replaced daemon has finished writing them.
+    let server = if args.command.is_none() {
+        if args.replace {
+            match ipc::stop_running_daemon() {
+                Ok(true) => info!("Replaced the running wl-distore"),
+                Ok(false) => {}
+                Err(err) => {
+                    eprintln!("Failed to replace the running wl-distore: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        match IpcServer::bind() {
+            Ok(server) => Some(server),
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                eprintln!(
+                    "wl-distore is already running on this display (pass --replace to replace it)"
+                );
+                std::process::exit(1);
+            }
+            Err(err) => {
+                error!("Failed to listen for IPC requests: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // The daemon reloads its config on SIGHUP, and exits on SIGTERM and SIGINT once the pending
+    // writes are finished. The signals are blocked here, before any threads are spawned, so they
+    // inherit the mask and they are only handled by the event loop.
+    let signals = if args.command.is_none() {
+        match Signals::new(&[Signal::SIGHUP, Signal::SIGTERM, Signal::SIGINT]) {
+            Ok(signals) => Some(signals),
+            Err(err) => {
+                error!("Failed to listen for signals: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Load the layouts before connecting, so the first `Done` event (which is when monitors snap
+    // into place on login) doesn't wait on the disk.
+    let file_layout = match &args.command {
+        Some(Command::ApplyFile { path }) => Some(cli::read_layout_file(path)),
+        _ => None,
+    };
+    let mut app_data = match AppData::new(args) {
+        Ok(app_data) => app_data,
+        Err(err) => {
+            eprintln!("Failed to load layouts: {err}");
+            std::process::exit(1);
+        }
+    };
+    app_data.file_layout = file_layout;
+
     let connection = Connection::connect_to_env().expect("Failed to establish a connection");
     let display = connection.display();
 
-    let mut event_queue = connection.new_event_queue();
+    let event_queue = connection.new_event_queue();
     let qhandle = event_queue.handle();
 
     display.get_registry(&qhandle, ());
 
-    let mut app_data = AppData::new(args).expect("Failed to load layouts");
-    loop {
-        event_queue.blocking_dispatch(&mut app_data).unwrap();
+    let mut event_loop: EventLoop<'static, AppData> =
+        EventLoop::try_new().expect("Failed to create the event loop");
+    app_data.loop_handle = Some(event_loop.handle());
+    WaylandSource::new(connection, event_queue)
+        .insert(event_loop.handle())
+        .expect("Failed to insert the Wayland source");
+
+    if let Some(server) = server {
+        let qhandle = qhandle.clone();
+        let signal = event_loop.get_signal();
+        event_loop
+            .handle()
+            .insert_source(
+                Generic::new(server, Interest::READ, calloop::Mode::Level),
+                move |_, server, state: &mut AppData| {
+                    let subscribers =
+                        server.accept_all(|request| state.handle_request(request, &qhandle));
+                    for subscriber in subscribers {
+                        state.subscribe(subscriber);
+                    }
+                    if state.quit_requested {
+                        signal.stop();
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .expect("Failed to insert the IPC source");
+    }
+
+    if let Some(signals) = signals {
+        let signal = event_loop.get_signal();
+        event_loop
+            .handle()
+            .insert_source(signals, move |event, _, state| match event.signal() {
+                Signal::SIGHUP => state.reload_config(),
+                received => {
+                    info!("Received {received:?}, exiting");
+                    signal.stop();
+                }
+            })
+            .expect("Failed to insert the signal source");
+    }
+
+    if app_data.args.command.is_none() {
+        let paths = std::iter::once(app_data.args.layouts.as_path())
+            .chain(app_data.args.layouts_ro.as_deref())
+            .collect::<Vec<_>>();
+        match LayoutsWatcher::new(paths, app_data.args.layouts_dir.as_deref()) {
+            Ok(watcher) => {
+                let qhandle = qhandle.clone();
+                event_loop
+                    .handle()
+                    .insert_source(
+                        Generic::new(watcher, Interest::READ, calloop::Mode::Level),
+                        move |_, watcher, state: &mut AppData| {
+                            let changed_files = watcher.changed_files();
+                            if !changed_files.is_empty() {
+                                state.reload_layouts(changed_files, &qhandle);
+                            }
+                            Ok(PostAction::Continue)
+                        },
+                    )
+                    .expect("Failed to insert the layouts watcher");
+            }
+            Err(err) => error!("Failed to watch the layouts files: {err}"),
+        }
+    }
+
+    if app_data.args.lid_switch {
+        match LidWatcher::connect().and_then(|watcher| Ok((watcher.state()?, watcher))) {
+            Ok((lid, watcher)) => {
+                debug!("The lid is {lid:?}");
+                app_data.layout_data.lid = Some(lid);
+                if app_data.args.command.is_none() {
+                    let (sender, lid_changes) = calloop::channel::channel();
+                    watcher.watch(sender);
+                    let qhandle = qhandle.clone();
+                    event_loop
+                        .handle()
+                        .insert_source(lid_changes, move |event, _, state: &mut AppData| {
+                            if let calloop::channel::Event::Msg(lid) = event {
+                                state.lid_changed(lid, &qhandle);
+                            }
+                        })
+                        .expect("Failed to insert the lid source");
+                }
+            }
+            Err(err) => error!("Failed to read the state of the lid: {err}"),
+        }
+    }
+
+    if app_data.args.watch_power_source {
+        match PowerWatcher::connect().and_then(|watcher| Ok((watcher.state()?, watcher))) {
+            Ok((power_source, watcher)) => {
+                debug!("The power source is {power_source:?}");
+                app_data.layout_data.power_source = Some(power_source);
+                if app_data.args.command.is_none() {
+                    let (sender, power_source_changes) = calloop::channel::channel();
+                    watcher.watch(sender);
+                    let qhandle = qhandle.clone();
+                    event_loop
+                        .handle()
+                        .insert_source(
+                            power_source_changes,
+                            move |event, _, state: &mut AppData| {
+                                if let calloop::channel::Event::Msg(power_source) = event {
+                                    state.power_source_changed(power_source, &qhandle);
+                                }
+                            },
+                        )
+                        .expect("Failed to insert the power source");
+                }
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to read the power source from UPower, so reading sysfs instead: {err}"
+                );
+                match read_power_supplies() {
+                    Ok(power_source) => {
+                        debug!("The power source is {power_source:?}");
+                        app_data.layout_data.power_source = Some(power_source);
+                        if app_data.args.command.is_none() {
+                            let qhandle = qhandle.clone();
+                            event_loop
+                                .handle()
+                                .insert_source(
+                                    Timer::from_duration(POLL_INTERVAL),
+                                    move |_, _, state| {
+                                        match read_power_supplies() {
+                                            Ok(power_source) => {
+                                                state.power_source_changed(power_source, &qhandle)
+                                            }
+                                            Err(err) => {
+                                                warn!("Failed to read the power supplies: {err}")
+                                            }
+                                        }
+                                        TimeoutAction::ToDuration(POLL_INTERVAL)
+                                    },
+                                )
+                                .expect("Failed to insert the power supply timer");
+                        }
+                    }
+                    Err(err) => error!("Failed to read the power supplies: {err}"),
+                }
+            }
+        }
+    }
+
+    if app_data.args.reapply_after_resume && app_data.args.command.is_none() {
+        let (sender, sleep_events) = calloop::channel::channel();
+        match SleepWatcher::connect().and_then(|watcher| watcher.watch(sender)) {
+            Ok(()) => {
+                let qhandle = qhandle.clone();
+                event_loop
+                    .handle()
+                    .insert_source(sleep_events, move |event, _, state: &mut AppData| {
+                        if let calloop::channel::Event::Msg(event) = event {
+                            state.sleep_event(event, &qhandle);
+                        }
+                    })
+                    .expect("Failed to insert the suspend source");
+            }
+            Err(err) => error!("Failed to watch for suspends through systemd-logind: {err}"),
+        }
+    }
+
+    if !app_data.args.sway_reload_grace.is_zero() && app_data.args.command.is_none() {
+        if let Some(sway_ipc) = SwayIpc::connect() {
+            let (sender, reloads) = calloop::channel::channel();
+            match sway_ipc.and_then(|sway_ipc| sway_ipc.watch_reloads(sender)) {
+                Ok(()) => {
+                    event_loop
+                        .handle()
+                        .insert_source(reloads, |event, _, state: &mut AppData| {
+                            if let calloop::channel::Event::Msg(()) = event {
+                                state.sway_reloaded();
+                            }
+                        })
+                        .expect("Failed to insert the sway reload source");
+                }
+                Err(err) => error!("Failed to watch for sway reloading its config: {err}"),
+            }
+        }
+    }
+
+    if let Some(interval) = app_data.args.object_report_interval {
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(interval), move |_, _, state| {
+                state.report_live_objects();
+                TimeoutAction::ToDuration(interval)
+            })
+            .expect("Failed to insert the object report timer");
+    }
+
+    event_loop
+        .run(None, &mut app_data, AppData::notify_ready)
+        .expect("Failed to dispatch events");
+    // Finish writing the layouts before the socket is removed, since that tells a replacing
+    // instance it can load them.
+    drop(app_data);
+    drop(event_loop);
+}
+
+struct AppData {
+    args: Args,
+
+    partial_objects: PartialObjects,
+    id_to_head: HashMap<ObjectId, HeadState>,
+    head_identity_to_id: HashMap<HeadIdentity, ObjectId>,
+    id_to_mode: HashMap<ObjectId, ModeState>,
+    done_action: DoneAction,
+    layout_data: LayoutData,
+    /// The heads as of the last `Done` event, used by the `watch` subcommand and to tell
+    /// subscribers about changes to the heads.
+    watched_heads: HashMap<HeadIdentity, WatchedHead>,
+    /// The heads that were disconnected and haven't been connected again since, to count
+    /// reconnects.
+    disconnected_heads: HashSet<HeadIdentity>,
+    /// The connections streaming events (see `wl-distore subscribe`).
+    subscribers: Vec<Subscriber>,
+    stats: Stats,
+    started: Instant,
+    /// Whether another instance asked the daemon to exit (see `--replace`).
+    quit_requested: bool,
+    /// Whether changes to the configuration are neither saved nor enforced (see `wl-distore ctl
+    /// pause`).
+    paused: bool,
+    /// The serial a layout we successfully applied was configured against, until the `Done` event
+    /// that results from it (the first one with a later serial) arrives. In enforce mode, this
+    /// result is accepted rather than enforced, since the compositor may have adjusted the layout
+    /// (e.g., by rounding the scale).
+    applied_layout_pending: Option<u32>,
+    /// The output manager and the serial of its most recent `Done` event. This allows applying
+    /// configurations outside of the `Done` handler.
+    output_manager: Option<(ZwlrOutputManagerV1, u32)>,
+    /// The number of consecutive attempts to apply a layout that failed.
+    failed_applies: u32,
+    /// Whether the configuration being applied is the safe-mode fallback.
+    applying_safe_mode: bool,
+    /// Whether the next `Done` event is the result of applying the safe-mode fallback, which
+    /// should not be saved over the layout that failed to apply.
+    safe_mode_result_pending: bool,
+    scale_quirks: ScaleQuirks,
+    /// The index of the layout being applied, so its metadata can be updated once it succeeds.
+    applying_layout: Option<usize>,
+    /// The head commands of the layout being applied, to run once it succeeds.
+    pending_head_commands: Vec<String>,
+    /// The number of configuration objects that haven't been destroyed yet.
+    live_configurations: usize,
+    /// Whether the first `Done` event is still to come, so its layout can be applied right away.
+    startup: bool,
+    /// Writes the layouts and state files. The daemon writes them in the background.
+    persister: Persister,
+    /// The `--ready-fd` file descriptor, until readiness is signalled on it.
+    ready_fd: Option<File>,
+    /// The layout read by the `apply-file` subcommand.
+    file_layout: Option<Layout>,
+    /// The configuration being applied by the `arrange` subcommand, to save once it succeeds.
+    arranged_layout: Option<HashMap<HeadIdentity, Option<SavedConfiguration>>>,
+    /// The handle of the event loop, to schedule retries of failed applies.
+    loop_handle: Option<LoopHandle<'static, AppData>>,
+    /// The timer that applies the layout once the heads settle, if they are settling.
+    settle_timer: Option<RegistrationToken>,
+    /// Whether the machine is suspending or has just resumed (see `reapply_after_resume`). `Done`
+    /// events are ignored until the layout is applied again, since compositors may scramble the
+    /// configuration in the meantime.
+    suspended: bool,
+    /// The timer that applies the layout again after resuming, if the machine just resumed.
+    resume_timer: Option<RegistrationToken>,
+    /// The timer that ends the grace period after sway reloads its config (see
+    /// `sway_reload_grace`), during which changes to the configuration are reverted instead of
+    /// saved.
+    sway_reload_timer: Option<RegistrationToken>,
+    /// The contents of the layouts files most recently handed to the persister (one entry per
+    /// save), so the daemon's own writes aren't mistaken for external edits.
+    recent_writes: VecDeque<Vec<Vec<u8>>>,
+    /// The last pick of the matcher, along with the heads and the built-in match it was asked
+    /// about, so it is only run again once those (or the layouts) change.
+    matcher_pick: Option<(Vec<HeadIdentity>, Option<usize>, MatcherPick)>,
+}
+
+/// The number of writes kept in [`AppData::recent_writes`].
+const RECENT_WRITES: usize = 8;
+
+#[derive(Default, Clone, Copy)]
+enum DoneAction {
+    /// Update the layout for the current head setup.
+    #[default]
+    Update,
+    /// Apply the layout for the current head setup.
+    Apply,
+    /// The next Done events corresponds to the result of an Apply action, so ignore it.
+    ApplyResult,
+    /// Applying failed, and will be retried once the backoff elapses, so ignore Done events until
+    /// then.
+    Retry,
+    /// The heads changed, and their layout will be applied once they stop changing for
+    /// `apply_settle`, so ignore Done events until then.
+    Settle,
+}
+
+impl AppData {
+    fn new(args: Args) -> Result<Self, std::io::Error> {
+        // SAFETY: The user passed us this file descriptor to write to, so nothing else owns it,
+        // and it was checked to be open.
+        let ready_fd = args
+            .ready_fd
+            .filter(|_| args.command.is_none())
+            .map(|fd| unsafe { File::from_raw_fd(fd) });
+        let mut layout_data = LayoutData::load_with_read_only(
+            &args.seed_layouts,
+            args.layouts_ro.as_deref(),
+            &args.layouts,
+            args.layouts_dir.as_deref(),
+            // Only the daemon moves a damaged layouts file aside, since subcommands may only read
+            // the layouts.
+            if args.command.is_none() {
+                Recovery::MoveAside
+            } else {
+                Recovery::InPlace
+            },
+        )?;
+        layout_data.match_fields = args.match_fields.clone();
+        layout_data.matcher = args.matcher();
+        layout_data.host = args.host.clone();
+        layout_data.compositor = compositor::from_env();
+        layout_data.scope_to_compositor = args.scope_to_compositor;
+        layout_data.match_supersets = args.match_supersets;
+        layout_data.match_subsets = args.match_subsets;
+        Ok(Self {
+            partial_objects: Default::default(),
+            id_to_head: Default::default(),
+            head_identity_to_id: Default::default(),
+            id_to_mode: Default::default(),
+            done_action: Default::default(),
+            startup: args.command.is_none() && args.apply,
+            layout_data,
+            persister: Persister::new(
+                args.command.is_none(),
+                args.save_delay,
+                args.min_save_interval,
+            ),
+            ready_fd,
+            file_layout: None,
+            arranged_layout: None,
+            watched_heads: Default::default(),
+            disconnected_heads: Default::default(),
+            subscribers: vec![],
+            stats: Default::default(),
+            started: Instant::now(),
+            paused: false,
+            quit_requested: false,
+            applied_layout_pending: None,
+            output_manager: None,
+            failed_applies: 0,
+            applying_safe_mode: false,
+            safe_mode_result_pending: false,
+            scale_quirks: ScaleQuirks::detect(),
+            applying_layout: None,
+            pending_head_commands: vec![],
+            live_configurations: 0,
+            loop_handle: None,
+            settle_timer: None,
+            suspended: false,
+            resume_timer: None,
+            sway_reload_timer: None,
+            recent_writes: VecDeque::new(),
+            matcher_pick: None,
+            args,
+        })
+    }
+
+    /// Saves the layouts after the layout at `index` was changed, or after a layout was removed if
+    /// [`None`]. Returns the index of that layout, which changes if layouts before it are deleted
+    /// to stay under `max_layouts`.
+    fn save_layouts(&mut self, index: Option<usize>) -> Option<usize> {
+        if self.args.dry_run {
+            info!("Not saving the layouts since this is a dry run");
+            return index;
+        }
+        let index = self.evict_layouts(index);
+        if let Err(err) = self.write_layouts() {
+            self.exit_if_immediate(&err);
+            error!("{err}");
+            return index;
+        }
+        self.stats.saves += 1;
+        self.layout_data.generation += 1;
+        self.matcher_pick = None;
+        if let Some(index) = index {
+            self.layout_data.layouts[index].metadata.last_saved = Some(unix_now());
+            let layout = &self.layout_data.layouts[index];
+            self.publish(Event::Saved {
+                layout: index,
+                name: layout.name.clone(),
+                profile: layout.profile().to_string(),
+            });
+        }
+        if let Err(err) = self.save_state() {
+            self.exit_if_immediate(&err);
+            error!("{err}");
+        }
+        index
+    }
+
+    /// Writes the layouts (and a snapshot of them) through the persister. In the background, only
+    /// serialization errors are returned, since the files are written later.
+    fn write_layouts(&mut self) -> Result<(), std::io::Error> {
+        let contents = self
+            .layout_data
+            .to_json()
+            .map_err(|err| std::io::Error::other(format!("Failed to serialize layouts: {err}")))?;
+        let written = match self.args.layouts_dir.clone() {
+            None => {
+                self.persister
+                    .write(self.args.layouts.clone(), contents.clone())?;
+                self.persister
+                    .write(serde::backup_path(&self.args.layouts), contents.clone())?;
+                vec![contents.clone()]
+            }
+            Some(dir) => {
+                let files = self.layout_data.to_layout_files().map_err(|err| {
+                    std::io::Error::other(format!("Failed to serialize layouts: {err}"))
+                })?;
+                let written = files.iter().map(|(_, contents)| contents.clone()).collect();
+                self.layout_data
+                    .layout_files
+                    .extend(files.iter().map(|(file_name, _)| file_name.clone()));
+                let layout_files = self.layout_data.layout_files.clone();
+                self.persister.run("layouts_dir", move || {
+                    serde::write_layout_files(&dir, &files, &layout_files).map_err(|err| {
+                        std::io::Error::new(
+                            err.kind(),
+                            format!("Failed to write the layouts to {dir:?}: {err}"),
+                        )
+                    })
+                })?;
+                written
+            }
+        };
+        if self.recent_writes.len() == RECENT_WRITES {
+            self.recent_writes.pop_front();
+        }
+        self.recent_writes.push_back(written);
+        let layouts_path = self.args.layouts.clone();
+        let history_size = self.args.history_size;
+        self.persister.run("history", move || {
+            history::record(&layouts_path, &contents, history_size);
+            Ok(())
+        })
+    }
+
+    /// Exits with `err` if files are written immediately, i.e., this is a one-shot subcommand that
+    /// would otherwise report success after failing to save.
+    fn exit_if_immediate(&self, err: &std::io::Error) {
+        if !self.persister.is_background() {
+            eprintln!("Failed to save layouts: {err}");
+            std::process::exit(1);
+        }
+    }
+
+    /// Deletes the least recently used layouts if there are more than `max_layouts`, keeping the
+    /// layout at `index`. Returns the index of that layout once the others are deleted.
+    fn evict_layouts(&mut self, index: Option<usize>) -> Option<usize> {
+        let Some(max_layouts) = self.args.max_layouts else {
+            return index;
+        };
+        let evicted = self.layout_data.evict(max_layouts, index);
+        if evicted.is_empty() {
+            return index;
+        }
+        info!(
+            "Deleted {} least recently used layouts, since there were more than {max_layouts}",
+            evicted.len()
+        );
+        let remap = |index: usize| {
+            evicted
+                .binary_search(&index)
+                .err()
+                .map(|position| index - position)
+        };
+        self.applying_layout = self.applying_layout.and_then(remap);
+        index.and_then(remap)
+    }
+
+    /// Reloads the config (e.g. on SIGHUP), keeping the current config if it can't be loaded. The
+    /// layouts files, the seed layouts, the save timing and the log options are only read on
+    /// startup.
+    fn reload_config(&mut self) {
+        let mut args = match Args::collect() {
+            Ok(args) => args,
+            Err(err) => {
+                error!("Failed to reload the config, so keeping the current one: {err}");
+                return;
+            }
+        };
+        let seeds_changed = args.seed_layouts.len() != self.args.seed_layouts.len()
+            || args
+                .seed_layouts
+                .iter()
+                .zip(&self.args.seed_layouts)
+                .any(|(new, old)| new.to_json().ok() != old.to_json().ok());
+        if seeds_changed
+            || args.layouts != self.args.layouts
+            || args.layouts_ro != self.args.layouts_ro
+            || args.layouts_dir != self.args.layouts_dir
+            || args.save_delay != self.args.save_delay
+            || args.min_save_interval != self.args.min_save_interval
+        {
+            warn!("Restart wl-distore to use the new layouts files, seed layouts or save timing");
+            args.seed_layouts = std::mem::take(&mut self.args.seed_layouts);
+            args.layouts = self.args.layouts.clone();
+            args.layouts_ro = self.args.layouts_ro.clone();
+            args.layouts_dir = self.args.layouts_dir.clone();
+            args.save_delay = self.args.save_delay;
+            args.min_save_interval = self.args.min_save_interval;
+        }
+        if args.lid_switch != self.args.lid_switch {
+            warn!("Restart wl-distore to start or stop watching the lid");
+            args.lid_switch = self.args.lid_switch;
+        }
+        if args.reapply_after_resume != self.args.reapply_after_resume {
+            warn!("Restart wl-distore to start or stop watching for suspends");
+            args.reapply_after_resume = self.args.reapply_after_resume;
+        }
+        if args.sway_reload_grace.is_zero() != self.args.sway_reload_grace.is_zero() {
+            warn!("Restart wl-distore to start or stop watching for sway reloading its config");
+            args.sway_reload_grace = self.args.sway_reload_grace;
+        }
+        if args.watch_power_source != self.args.watch_power_source {
+            warn!("Restart wl-distore to start or stop watching the power source");
+            args.watch_power_source = self.args.watch_power_source;
+        }
+        if args.log_level != self.args.log_level
+            || args.log_file != self.args.log_file
+            || args.log_format != self.args.log_format
+        {
+            warn!("Restart wl-distore to use the new log options");
+        }
+        self.layout_data.match_fields = args.match_fields.clone();
+        self.layout_data.matcher = args.matcher();
+        self.matcher_pick = None;
+        self.layout_data.host = args.host.clone();
+        self.layout_data.scope_to_compositor = args.scope_to_compositor;
+        self.layout_data.match_supersets = args.match_supersets;
+        self.layout_data.match_subsets = args.match_subsets;
+        self.args = args;
+        // Heads may have started or stopped being ignored.
+        self.head_identity_to_id = self
+            .id_to_head
+            .iter()
+            .filter(|(_, head)| !self.args.is_ignored(&head.head.identity))
+            .map(|(id, head)| (head.head.identity.clone(), id.clone()))
+            .collect();
+        info!("Reloaded the config");
+    }
+
+    /// Reloads the layouts after `changed_files` were edited by another program, then applies the
+    /// layout that now matches the current heads. Keeps the current layouts if they can't be
+    /// loaded.
+    fn reload_layouts(
+        &mut self,
+        changed_files: Vec<std::path::PathBuf>,
+        qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        let external = changed_files.iter().any(|path| {
+            let written_by_us = *path == self.args.layouts
+                || self
+                    .args
+                    .layouts_dir
+                    .as_deref()
+                    .is_some_and(|dir| path.parent() == Some(dir));
+            if !written_by_us {
+                return true;
+            }
+            match std::fs::read(path) {
+                Ok(contents) => !self
+                    .recent_writes
+                    .iter()
+                    .any(|written| written.contains(&contents)),
+                // The file is gone, so a later event will tell us about its replacement.
+                Err(_) => false,
+            }
+        });
+        if !external {
+            return;
+        }
+        // The changes on disk win over ours that haven't been written yet, which would otherwise
+        // overwrite them.
+        self.persister.cancel(
+            vec![
+                self.args.layouts.clone(),
+                serde::backup_path(&self.args.layouts),
+            ],
+            vec!["layouts_dir"],
+        );
+        let mut layout_data = match LayoutData::load_with_read_only(
+            &self.args.seed_layouts,
+            self.args.layouts_ro.as_deref(),
+            &self.args.layouts,
+            self.args.layouts_dir.as_deref(),
+            Recovery::Never,
+        ) {
+            Ok(layout_data) => layout_data,
+            Err(err) => {
+                error!("Failed to reload the changed layouts, so keeping the current ones: {err}");
+                return;
+            }
+        };
+        layout_data.match_fields = self.layout_data.match_fields.clone();
+        layout_data.matcher = self.layout_data.matcher.take();
+        layout_data.host = self.layout_data.host.take();
+        layout_data.compositor = self.layout_data.compositor.take();
+        layout_data.scope_to_compositor = self.layout_data.scope_to_compositor;
+        layout_data.match_supersets = self.layout_data.match_supersets;
+        layout_data.match_subsets = self.layout_data.match_subsets;
+        layout_data.lid = self.layout_data.lid;
+        layout_data.power_source = self.layout_data.power_source;
+        layout_data.active_profile = self.layout_data.active_profile.take();
+        layout_data.profile_choices = std::mem::take(&mut self.layout_data.profile_choices);
+        layout_data.generation = layout_data.generation.max(self.layout_data.generation);
+        self.layout_data = layout_data;
+        // The layout being applied refers to the old layouts.
+        self.applying_layout = None;
+        self.matcher_pick = None;
+        info!("Reloaded the layouts after they were changed on disk");
+        if !self.args.apply || self.paused || self.applying() {
+            return;
+        }
+        self.apply_matching_layout(qhandle);
+    }
+
+    /// Returns the context passed to commands run for `event`, about the layout at `layout` (or
+    /// the current configuration if [`None`]) and the `head` that was added or removed.
+    fn hook_context(
+        &self,
+        event: &'static str,
+        layout: Option<usize>,
+        head: Option<&HeadIdentity>,
+    ) -> HookContext {
+        let layout = match layout {
+            Some(index) => self.layout_data.layouts[index].clone(),
+            None => Layout::new(self.current_layout()),
+        };
+        HookContext::new(event, layout, head.map(|head| head.name.clone()))
+    }
+
+    /// Sends `event` to every subscriber, dropping those that are gone, and runs its hook.
+    fn publish(&mut self, event: Event) {
+        if let Some(hook) = self.args.hooks.for_event(&event) {
+            let (layout, head) = match &event {
+                Event::Saved { layout, .. } => (Some(*layout), None),
+                Event::Applied { layout, .. } | Event::ApplyFailed { layout, .. } => {
+                    (*layout, None)
+                }
+                Event::HeadAdded { head, .. }
+                | Event::HeadRemoved { head }
+                | Event::HeadChanged { head, .. } => (None, Some(head)),
+                Event::ProfileSwitched { .. } | Event::Paused | Event::Resumed => (None, None),
+            };
+            let context = self.hook_context(hooks::event_name(&event), layout, head);
+            let thread = run_command(hook.to_command(&self.args.command_shell), &context);
+            // One-shot subcommands may exit right after this, which would kill the hook.
+            if self.args.command.is_some() {
+                let _ = thread.join();
+            }
+        }
+        self.subscribers
+            .retain_mut(|subscriber| match subscriber.send(&event) {
+                Ok(()) => true,
+                Err(err) => {
+                    debug!("Dropping a subscriber: {err}");
+                    false
+                }
+            });
+    }
+
+    /// Adds `subscriber`, first telling it about the heads that are already connected.
+    fn subscribe(&mut self, mut subscriber: Subscriber) {
+        let mut heads = self.watched_heads.iter().collect::<Vec<_>>();
+        heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        for (identity, head) in heads {
+            let event = Event::HeadAdded {
+                head: identity.clone(),
+                configuration: head.configuration.clone(),
+            };
+            if let Err(err) = subscriber.send(&event) {
+                debug!("Dropping a subscriber: {err}");
+                return;
+            }
+        }
+        self.subscribers.push(subscriber);
+    }
+
+    /// Records that the layout at `index` matched the current heads.
+    fn record_match(&mut self, index: usize) {
+        let now = unix_now();
+        let metadata = &mut self.layout_data.layouts[index].metadata;
+        if metadata
+            .last_matched
+            .is_some_and(|last_matched| now.saturating_sub(last_matched) < MATCH_RESOLUTION_SECS)
+        {
+            return;
+        }
+        metadata.last_matched = Some(now);
+        if let Err(err) = self.save_state() {
+            error!("{err}");
+        }
+    }
+
+    /// Signals readiness on the `--ready-fd` file descriptor once the first `Done` event has been
+    /// handled (including issuing the initial apply). This runs after each dispatch of the event
+    /// loop.
+    fn notify_ready(&mut self) {
+        if self.output_manager.is_none() {
+            return;
+        }
+        // Dropping the file closes it.
+        let Some(mut ready_fd) = self.ready_fd.take() else {
+            return;
+        };
+        if let Err(err) = ready_fd.write_all(b"\n") {
+            error!("Failed to notify readiness: {err}");
+        }
+    }
+
+    /// Releases the modes in `ids`, whether or not they are complete yet.
+    fn release_modes(&mut self, ids: impl IntoIterator<Item = ObjectId>) {
+        for id in ids {
+            if let Some(mode) = self.id_to_mode.remove(&id) {
+                mode.proxy.release();
+            }
+            if let Some(mode) = self.partial_objects.id_to_mode.remove(&id) {
+                mode.proxy.release();
+            }
+        }
+    }
+
+    /// Logs the number of Wayland objects we are holding on to. These should stay bounded by the
+    /// number of connected heads no matter how many times heads are plugged in and out.
+    fn report_live_objects(&self) {
+        // Modes that no head refers to can never be used again, so they have leaked.
+        let referenced_modes = self
+            .id_to_head
+            .values()
+            .flat_map(|head| head.head.mode_to_id.values())
+            .chain(
+                self.partial_objects
+                    .id_to_head
+                    .values()
+                    .flat_map(|head| head.head.modes.iter()),
+            )
+            .collect::<HashSet<_>>();
+        let orphaned_modes = self
+            .id_to_mode
+            .keys()
+            .filter(|id| !referenced_modes.contains(id))
+            .count();
+        info!(
+            heads = self.id_to_head.len(),
+            modes = self.id_to_mode.len(),
+            partial_heads = self.partial_objects.id_to_head.len(),
+            partial_modes = self.partial_objects.id_to_mode.len(),
+            configurations = self.live_configurations,
+            orphaned_modes,
+            "Live objects: {} heads, {} modes, {} partial heads, {} partial modes, {} configurations ({orphaned_modes} orphaned modes)",
+            self.id_to_head.len(),
+            self.id_to_mode.len(),
+            self.partial_objects.id_to_head.len(),
+            self.partial_objects.id_to_mode.len(),
+            self.live_configurations,
+        );
+    }
+
+    /// A short label for the layout at `index` for logs: its name, or its index if it has none.
+    fn layout_label(&self, index: usize) -> String {
+        self.layout_data.layouts[index]
+            .name
+            .clone()
+            .unwrap_or_else(|| index.to_string())
+    }
+
+    /// Describes the layout at `index` for log messages: its quoted name, or its index and the
+    /// descriptions of its heads if it has none.
+    fn describe_layout(&self, index: usize) -> String {
+        let layout = &self.layout_data.layouts[index];
+        match layout.name.as_ref() {
+            Some(name) => format!("'{name}'"),
+            None => format!(
+                "{index} {:?}",
+                layout
+                    .heads
+                    .keys()
+                    .map(|head_identity| head_identity.description.as_str())
+                    .collect::<HashSet<_>>()
+            ),
+        }
+    }
+
+    /// Saves the runtime metadata of the layouts to the state file, unless this is a dry run.
+    fn save_state(&self) -> Result<(), std::io::Error> {
+        if self.args.dry_run {
+            return Ok(());
+        }
+        let contents = state::to_json(&self.layout_data)
+            .map_err(|err| std::io::Error::other(format!("Failed to serialize state: {err}")))?;
+        self.persister
+            .write(state::state_path(&self.args.layouts), contents)
+    }
+
+    /// Warns about (and optionally snaps) any scales in `layout` that the compositor may not
+    /// accept.
+    fn validate_scales(&self, layout: &mut HashMap<HeadIdentity, Option<SavedConfiguration>>) {
+        for (identity, configuration) in layout.iter_mut() {
+            if let Some(configuration) = configuration {
+                validate_scale(
+                    identity,
+                    configuration,
+                    &self.scale_quirks,
+                    self.args.snap_scales,
+                );
+            }
+        }
+    }
+
+    /// Prepares `current_layout` to be saved over the layout at `layout_index` (if any). Only the
+    /// properties in the `capture` setting are recorded, and properties the saved layout leaves to
+    /// the compositor stay that way.
+    fn capture_properties(
+        &self,
+        layout_index: Option<usize>,
+        current_layout: &mut HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) {
+        for configuration in current_layout.values_mut().flatten() {
+            configuration.retain_properties(&self.args.capture);
+        }
+        let Some(layout_index) = layout_index else {
+            return;
+        };
+        let layout = &self.layout_data.layouts[layout_index];
+        let layout_head_to_query_head = map_layout_heads(
+            layout,
+            &current_layout.keys().cloned().collect(),
+            &self.layout_data.match_fields,
+        );
+        for (layout_head, saved_configuration) in layout.heads.iter() {
+            let (Some(Some(current)), Some(saved)) = (
+                layout_head_to_query_head
+                    .get(layout_head)
+                    .and_then(|query_head| current_layout.get_mut(query_head)),
+                saved_configuration,
+            ) else {
+                continue;
+            };
+            if saved.mode.is_none() {
+                current.mode = None;
+            }
+            if saved.adaptive_sync.is_none() {
+                current.adaptive_sync = None;
+            }
+            current.dont_care.position |= saved.dont_care.position;
+            current.dont_care.transform |= saved.dont_care.transform;
+            current.dont_care.scale |= saved.dont_care.scale;
+        }
+    }
+
+    /// For heads with a preferred refresh rate, keeps the mode saved in the layout at
+    /// `layout_index` if `current_layout` only changes the refresh rate away from the preferred
+    /// one. This prevents compositors that flip-flop between modes from overwriting the preferred
+    /// mode.
+    fn keep_preferred_refresh(
+        &self,
+        layout_index: usize,
+        layout_head_to_query_head: &HashMap<HeadIdentity, HeadIdentity>,
+        current_layout: &mut HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) {
+        for (layout_head, saved_configuration) in
+            self.layout_data.layouts[layout_index].heads.iter()
+        {
+            let query_head = layout_head_to_query_head
+                .get(layout_head)
+                .unwrap_or(layout_head);
+            let Some(preferred_refresh) =
+                self.args.mode_preference_for(query_head).preferred_refresh
+            else {
+                continue;
+            };
+            let (
+                Some(Some(SavedConfiguration {
+                    mode: Some(current_mode),
+                    ..
+                })),
+                Some(SavedConfiguration {
+                    mode: Some(saved_mode),
+                    ..
+                }),
+            ) = (current_layout.get_mut(query_head), saved_configuration)
+            else {
+                continue;
+            };
+            if current_mode.size != saved_mode.size {
+                continue;
+            }
+            let distance = |mode: &Mode| mode.refresh.unwrap_or(0).abs_diff(preferred_refresh);
+            if distance(saved_mode) < distance(current_mode) {
+                debug!(
+                    "Keeping mode {saved_mode} instead of {current_mode} for \"{}\" since it is closer to the preferred refresh rate",
+                    query_head.description
+                );
+                *current_mode = *saved_mode;
+            }
+        }
+    }
+
+    /// Responds to a request from a CLI invocation.
+    fn handle_request(
+        &mut self,
+        request: Request,
+        qhandle: &wayland_client::QueueHandle<Self>,
+    ) -> Response {
+        match request {
+            Request::Stats => Response::Stats(Stats {
+                uptime_secs: self.started.elapsed().as_secs(),
+                ..self.stats.clone()
+            }),
+            Request::Switch { profile } => self.switch_profile(profile, qhandle),
+            Request::Subscribe => unreachable!("The IPC server keeps subscriptions to itself"),
+            Request::Reapply => self.reapply(qhandle),
+            Request::Save => self.save_current(),
+            Request::Quit => {
+                info!("Exiting since another instance is replacing this one");
+                self.quit_requested = true;
+                Response::Ok("Exiting".into())
+            }
+            Request::Pause => self.set_paused(true),
+            Request::Resume => self.set_paused(false),
+            Request::Status => {
+                let heads = self.head_identity_to_id.keys().cloned().collect();
+                Response::Status(Status {
+                    paused: self.paused,
+                    active_profile: self.layout_data.active_profile.clone(),
+                    layout: self
+                        .layout_data
+                        .find_layout_match(&heads)
+                        .map(|(index, _, _)| index),
+                })
+            }
+        }
+    }
+
+    /// Applies the layout matching the current heads again, e.g. after something else changed the
+    /// configuration.
+    fn reapply(&mut self, qhandle: &wayland_client::QueueHandle<Self>) -> Response {
+        let Some((output_manager, serial)) = self.output_manager.clone() else {
+            return Response::Error("The heads haven't been received yet".into());
+        };
+        if self.applying() {
+            return Response::Error("A layout is being applied".into());
+        }
+        let heads = self.head_identity_to_id.keys().cloned().collect();
+        let Some((index, _, layout_head_to_query_head)) =
+            self.layout_data.find_layout_match(&heads)
+        else {
+            return Response::Error("No layout matches the current heads".into());
+        };
+        info!(
+            layout = self.layout_label(index),
+            action = "apply",
+            "Re-apply layout {}",
+            self.describe_layout(index)
+        );
+        self.apply_layout(
+            index,
+            layout_head_to_query_head,
+            &output_manager,
+            qhandle,
+            serial,
+        );
+        Response::Ok(format!("Re-applying layout {}", self.layout_label(index)))
+    }
+
+    /// Pauses or resumes saving changes to the configuration.
+    fn set_paused(&mut self, paused: bool) -> Response {
+        if self.paused == paused {
+            return Response::Ok(if paused {
+                "Saving is already paused".into()
+            } else {
+                "Saving isn't paused".into()
+            });
+        }
+        self.paused = paused;
+        if paused {
+            info!("Paused saving changes to the configuration");
+            self.publish(Event::Paused);
+            Response::Ok("Paused saving changes".into())
+        } else {
+            info!("Resumed saving changes to the configuration");
+            self.publish(Event::Resumed);
+            Response::Ok("Resumed saving changes".into())
+        }
+    }
+
+    /// Makes `profile` (or the next profile of the current heads if [`None`]) the active profile,
+    /// and applies its layout for the current heads. If it has none, the current configuration is
+    /// saved as its layout instead.
+    fn switch_profile(
+        &mut self,
+        profile: Option<String>,
+        qhandle: &wayland_client::QueueHandle<Self>,
+    ) -> Response {
+        let Some((output_manager, serial)) = self.output_manager.clone() else {
+            return Response::Error("The heads haven't been received yet".into());
+        };
+        let heads = self.head_identity_to_id.keys().cloned().collect();
+        let layout_match = self.layout_data.find_layout_to_apply(&heads);
+        let profile = match profile {
+            Some(profile) => profile,
+            None => {
+                let Some((index, _, _)) = layout_match.as_ref() else {
+                    return Response::Error("No layout matches the current heads".into());
+                };
+                let profiles = self.layout_data.matching_profiles(&heads);
+                let current = self.layout_data.layouts[*index].profile();
+                let position = profiles
+                    .iter()
+                    .position(|profile| *profile == current)
+                    .unwrap_or_default();
+                profiles[(position + 1) % profiles.len()].to_string()
+            }
+        };
+        info!("Switch to profile '{profile}'");
+        self.layout_data.active_profile = Some(profile.clone());
+        self.layout_data.choose_profile(&heads, &profile);
+        if let Err(err) = self.save_state() {
+            error!("{err}");
+        }
+        self.publish(Event::ProfileSwitched {
+            profile: profile.clone(),
+        });
+        match self.layout_data.find_layout_to_apply(&heads) {
+            Some((index, _, layout_head_to_query_head))
+                if self.layout_data.layouts[index].profile() == profile =>
+            {
+                info!(
+                    layout = self.layout_label(index),
+                    action = "apply",
+                    "Apply layout {}",
+                    self.describe_layout(index)
+                );
+                self.apply_layout(
+                    index,
+                    layout_head_to_query_head,
+                    &output_manager,
+                    qhandle,
+                    serial,
+                );
+                Response::Switched(Switched {
+                    profile,
+                    layout: index,
+                    created: false,
+                })
+            }
+            _ if self.args.read_only => Response::Error(format!(
+                "Profile '{profile}' has no layout for the current heads, and wl-distore is \
+                 read-only so it can't save one"
+            )),
+            _ => {
+                let mut current_layout = self.current_layout();
+                self.validate_scales(&mut current_layout);
+                self.capture_properties(
+                    layout_match.map(|(index, _, _)| index),
+                    &mut current_layout,
+                );
+                let index = self.layout_data.layouts.len();
+                info!(
+                    layout = index,
+                    action = "save",
+                    "Saved layout {index} for profile '{profile}'"
+                );
+                self.layout_data
+                    .layouts
+                    .push(self.layout_data.scoped(Layout {
+                        profile: (profile != DEFAULT_PROFILE).then(|| profile.clone()),
+                        ..Layout::new(current_layout)
+                    }));
+                let index = self.save_layouts(Some(index)).unwrap_or(index);
+                Response::Switched(Switched {
+                    profile,
+                    layout: index,
+                    created: true,
+                })
+            }
+        }
+    }
+
+    /// Tells subscribers that applying the layout failed or was `cancelled`.
+    fn publish_apply_failed(&mut self, cancelled: bool) {
+        let layout = self.applying_layout;
+        self.publish(Event::ApplyFailed {
+            layout,
+            name: layout.and_then(|index| self.layout_data.layouts[index].name.clone()),
+            cancelled,
+        });
+    }
+
+    /// Returns the current configuration of every head that isn't ignored.
+    fn current_layout(&self) -> HashMap<HeadIdentity, Option<SavedConfiguration>> {
+        self.id_to_head
+            .values()
+            .filter(|head| !self.args.is_ignored(&head.head.identity))
+            .map(|head| {
+                (
+                    head.head.identity.clone(),
+                    head.head.configuration.as_ref().map(|configuration| {
+                        SavedConfiguration::from_config(
+                            configuration,
+                            &self.id_to_mode,
+                            &self.args.managed_properties,
+                        )
+                    }),
+                )
+            })
+            .collect()
+    }
+
+    /// Saves `current_layout` as a new layout and returns its index.
+    fn save_new_layout(
+        &mut self,
+        current_layout: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) -> usize {
+        self.save_as_new_layout(Layout::new(current_layout))
+    }
+
+    /// Saves `layout` (with the current configuration as its heads) as a new layout and returns its
+    /// index.
+    fn save_as_new_layout(&mut self, mut layout: Layout) -> usize {
+        let index = self.layout_data.layouts.len();
+        info!(
+            layout = index,
+            action = "save",
+            "Saved layout: {:?}",
+            layout
+                .heads
+                .keys()
+                .map(|head_identity| head_identity.description.as_str())
+                .collect::<HashSet<_>>()
+        );
+        self.validate_scales(&mut layout.heads);
+        self.capture_properties(None, &mut layout.heads);
+        self.layout_data
+            .layouts
+            .push(self.layout_data.scoped(layout));
+        self.save_layouts(Some(index)).unwrap_or(index)
+    }
+
+    /// Saves `current_layout` over the layout at `layout_index`, which matched the current heads
+    /// with `layout_head_to_query_head`. Templates (and layouts for any state of the lid while it
+    /// is closed) are never saved over, so `current_layout` is saved as a new layout of the same
+    /// profile instead (see [`LayoutData::saves_as_new_layout`]). Returns the index of the layout
+    /// saved into.
+    fn update_layout(
+        &mut self,
+        layout_index: usize,
+        layout_head_to_query_head: &HashMap<HeadIdentity, HeadIdentity>,
+        mut current_layout: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) -> usize {
+        let layout = &self.layout_data.layouts[layout_index];
+        if self.layout_data.saves_as_new_layout(layout) {
+            info!(
+                layout = self.layout_label(layout_index),
+                "Saving the configuration as a new layout, since layout {} is {}",
+                self.describe_layout(layout_index),
+                if layout.template {
+                    "a template"
+                } else {
+                    "for an open lid"
+                }
+            );
+            return self.save_as_new_layout(Layout {
+                profile: self.layout_data.layouts[layout_index].profile.clone(),
+                ..Layout::new(current_layout)
+            });
+        }
+        info!(
+            layout = self.layout_label(layout_index),
+            action = "save",
+            "Update layout {}",
+            self.describe_layout(layout_index)
+        );
+        if self.args.command.is_none() {
+            self.keep_preferred_refresh(
+                layout_index,
+                layout_head_to_query_head,
+                &mut current_layout,
+            );
+        }
+        self.validate_scales(&mut current_layout);
+        self.capture_properties(Some(layout_index), &mut current_layout);
+        self.layout_data.layouts[layout_index].heads = current_layout;
+        self.save_layouts(Some(layout_index))
+            .unwrap_or(layout_index)
+    }
+
+    /// Saves the current configuration into the layout matching the current heads (or as a new
+    /// layout if none matches) for `wl-distore ctl save`. This saves even if saving is paused.
+    fn save_current(&mut self) -> Response {
+        if self.args.read_only {
+            return Response::Error("wl-distore is read-only".into());
+        }
+        if self.output_manager.is_none() {
+            return Response::Error("The heads haven't been received yet".into());
+        }
+        if self.applying() {
+            return Response::Error("A layout is being applied".into());
+        }
+        let current_layout = self.current_layout();
+        match self
+            .layout_data
+            .find_layout_match(&current_layout.keys().cloned().collect())
+        {
+            Some((index, _, _)) if self.layout_data.layouts[index].read_only => {
+                Response::Error(format!("Layout {} is read-only", self.layout_label(index)))
+            }
+            Some((index, _, layout_head_to_query_head))
+                if self
+                    .layout_data
+                    .saves_as_new_layout(&self.layout_data.layouts[index]) =>
+            {
+                let index = self.update_layout(index, &layout_head_to_query_head, current_layout);
+                Response::Ok(format!("Saved the current configuration as layout {index}"))
+            }
+            Some((index, _, layout_head_to_query_head)) => {
+                self.update_layout(index, &layout_head_to_query_head, current_layout);
+                Response::Ok(format!("Updated layout {}", self.layout_label(index)))
+            }
+            None => {
+                let index = self.save_new_layout(current_layout);
+                Response::Ok(format!("Saved the current configuration as layout {index}"))
+            }
+        }
+    }
+
+    /// Saves `current_layout` into the layout named `name` for `save-current --into` and exits. If
+    /// no layout has that name, a new layout is created with it.
+    fn save_current_into(
+        &mut self,
+        name: String,
+        mut current_layout: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) -> ! {
+        self.validate_scales(&mut current_layout);
+        let existing = self.layout_data.find_layout_by_name(&name);
+        self.capture_properties(existing, &mut current_layout);
+        let outcome = match existing {
+            Some(index) => {
+                if self.layout_data.layouts[index].read_only {
+                    eprintln!("Layout \"{name}\" is read-only");
+                    std::process::exit(1);
+                }
+                if self.layout_data.layouts[index].template {
+                    eprintln!("Layout \"{name}\" is a template, so it can't be saved over");
+                    std::process::exit(1);
+                }
+                if !self.layout_data.layouts[index].matches(
+                    &current_layout.keys().cloned().collect(),
+                    &self.layout_data.match_fields,
+                ) {
+                    eprintln!(
+                        "The heads of layout \"{name}\" are not compatible with the current heads"
+                    );
+                    std::process::exit(1);
+                }
+                info!(layout = %name, action = "save", "Update layout \"{name}\"");
+                self.layout_data.layouts[index].heads = current_layout;
+                (SaveOutcome::Updated, index)
+            }
+            None => {
+                info!(layout = %name, action = "save", "Saved layout \"{name}\"");
+                self.layout_data
+                    .layouts
+                    .push(self.layout_data.scoped(Layout {
+                        name: Some(name),
+                        ..Layout::new(current_layout)
+                    }));
+                (SaveOutcome::Created, self.layout_data.layouts.len() - 1)
+            }
+        };
+        let index = self.save_layouts(Some(outcome.1)).unwrap_or(outcome.1);
+        print_save_result(outcome.0, index, &self.layout_data);
+        std::process::exit(0);
+    }
+
+    /// Returns every head that isn't ignored along with its current configuration and modes.
+    fn connected_heads(&self) -> Vec<ConnectedHead> {
+        self.id_to_head
+            .values()
+            .filter(|head_state| !self.args.is_ignored(&head_state.head.identity))
+            .map(|head_state| {
+                let head = &head_state.head;
+                ConnectedHead {
+                    identity: head.identity.clone(),
+                    configuration: head.configuration.as_ref().map(|configuration| {
+                        SavedConfiguration::from_config(
+                            configuration,
+                            &self.id_to_mode,
+                            Property::ALL,
+                        )
+                    }),
+                    modes: head.mode_to_id.keys().copied().collect(),
+                    preferred_mode: head
+                        .mode_to_id
+                        .values()
+                        .filter_map(|id| self.id_to_mode.get(id))
+                        .find(|mode_state| mode_state.preferred)
+                        .map(|mode_state| mode_state.mode),
+                }
+            })
+            .collect()
+    }
+
+    /// Lets the user arrange the heads for the `arrange` subcommand, then applies the result. It is
+    /// saved once it has been applied. Exits if the user quits instead.
+    fn arrange_heads(
+        &mut self,
+        output_manager: &ZwlrOutputManagerV1,
+        qhandle: &wayland_client::QueueHandle<Self>,
+        serial: u32,
+    ) {
+        let heads = self.connected_heads();
+        if heads.is_empty() {
+            eprintln!("There are no heads to arrange");
+            std::process::exit(1);
+        }
+        let arranged = match arrange::run(heads) {
+            Ok(Some(arranged)) => arranged,
+            Ok(None) => std::process::exit(0),
+            Err(err) => {
+                eprintln!("Failed to run the terminal UI: {err}");
+                std::process::exit(1);
+            }
+        };
+        info!(
+            "Apply the arranged heads: {:?}",
+            arranged
+                .keys()
+                .map(|head_identity| head_identity.name.as_str())
+                .collect::<HashSet<_>>()
+        );
+        self.arranged_layout = Some(arranged.clone());
+        self.apply_configurations(arranged, output_manager, qhandle, serial);
+    }
+
+    /// Saves the layout applied by the `arrange` subcommand into the layout matching the current
+    /// heads, or as a new layout if none matches.
+    fn save_arranged_layout(
+        &mut self,
+        arranged: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) {
+        match self
+            .layout_data
+            .find_layout_match(&arranged.keys().cloned().collect())
+        {
+            Some((index, _, _)) if self.layout_data.layouts[index].read_only => {
+                eprintln!(
+                    "Applied the arrangement, but the matching layout {} is read-only, so it wasn't saved",
+                    self.layout_label(index)
+                );
+            }
+            Some((index, _, layout_head_to_query_head))
+                if self
+                    .layout_data
+                    .saves_as_new_layout(&self.layout_data.layouts[index]) =>
+            {
+                let index = self.update_layout(index, &layout_head_to_query_head, arranged);
+                println!("Saved the arrangement as layout {index}");
+            }
+            Some((index, _, layout_head_to_query_head)) => {
+                self.update_layout(index, &layout_head_to_query_head, arranged);
+                println!(
+                    "Saved the arrangement into layout {}",
+                    self.layout_label(index)
+                );
+            }
+            None => {
+                let index = self.save_new_layout(arranged);
+                println!("Saved the arrangement as layout {index}");
+            }
+        }
+    }
+
+    /// Imports the layouts in the file at `path` for the `import` subcommand and exits. Imported
+    /// layouts replace layouts with the same name.
+    fn import_layouts(&mut self, format: ImportFormat, path: &Path, name: Option<String>) -> ! {
+        let connected_heads = self.connected_heads();
+        let mut layouts = match formats::import(format, path, &connected_heads) {
+            Ok(layouts) => layouts,
+            Err(err) => {
+                eprintln!("Failed to import {}: {err}", path.display());
+                std::process::exit(1);
+            }
+        };
+        if let Some(name) = name {
+            let count = layouts.len();
+            for (i, layout) in layouts.iter_mut().enumerate() {
+                layout.name = Some(if count == 1 {
+                    name.clone()
+                } else {
+                    format!("{name}-{}", i + 1)
+                });
+            }
+        }
+        for layout in layouts {
+            let mut layout = self.layout_data.scoped(layout);
+            self.validate_scales(&mut layout.heads);
+            let existing = layout
+                .name
+                .as_deref()
+                .and_then(|name| self.layout_data.find_layout_by_name(name));
+            let index = match existing {
+                Some(index) if self.layout_data.layouts[index].read_only => {
+                    eprintln!("Layout {index} is read-only, so it can't be replaced");
+                    std::process::exit(1);
+                }
+                Some(index) => {
+                    self.layout_data.layouts[index] = layout;
+                    index
+                }
+                None => {
+                    self.layout_data.layouts.push(layout);
+                    self.layout_data.layouts.len() - 1
+                }
+            };
+            match &self.layout_data.layouts[index].name {
+                Some(name) => println!("Imported layout {index} (\"{name}\")"),
+                None => println!("Imported layout {index}"),
+            }
+            self.save_layouts(Some(index));
+        }
+        std::process::exit(0);
+    }
+
+    /// Applies the layout for the new state of the lid (e.g., one that disables the laptop's
+    /// display when it is closed at a dock).
+    fn lid_changed(&mut self, lid: LidState, qhandle: &wayland_client::QueueHandle<Self>) {
+        if self.layout_data.lid == Some(lid) {
+            return;
+        }
+        match lid {
+            LidState::Open => info!("The lid was opened"),
+            LidState::Closed => info!("The lid was closed"),
+        }
+        self.layout_data.lid = Some(lid);
+        self.rematch(qhandle);
+    }
+
+    /// Applies the layout for the new power source (e.g., one with a lower refresh rate on
+    /// battery).
+    fn power_source_changed(
+        &mut self,
+        power_source: PowerSource,
+        qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if self.layout_data.power_source == Some(power_source) {
+            return;
+        }
+        match power_source {
+            PowerSource::Ac => info!("The machine is now on AC"),
+            PowerSource::Battery => info!("The machine is now on battery"),
+        }
+        self.layout_data.power_source = Some(power_source);
+        self.rematch(qhandle);
+    }
+
+    /// Stops saving changes to the configuration while the machine suspends, and applies the
+    /// layout matching the current heads again `resume_delay` after it resumes, since some
+    /// compositors scramble positions or scales while suspended.
+    fn sleep_event(&mut self, event: SleepEvent, qhandle: &wayland_client::QueueHandle<Self>) {
+        let Some(loop_handle) = self.loop_handle.as_ref() else {
+            return;
+        };
+        if let Some(token) = self.resume_timer.take() {
+            loop_handle.remove(token);
+        }
+        match event {
+            SleepEvent::Suspending => {
+                info!("The machine is suspending");
+                self.suspended = true;
+            }
+            SleepEvent::Resumed => {
+                info!(
+                    "The machine resumed, so applying the layout again in {:?}",
+                    self.args.resume_delay
+                );
+                self.suspended = true;
+                let qhandle = qhandle.clone();
+                let timer = loop_handle.insert_source(
+                    Timer::from_duration(self.args.resume_delay),
+                    move |_, _, state| {
+                        state.resume_timer = None;
+                        state.suspended = false;
+                        if state.applying() {
+                            debug!(
+                                "Not applying the layout after resuming since one is being applied"
+                            );
+                        } else if let Some((proxy, serial)) = state.output_manager.clone() {
+                            // Whatever the compositor reported while suspended is not a change
+                            // to save, so apply the layout for the heads as they are now.
+                            state.done_action = DoneAction::Apply;
+                            let current_layout = state.current_layout();
+                            state.handle_heads(current_layout, &proxy, &qhandle, serial);
+                        }
+                        TimeoutAction::Drop
+                    },
+                );
+                match timer {
+                    Ok(token) => self.resume_timer = Some(token),
+                    Err(err) => {
+                        error!("Failed to schedule applying the layout after resuming: {err}");
+                        self.suspended = false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts (or restarts) the grace period after sway reloads its config, during which changes
+    /// to the configuration are reverted instead of saved, since sway resets the heads to its
+    /// config when it reloads.
+    fn sway_reloaded(&mut self) {
+        let Some(loop_handle) = self.loop_handle.as_ref() else {
+            return;
+        };
+        if let Some(token) = self.sway_reload_timer.take() {
+            loop_handle.remove(token);
+        }
+        info!(
+            "sway reloaded its config, so not saving changes for {:?}",
+            self.args.sway_reload_grace
+        );
+        let timer = loop_handle.insert_source(
+            Timer::from_duration(self.args.sway_reload_grace),
+            |_, _, state| {
+                debug!("The grace period after sway reloaded its config ended");
+                state.sway_reload_timer = None;
+                TimeoutAction::Drop
+            },
+        );
+        match timer {
+            Ok(token) => self.sway_reload_timer = Some(token),
+            Err(err) => error!("Failed to start the grace period after sway reloaded: {err}"),
+        }
+    }
+
+    /// Applies the layout matching the current heads again after the layouts that can match them
+    /// changed (e.g., the lid was closed), unless applying is disabled, paused or in progress, or
+    /// the machine is suspended.
+    fn rematch(&mut self, qhandle: &wayland_client::QueueHandle<Self>) {
+        if !self.args.apply || self.paused || self.suspended || self.applying() {
+            return;
+        }
+        self.apply_matching_layout(qhandle);
+    }
+
+    /// Returns whether a layout is being applied: its result, a retry or the heads settling is
+    /// awaited. The heads shouldn't be matched again until then, since that will happen anyway.
+    fn applying(&self) -> bool {
+        matches!(
+            self.done_action,
+            DoneAction::ApplyResult | DoneAction::Retry | DoneAction::Settle
+        )
+    }
+
+    /// Applies the layout matching the current heads, if there is one. Returns whether a layout was
+    /// applied.
+    fn apply_matching_layout(&mut self, qhandle: &wayland_client::QueueHandle<Self>) -> bool {
+        let Some((output_manager, serial)) = self.output_manager.clone() else {
+            return false;
+        };
+        let heads = self.head_identity_to_id.keys().cloned().collect();
+        let layout_match = self.layout_data.find_layout_to_apply(&heads);
+        let Some(layout_match) = self.pick_layout_match(&heads, layout_match) else {
+            return false;
+        };
+        let Some((layout_index, _, layout_head_to_query_head)) =
+            layout_match.or_else(|| self.layout_data.find_partial_layout_match(&heads))
+        else {
+            return false;
+        };
+        self.apply_layout(
+            layout_index,
+            layout_head_to_query_head,
+            &output_manager,
+            qhandle,
+            serial,
+        );
+        true
+    }
+
+    /// Lets the matcher (if there is one) pick between the layouts matching `heads` instead of
+    /// `builtin_match`, the built-in match. Returns [`None`] if the matcher picked no layout, in
+    /// which case no layout should be applied, and the configuration shouldn't be saved either.
+    fn pick_layout_match(
+        &mut self,
+        heads: &HashSet<HeadIdentity>,
+        builtin_match: Option<LayoutMatch>,
+    ) -> Option<Option<LayoutMatch>> {
+        let heads_key = head_set_key(heads.iter());
+        let builtin = builtin_match.as_ref().map(|(index, _, _)| *index);
+        let pick = match self.matcher_pick.as_ref() {
+            Some((cached_heads, cached_builtin, pick))
+                if *cached_heads == heads_key && *cached_builtin == builtin =>
+            {
+                *pick
+            }
+            _ => {
+                let pick = self.layout_data.ask_matcher(heads, builtin);
+                self.matcher_pick = Some((heads_key, builtin, pick));
+                pick
+            }
+        };
+        match pick {
+            MatcherPick::Builtin => Some(builtin_match),
+            MatcherPick::Layout(index) => Some(self.layout_data.match_layout(index, heads)),
+            MatcherPick::Nothing => {
+                info!("The matcher picked no layout for the heads, so not applying or saving one");
+                None
+            }
+        }
+    }
+
+    /// Saves or applies the layout for the current heads (whose configuration is
+    /// `current_layout`), depending on the [`DoneAction`]. Nothing is done while the machine is
+    /// suspended, since the layout is applied again once it resumes.
+    fn handle_heads(
+        &mut self,
+        current_layout: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+        proxy: &ZwlrOutputManagerV1,
+        qhandle: &wayland_client::QueueHandle<Self>,
+        serial: u32,
+    ) {
+        if self.suspended {
+            debug!("Not saving or applying a layout since the machine is suspended");
+            return;
+        }
+        let heads = current_layout.keys().cloned().collect();
+        let mut layout_match = self.layout_data.find_layout_match(&heads);
+        let applied_layout_pending = self
+            .applied_layout_pending
+            .take()
+            .is_some_and(|applied_serial| applied_serial != serial);
+        if std::mem::take(&mut self.safe_mode_result_pending)
+            && matches!(self.done_action, DoneAction::Update)
+        {
+            debug!("Ignored the Done event since this is the result of applying the safe mode");
+            return;
+        }
+        if matches!(self.done_action, DoneAction::Apply)
+            && !matches!(self.args.command, Some(Command::SaveCurrent { .. }))
+            && (self.args.apply || self.args.command.is_some())
+        {
+            let layout_to_apply = self.layout_data.find_layout_to_apply(&heads);
+            if let (None, Some((index, _, _))) = (layout_to_apply.as_ref(), layout_match.as_ref()) {
+                info!(
+                    layout = self.layout_label(*index),
+                    "Not applying layout {} since it isn't applied automatically",
+                    self.describe_layout(*index)
+                );
+                self.done_action = DoneAction::Update;
+                return;
+            }
+            match self.pick_layout_match(&heads, layout_to_apply) {
+                Some(picked) => layout_match = picked,
+                None => {
+                    self.done_action = DoneAction::Update;
+                    return;
+                }
+            }
+        }
+        if let Some((index, _, _)) = layout_match.as_ref() {
+            self.record_match(*index);
+        }
+        if layout_match.is_none()
+            && matches!(self.done_action, DoneAction::Apply)
+            && self.args.command.is_none()
+            && self.args.apply
+        {
+            if let Some(defaults) = self.args.new_heads.clone() {
+                if self.apply_with_new_heads(&defaults, proxy, qhandle, serial) {
+                    return;
+                }
+            }
+            if let Some((index, _, layout_head_to_query_head)) = self
+                .layout_data
+                .find_partial_layout_match(&current_layout.keys().cloned().collect())
+            {
+                info!(
+                    layout = self.layout_label(index),
+                    action = "apply",
+                    "Apply layout {} to the heads it has",
+                    self.describe_layout(index)
+                );
+                self.apply_layout(index, layout_head_to_query_head, proxy, qhandle, serial);
+                return;
+            }
+        }
+        match (
+            layout_match,
+            // If save_and_exit is set (or applying is disabled), then we don't want to apply the
+            // layout at all.
+            if matches!(self.args.command, Some(Command::SaveCurrent { .. }))
+                || (!self.args.apply && self.args.command.is_none())
+            {
+                DoneAction::Update
+            } else {
+                self.done_action
+            },
+        ) {
+            (_, DoneAction::Update) | (None, DoneAction::Apply) if self.paused => {
+                info!("Not saving the configuration since saving is paused");
+                self.done_action = DoneAction::Update;
+            }
+            (Some((layout_index, _, layout_head_to_query_head)), DoneAction::Update)
+                if self.sway_reload_timer.is_some()
+                    && self.args.apply
+                    && !applied_layout_pending
+                    && self.layout_data.layouts[layout_index].differs_from(
+                        &layout_head_to_query_head,
+                        &current_layout,
+                        &self.args.managed_properties,
+                    ) =>
+            {
+                info!(
+                    layout = self.layout_label(layout_index),
+                    action = "apply",
+                    "sway reloaded its config, so re-applying layout {}",
+                    self.describe_layout(layout_index)
+                );
+                self.apply_layout(
+                    layout_index,
+                    layout_head_to_query_head,
+                    proxy,
+                    qhandle,
+                    serial,
+                );
+            }
+            (_, DoneAction::Update) | (None, DoneAction::Apply)
+                if self.sway_reload_timer.is_some() =>
+            {
+                info!("Not saving the configuration since sway just reloaded its config");
+                self.done_action = DoneAction::Update;
+            }
+            (None, DoneAction::Update | DoneAction::Apply)
+                if self.args.read_only && self.args.command.is_none() =>
+            {
+                info!("Not saving the configuration since wl-distore is read-only");
+                self.done_action = DoneAction::Update;
+            }
+            (None, DoneAction::Update | DoneAction::Apply)
+                if self.args.command.is_none()
+                    && self
+                        .layout_data
+                        .find_partial_layout_match(&current_layout.keys().cloned().collect())
+                        .is_some_and(|(index, _, _)| {
+                            self.layout_data.layouts[index].heads.len() > current_layout.len()
+                        }) =>
+            {
+                info!(
+                    "Not saving the configuration since a layout matches the heads that are \
+                     connected"
+                );
+                self.done_action = DoneAction::Update;
+            }
+            (None, DoneAction::Update | DoneAction::Apply) => {
+                let index = self.save_new_layout(current_layout);
+                if matches!(self.args.command, Some(Command::SaveCurrent { .. })) {
+                    // Bail out after the save.
+                    print_save_result(SaveOutcome::Created, index, &self.layout_data);
+                    std::process::exit(0);
+                }
+                // Ensure we go back to updating.
+                self.done_action = DoneAction::Update;
+            }
+            (None, DoneAction::ApplyResult) => {
+                panic!("We applied a layout, but then that layout didn't match?");
+            }
+            (Some((layout_index, _, layout_head_to_query_head)), DoneAction::Update)
+                if self.args.detect_resets
+                    && self.args.apply
+                    && self.args.command.is_none()
+                    && !applied_layout_pending
+                    && self.layout_data.layouts[layout_index].differs_from(
+                        &layout_head_to_query_head,
+                        &current_layout,
+                        &self.args.managed_properties,
+                    )
+                    && reset::is_reset(&self.connected_heads()) =>
+            {
+                info!(
+                    layout = self.layout_label(layout_index),
+                    action = "apply",
+                    "The heads look like the compositor reset them, so re-applying layout {}",
+                    self.describe_layout(layout_index)
+                );
+                self.apply_layout(
+                    layout_index,
+                    layout_head_to_query_head,
+                    proxy,
+                    qhandle,
+                    serial,
+                );
+            }
+            (Some((layout_index, _, layout_head_to_query_head)), DoneAction::Update)
+                if self.args.enforce
+                    && self.args.apply
+                    && self.args.command.is_none()
+                    && !applied_layout_pending
+                    && self.layout_data.layouts[layout_index].differs_from(
+                        &layout_head_to_query_head,
+                        &current_layout,
+                        &self.args.managed_properties,
+                    ) =>
+            {
+                info!(
+                    layout = self.layout_label(layout_index),
+                    action = "enforce",
+                    "Layout {} drifted, re-applying",
+                    self.describe_layout(layout_index)
+                );
+                self.apply_layout(
+                    layout_index,
+                    layout_head_to_query_head,
+                    proxy,
+                    qhandle,
+                    serial,
+                );
+            }
+            (Some((layout_index, _, _)), DoneAction::Update)
+                if self.args.read_only && self.args.command.is_none() =>
+            {
+                info!(
+                    layout = self.layout_label(layout_index),
+                    "Not updating layout {} since wl-distore is read-only",
+                    self.describe_layout(layout_index)
+                );
+            }
+            (Some((layout_index, _, _)), DoneAction::Update)
+                if self.layout_data.layouts[layout_index].read_only =>
+            {
+                info!(
+                    layout = self.layout_label(layout_index),
+                    "Not updating layout {} since it is read-only",
+                    self.describe_layout(layout_index)
+                );
+                if matches!(self.args.command, Some(Command::SaveCurrent { .. })) {
+                    eprintln!("The matching layout is read-only, so it can't be saved");
+                    std::process::exit(1);
+                }
+            }
+            (Some((layout_index, _, _)), DoneAction::Update)
+                if self.layout_data.layouts[layout_index].pinned && self.args.command.is_none() =>
+            {
+                info!(
+                    layout = self.layout_label(layout_index),
+                    "Not updating layout {} since it is pinned",
+                    self.describe_layout(layout_index)
+                );
+            }
+            (Some((layout_index, _, layout_head_to_query_head)), DoneAction::Update)
+                if self
+                    .layout_data
+                    .saves_as_new_layout(&self.layout_data.layouts[layout_index])
+                    && self.args.command.is_none()
+                    && !self.layout_data.layouts[layout_index].differs_from(
+                        &layout_head_to_query_head,
+                        &current_layout,
+                        &self.args.managed_properties,
+                    ) =>
+            {
+                debug!(
+                    layout = self.layout_label(layout_index),
+                    "Not saving the configuration as a new layout since it is the same as layout {}",
+                    self.describe_layout(layout_index)
+                );
+            }
+            (Some((layout_index, _, layout_head_to_query_head)), DoneAction::Update) => {
+                let outcome = if self
+                    .layout_data
+                    .saves_as_new_layout(&self.layout_data.layouts[layout_index])
+                {
+                    SaveOutcome::Created
+                } else {
+                    SaveOutcome::Updated
+                };
+                let index =
+                    self.update_layout(layout_index, &layout_head_to_query_head, current_layout);
+                if matches!(self.args.command, Some(Command::SaveCurrent { .. })) {
+                    // Bail out after the save.
+                    print_save_result(outcome, index, &self.layout_data);
+                    std::process::exit(0);
+                }
+            }
+            (Some((layout_index, score, layout_head_to_query_head)), DoneAction::Apply) => {
+                if score != LayoutMatchScore::Exact {
+                    self.stats.fuzzy_matches += 1;
+                }
+                info!(
+                    layout = self.layout_label(layout_index),
+                    action = "apply",
+                    "Apply layout {}",
+                    self.describe_layout(layout_index)
+                );
+                self.apply_layout(
+                    layout_index,
+                    layout_head_to_query_head,
+                    proxy,
+                    qhandle,
+                    serial,
+                );
+            }
+            (Some(_), DoneAction::ApplyResult) => {
+                debug!("Ignored the Done event since this is the result of an Apply");
+            }
+            (_, DoneAction::Retry) => {
+                debug!("Ignored the Done event since applying will be retried");
+            }
+            (_, DoneAction::Settle) => {
+                debug!("Ignored the Done event since the heads are settling");
+            }
+        }
+    }
+
+    /// Waits until the heads have stopped changing for `apply_settle` before applying their layout,
+    /// since docks may connect their displays one at a time. Returns true if the Done event should
+    /// be ignored in the meantime.
+    fn settle_heads(&mut self, qhandle: &wayland_client::QueueHandle<Self>) -> bool {
+        if self.args.apply_settle.is_zero() || self.args.command.is_some() || !self.args.apply {
+            return false;
+        }
+        match self.done_action {
+            DoneAction::Settle => return true,
+            DoneAction::Apply => {}
+            _ => return false,
+        }
+        let Some(loop_handle) = self.loop_handle.as_ref() else {
+            return false;
+        };
+        // The heads changed again, so start waiting over.
+        if let Some(token) = self.settle_timer.take() {
+            loop_handle.remove(token);
+        }
+        let qhandle = qhandle.clone();
+        let timer = loop_handle.insert_source(
+            Timer::from_duration(self.args.apply_settle),
+            move |_, _, state| {
+                state.settle_timer = None;
+                if matches!(state.done_action, DoneAction::Settle) {
+                    if let Some((proxy, serial)) = state.output_manager.clone() {
+                        debug!("The heads have settled");
+                        state.done_action = DoneAction::Apply;
+                        let current_layout = state.current_layout();
+                        state.handle_heads(current_layout, &proxy, &qhandle, serial);
+                    }
+                }
+                TimeoutAction::Drop
+            },
+        );
+        let Ok(token) = timer else {
+            return false;
+        };
+        debug!(
+            "Waiting {:?} for the heads to settle before applying",
+            self.args.apply_settle
+        );
+        self.settle_timer = Some(token);
+        self.done_action = DoneAction::Settle;
+        true
     }
-}
 
-struct AppData {
-    args: Args,
-
-    partial_objects: PartialObjects,
-    id_to_head: HashMap<ObjectId, HeadState>,
-    head_identity_to_id: HashMap<HeadIdentity, ObjectId>,
-    id_to_mode: HashMap<ObjectId, ModeState>,
-    done_action: DoneAction,
-    layout_data: LayoutData,
-}
+    /// Schedules applying the layout matching the current heads again after a failed or cancelled
+    /// attempt, waiting longer after each consecutive one. Returns false if there are no retries
+    /// left.
+    fn retry_apply(&mut self, qhandle: &wayland_client::QueueHandle<Self>) -> bool {
+        self.failed_applies += 1;
+        let attempts = self.args.apply_retries + 1;
+        if self.failed_applies >= attempts {
+            error!("Failed to apply the layout after {attempts} attempts");
+            self.failed_applies = 0;
+            return false;
+        }
+        let delay = self
+            .args
+            .apply_retry_delay
+            .saturating_mul(1 << (self.failed_applies - 1).min(16));
+        warn!(
+            "Retrying to apply the layout in {delay:?} (attempt {} of {attempts})",
+            self.failed_applies + 1
+        );
+        self.done_action = DoneAction::Retry;
+        let qhandle = qhandle.clone();
+        let timer = self.loop_handle.as_ref().map(|loop_handle| {
+            loop_handle.insert_source(Timer::from_duration(delay), move |_, _, state| {
+                // The heads may have changed in the meantime, in which case the next Done event
+                // applies their layout instead.
+                if matches!(state.done_action, DoneAction::Retry) {
+                    state.done_action = DoneAction::Apply;
+                    state.apply_matching_layout(&qhandle);
+                }
+                TimeoutAction::Drop
+            })
+        });
+        if !matches!(timer, Some(Ok(_))) {
+            // Without a timer, try again on the next Done event.
+            self.done_action = DoneAction::Apply;
+        }
+        true
+    }
 
-#[derive(Default, Clone, Copy)]
-enum DoneAction {
-    /// Update the layout for the current head setup.
-    #[default]
-    Update,
-    /// Apply the layout for the current head setup.
-    Apply,
-    /// The next Done events corresponds to the result of an Apply action, so ignore it.
-    ApplyResult,
-}
+    /// The mode to give `head` when there is no saved one: its preferred mode if there is one, and
+    /// otherwise its largest mode.
+    fn default_mode(&self, head: &Head) -> Option<Mode> {
+        let modes = head
+            .mode_to_id
+            .values()
+            .filter_map(|id| self.id_to_mode.get(id))
+            .collect::<Vec<_>>();
+        let candidates = if modes.iter().any(|mode_state| mode_state.preferred) {
+            modes
+                .into_iter()
+                .filter(|mode_state| mode_state.preferred)
+                .collect::<Vec<_>>()
+        } else {
+            let largest_area = modes
+                .iter()
+                .map(|mode_state| mode_state.mode.size.0 as u64 * mode_state.mode.size.1 as u64)
+                .max();
+            modes
+                .into_iter()
+                .filter(|mode_state| {
+                    Some(mode_state.mode.size.0 as u64 * mode_state.mode.size.1 as u64)
+                        == largest_area
+                })
+                .collect()
+        };
+        pick_mode(
+            candidates.into_iter(),
+            None,
+            self.args.mode_preference_for(&head.identity),
+        )
+        .map(|mode_state| mode_state.mode)
+    }
 
-impl AppData {
-    fn new(args: Args) -> Result<Self, std::io::Error> {
-        Ok(Self {
-            partial_objects: Default::default(),
-            id_to_head: Default::default(),
-            head_identity_to_id: Default::default(),
-            id_to_mode: Default::default(),
-            done_action: Default::default(),
-            layout_data: LayoutData::load(&args.layouts)?,
-            // Move after we load the layout data.
-            args,
-        })
+    /// Applies the layout matching the heads that layouts have seen before, configuring the heads
+    /// that none have seen with `defaults` (placed to the right of the other heads). Returns false
+    /// if there are no such new heads, or the other heads have no layout.
+    fn apply_with_new_heads(
+        &mut self,
+        defaults: &NewHeadDefaults,
+        output_manager: &ZwlrOutputManagerV1,
+        qhandle: &wayland_client::QueueHandle<Self>,
+        serial: u32,
+    ) -> bool {
+        let (new_heads, known_heads): (Vec<_>, Vec<_>) = self
+            .head_identity_to_id
+            .keys()
+            .cloned()
+            .partition(|identity| !self.layout_data.knows_head(identity));
+        if new_heads.is_empty() || known_heads.is_empty() {
+            return false;
+        }
+        let Some((index, _, layout_head_to_query_head)) = self
+            .layout_data
+            .find_layout_to_apply(&known_heads.into_iter().collect())
+        else {
+            return false;
+        };
+        let layout = &self.layout_data.layouts[index];
+        let mut query_head_to_configuration = layout
+            .heads
+            .iter()
+            .map(|(identity, configuration)| {
+                let identity = layout_head_to_query_head.get(identity).unwrap_or(identity);
+                (identity.clone(), configuration.clone())
+            })
+            .collect::<HashMap<_, _>>();
+        self.place_heads(&mut query_head_to_configuration, new_heads, |head| {
+            info!(
+                head = head.identity.name,
+                "Configuring new head \"{}\" with the defaults", head.identity.description
+            );
+            defaults.enabled.then(|| SavedConfiguration {
+                mode: self.default_mode(head),
+                position: (0, 0),
+                transform: Transform::Normal,
+                scale: defaults.scale,
+                adaptive_sync: None,
+                dont_care: Default::default(),
+            })
+        });
+        info!(
+            layout = self.layout_label(index),
+            action = "apply",
+            "Apply layout {} along with new heads",
+            self.describe_layout(index)
+        );
+        // The heads no longer match this layout, so it isn't recorded as applied. The result is
+        // saved as a new layout once it is applied.
+        self.applying_layout = None;
+        self.pending_head_commands =
+            layout.resolve_head_commands(&layout_head_to_query_head, |_| true);
+        self.apply_configurations(query_head_to_configuration, output_manager, qhandle, serial);
+        true
     }
 
-    fn save_layouts(&self) {
-        self.layout_data
-            .save(&self.args.layouts)
-            .expect("Failed to save layouts");
+    /// Applies a minimal configuration that should always work: a single head enabled at its
+    /// preferred mode at the origin with no scaling, and every other head (except ignored ones)
+    /// disabled.
+    fn apply_safe_mode(&mut self, qhandle: &wayland_client::QueueHandle<Self>) {
+        let Some((output_manager, serial)) = self.output_manager.clone() else {
+            return;
+        };
+        // Prefer heads that are already enabled, since those are likely to be usable.
+        let Some(head) = self
+            .id_to_head
+            .values()
+            .map(|head_state| &head_state.head)
+            .filter(|head| !self.args.is_ignored(&head.identity))
+            .min_by_key(|head| (head.configuration.is_none(), &head.identity.name))
+        else {
+            return;
+        };
+        let preferred_mode = self.default_mode(head);
+        error!(
+            "Falling back to a safe configuration with only \"{}\" enabled",
+            head.identity.description
+        );
+        let query_head_to_configuration = self
+            .head_identity_to_id
+            .keys()
+            .map(|identity| {
+                let configuration = (*identity == head.identity).then_some(SavedConfiguration {
+                    mode: preferred_mode,
+                    position: (0, 0),
+                    transform: Transform::Normal,
+                    scale: 1.0,
+                    adaptive_sync: None,
+                    dont_care: Default::default(),
+                });
+                (identity.clone(), configuration)
+            })
+            .collect();
+        self.applying_safe_mode = true;
+        self.applying_layout = None;
+        self.pending_head_commands.clear();
+        self.apply_configurations(
+            query_head_to_configuration,
+            &output_manager,
+            qhandle,
+            serial,
+        );
     }
 
     /// Applies the layout at `index`. `serial` is the serial value provided from the most recent
@@ -114,69 +2548,357 @@ impl AppData {
         qhandle: &wayland_client::QueueHandle<Self>,
         serial: u32,
     ) {
+        self.applying_layout = Some(index);
+        // Heads of the layout that are missing (when it matched only some of the heads, see
+        // `LayoutData::find_partial_layout_match`) are left out.
+        let connected = |identity: &HeadIdentity| self.head_identity_to_id.contains_key(identity);
+        self.pending_head_commands = self.layout_data.layouts[index]
+            .resolve_head_commands(&layout_head_to_query_head, connected);
+        let mut query_head_to_configuration = self.layout_data.layouts[index]
+            .heads
+            .iter()
+            .map(|(identity, configuration)| {
+                // See if the layout head needs to be remapped to a query head, falling back to the
+                // identity on failure.
+                let identity = layout_head_to_query_head.get(identity).unwrap_or(identity);
+                (identity.clone(), configuration.clone())
+            })
+            .filter(|(identity, _)| connected(identity))
+            .collect::<HashMap<_, _>>();
+        self.place_extra_heads(&mut query_head_to_configuration);
+        if self.layout_data.lid == Some(LidState::Closed)
+            && self.layout_data.layouts[index].lid.is_none()
+        {
+            disable_internal_heads(&mut query_head_to_configuration);
+        }
+        self.apply_configurations(query_head_to_configuration, output_manager, qhandle, serial);
+    }
+
+    /// Moves the enabled heads missing from `query_head_to_configuration` (when a layout matched
+    /// only some of the heads, see [`LayoutData::find_partial_layout_match`]) to the right of the
+    /// heads in it, so they don't overlap. They otherwise keep their current configuration.
+    fn place_extra_heads(
+        &self,
+        query_head_to_configuration: &mut HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    ) {
+        let extra_heads = self
+            .head_identity_to_id
+            .keys()
+            .filter(|identity| !query_head_to_configuration.contains_key(identity))
+            .cloned()
+            .collect();
+        self.place_heads(query_head_to_configuration, extra_heads, |head| {
+            let configuration = head.configuration.as_ref()?;
+            info!(
+                head = head.identity.name,
+                "Placing head \"{}\" next to the layout", head.identity.description
+            );
+            Some(SavedConfiguration::from_config(
+                configuration,
+                &self.id_to_mode,
+                Property::ALL,
+            ))
+        });
+    }
+
+    /// Adds `heads` to `query_head_to_configuration` with the configuration `configure` returns
+    /// for them, placing the enabled ones left to right (sorted by name) to the right of the heads
+    /// already in it, so they don't overlap.
+    fn place_heads(
+        &self,
+        query_head_to_configuration: &mut HashMap<HeadIdentity, Option<SavedConfiguration>>,
+        mut heads: Vec<HeadIdentity>,
+        configure: impl Fn(&Head) -> Option<SavedConfiguration>,
+    ) {
+        if heads.is_empty() {
+            return;
+        }
+        let mut right = query_head_to_configuration
+            .values()
+            .flatten()
+            .filter_map(|configuration| {
+                logical_size(configuration)
+                    .map(|(width, _)| configuration.position.0 + width.round() as u32)
+            })
+            .max()
+            .unwrap_or(0);
+        heads.sort_by(|a, b| a.name.cmp(&b.name));
+        for identity in heads {
+            let head = &self.id_to_head[&self.head_identity_to_id[&identity]].head;
+            let mut configuration = configure(head);
+            if let Some(configuration) = configuration.as_mut() {
+                configuration.position = (right, 0);
+                if let Some((width, _)) = logical_size(configuration) {
+                    right += width.round() as u32;
+                }
+            }
+            query_head_to_configuration.insert(identity, configuration);
+        }
+    }
+
+    /// Applies `query_head_to_configuration` to the current heads. Any head missing from
+    /// `query_head_to_configuration` keeps its current configuration. `serial` is the serial value
+    /// provided from the most recent `Done` event.
+    fn apply_configurations(
+        &mut self,
+        query_head_to_configuration: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+        output_manager: &ZwlrOutputManagerV1,
+        qhandle: &wayland_client::QueueHandle<Self>,
+        serial: u32,
+    ) {
+        for identity in query_head_to_configuration.keys() {
+            assert!(
+                self.head_identity_to_id.contains_key(identity),
+                "Could not find head for matched layout"
+            );
+        }
+
         self.done_action = DoneAction::ApplyResult;
-        let identity_to_configuration = &self.layout_data.layouts[index];
-        let new_configuration = output_manager.create_configuration(serial, qhandle, ());
-        for (identity, configuration) in identity_to_configuration.iter() {
-            // See if the layout head needs to be remapped to a query head, falling back to the
-            // identity on failure.
-            let identity = layout_head_to_query_head.get(identity).unwrap_or(identity);
-
-            let id = self
-                .head_identity_to_id
-                .get(identity)
-                .expect("Could not find head for matched layout");
-
-            let head_state = &self
-                .id_to_head
-                .get(&id)
-                .expect("Could not find proxy for id");
-
-            match configuration.as_ref() {
-                None => {
+        let new_configuration = (!self.args.dry_run).then(|| {
+            self.live_configurations += 1;
+            output_manager.create_configuration(serial, qhandle, serial)
+        });
+        for head_state in self.id_to_head.values() {
+            // The protocol requires every head to be configured, so heads we don't want to touch
+            // are given their current configuration.
+            let current = head_state.head.configuration.as_ref().map(|configuration| {
+                SavedConfiguration::from_config(configuration, &self.id_to_mode, Property::ALL)
+            });
+            let configuration = match query_head_to_configuration.get(&head_state.head.identity) {
+                Some(configuration) => configuration.clone(),
+                None => current.clone(),
+            };
+            // Unless it is managed, heads stay enabled or disabled as they are.
+            let configuration = match (configuration, current) {
+                (_, None) if !self.args.manages(Property::Enabled) => None,
+                (None, Some(current)) if !self.args.manages(Property::Enabled) => Some(current),
+                (configuration, _) => configuration,
+            };
+
+            let name = &head_state.head.identity.name;
+            match (configuration, new_configuration.as_ref()) {
+                (None, Some(new_configuration)) => {
                     new_configuration.disable_head(&head_state.proxy);
                 }
-                Some(configuration) => {
-                    let mut new_configuration_head =
-                        new_configuration.enable_head(&head_state.proxy, qhandle, ());
-                    configuration.apply(
-                        &mut new_configuration_head,
-                        &head_state.head.mode_to_id,
-                        &self.id_to_mode,
+                (None, None) => info!("Would disable \"{name}\""),
+                (Some(mut configuration), new_configuration) => {
+                    validate_scale(
+                        &head_state.head.identity,
+                        &mut configuration,
+                        &self.scale_quirks,
+                        self.args.snap_scales,
                     );
+                    let mode_preference = self.args.mode_preference_for(&head_state.head.identity);
+                    match new_configuration {
+                        Some(new_configuration) => configuration.apply(
+                            &mut new_configuration.enable_head(&head_state.proxy, qhandle, ()),
+                            &head_state.head.mode_to_id,
+                            &self.id_to_mode,
+                            mode_preference,
+                            &self.args.managed_properties,
+                        ),
+                        None => {
+                            info!("Would enable \"{name}\"");
+                            configuration.apply(
+                                &mut DryRunHead { name },
+                                &head_state.head.mode_to_id,
+                                &self.id_to_mode,
+                                mode_preference,
+                                &self.args.managed_properties,
+                            );
+                        }
+                    }
                 }
             }
         }
-        new_configuration.apply();
+        match new_configuration {
+            Some(new_configuration) => new_configuration.apply(),
+            None => self.finish_dry_run(),
+        }
+    }
+
+    /// Logs the commands that applying the configuration would have run, and goes back to updating
+    /// as if it had been applied. The one-shot subcommands exit.
+    fn finish_dry_run(&mut self) {
+        info!("Would apply the configuration");
+        for command in std::mem::take(&mut self.pending_head_commands) {
+            info!("Would run \"{command}\"");
+        }
+        if let Some(hook) = self.args.hooks.on_apply_success.as_ref() {
+            info!("Would run \"{hook}\"");
+        }
+        self.done_action = DoneAction::Update;
+        self.applying_layout = None;
+        self.applying_safe_mode = false;
+        if self.exits_after_applying() {
+            std::process::exit(0);
+        }
+    }
+
+    /// Whether this is a one-shot subcommand that exits once it has applied a layout.
+    fn exits_after_applying(&self) -> bool {
+        matches!(
+            self.args.command,
+            Some(
+                Command::Apply { .. }
+                    | Command::ApplyFile { .. }
+                    | Command::Switch { .. }
+                    | Command::Arrange
+            )
+        )
+    }
+
+    /// Finds the index of the layout referred to by `selector` (a name or index) for the `apply`
+    /// subcommand. Exits if there is no such layout.
+    fn find_layout_or_exit(&self, selector: &str) -> usize {
+        let Some(index) = self.layout_data.find_layout(selector) else {
+            eprintln!(
+                "No layout is named \"{selector}\", and it is not a valid index (there are {} layouts)",
+                self.layout_data.layouts.len()
+            );
+            std::process::exit(1);
+        };
+        index
+    }
+
+    /// Finds the index of the layout matching the current heads for the `apply` subcommand, like
+    /// the daemon would. Exits with status 2 if no layout matches (or 0 for `--apply-and-exit`).
+    fn find_matching_layout_or_exit(&self) -> usize {
+        let Some((index, _, _)) = self
+            .layout_data
+            .find_layout_to_apply(&self.head_identity_to_id.keys().cloned().collect())
+        else {
+            eprintln!("No layout matches the current heads");
+            std::process::exit(if self.args.apply_and_exit { 0 } else { 2 });
+        };
+        index
+    }
+
+    /// Finds the index of the layout of `profile` matching the current heads for the `switch`
+    /// subcommand. Exits with status 2 if the profile has no such layout.
+    fn find_profile_layout_or_exit(&mut self, profile: &str) -> usize {
+        let heads = self.head_identity_to_id.keys().cloned().collect();
+        self.layout_data.active_profile = Some(profile.to_string());
+        self.layout_data.choose_profile(&heads, profile);
+        match self.layout_data.find_layout_to_apply(&heads) {
+            Some((index, _, _)) if self.layout_data.layouts[index].profile() == profile => index,
+            _ => {
+                eprintln!("Profile \"{profile}\" has no layout for the current heads");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// Applies the layout at `index` for the `apply` subcommand (or the layout from the file for
+    /// the `apply-file` subcommand if [`None`]), restricted to the heads named in `only` (if
+    /// provided). Exits if the layout cannot be applied.
+    fn apply_layout_one_shot(
+        &mut self,
+        index: Option<usize>,
+        only: Option<&[String]>,
+        output_manager: &ZwlrOutputManagerV1,
+        qhandle: &wayland_client::QueueHandle<Self>,
+        serial: u32,
+    ) {
+        let (layout, label) = match index {
+            Some(index) => (&self.layout_data.layouts[index], format!("layout {index}")),
+            None => (
+                self.file_layout
+                    .as_ref()
+                    .expect("The layout file is read before connecting"),
+                "the layout file".to_string(),
+            ),
+        };
+
+        let layout_head_to_query_head = map_layout_heads(
+            layout,
+            &self.head_identity_to_id.keys().cloned().collect(),
+            &self.layout_data.match_fields,
+        );
+        let query_head_to_configuration = layout
+            .heads
+            .iter()
+            .filter_map(|(identity, configuration)| {
+                layout_head_to_query_head
+                    .get(identity)
+                    .map(|query_head| (query_head.clone(), configuration.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let query_head_to_configuration = match only {
+            None => {
+                for identity in layout.heads.keys() {
+                    if !layout_head_to_query_head.contains_key(identity) {
+                        eprintln!(
+                            "Head \"{}\" from {label} is not connected",
+                            identity.description
+                        );
+                        std::process::exit(1);
+                    }
+                }
+                query_head_to_configuration
+            }
+            Some(only) => {
+                let mut filtered = HashMap::new();
+                for name in only {
+                    let Some((identity, configuration)) = query_head_to_configuration
+                        .iter()
+                        .find(|(identity, _)| &identity.name == name)
+                    else {
+                        eprintln!("Head \"{name}\" is not connected or not part of {label}");
+                        std::process::exit(1);
+                    };
+                    filtered.insert(identity.clone(), configuration.clone());
+                }
+                filtered
+            }
+        };
+
+        info!(
+            "Apply {label} to heads: {:?}",
+            query_head_to_configuration
+                .keys()
+                .map(|head_identity| head_identity.name.as_str())
+                .collect::<HashSet<_>>()
+        );
+        self.applying_layout = index;
+        self.pending_head_commands = layout
+            .resolve_head_commands(&layout_head_to_query_head, |identity| {
+                query_head_to_configuration.contains_key(identity)
+            });
+        self.apply_configurations(query_head_to_configuration, output_manager, qhandle, serial);
     }
 }
 
 impl Dispatch<WlRegistry, ()> for AppData {
     fn event(
-        _state: &mut Self,
+        state: &mut Self,
         proxy: &WlRegistry,
         event: wl_registry::Event,
         _data: &(),
         _conn: &Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        match event {
-            wl_registry::Event::Global {
-                name,
-                interface,
-                version,
-            } => match &interface[..] {
-                "zwlr_output_manager_v1" => {
-                    proxy.bind::<zwlr_output_manager_v1::ZwlrOutputManagerV1, _, _>(
-                        name,
-                        version,
-                        qhandle,
-                        (),
-                    );
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            if state.layout_data.compositor.is_none() {
+                if let Some(compositor) = compositor::from_global(&interface) {
+                    debug!("Detected the compositor {compositor} from the {interface} global");
+                    state.layout_data.compositor = Some(compositor.to_string());
                 }
-                _ => {}
-            },
-            _ => {}
+            }
+            if interface == "zwlr_output_manager_v1" {
+                proxy.bind::<zwlr_output_manager_v1::ZwlrOutputManagerV1, _, _>(
+                    name,
+                    version,
+                    qhandle,
+                    (),
+                );
+            }
         }
     }
 }
@@ -193,6 +2915,7 @@ impl Dispatch<ZwlrOutputManagerV1, ()> for AppData {
         debug!("Received Manager event: {event:?}");
         let serial = match event {
             zwlr_output_manager_v1::Event::Head { head } => {
+                state.stats.heads_added += 1;
                 // A new head was added, so try to apply a layout on the next `Done` event.
                 state.done_action = DoneAction::Apply;
                 state.partial_objects.id_to_head.insert(
@@ -205,8 +2928,29 @@ impl Dispatch<ZwlrOutputManagerV1, ()> for AppData {
                 return;
             }
             zwlr_output_manager_v1::Event::Done { serial } => serial,
+            zwlr_output_manager_v1::Event::Finished => {
+                // The compositor has destroyed the manager, so nothing we hold is useful anymore.
+                error!("The output manager was finished by the compositor");
+                let mode_ids = state
+                    .id_to_mode
+                    .keys()
+                    .chain(state.partial_objects.id_to_mode.keys())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                state.release_modes(mode_ids);
+                for (_, head) in state.id_to_head.drain() {
+                    head.proxy.release();
+                }
+                for (_, head) in state.partial_objects.id_to_head.drain() {
+                    head.proxy.release();
+                }
+                state.head_identity_to_id.clear();
+                state.output_manager = None;
+                return;
+            }
             _ => return,
         };
+        state.output_manager = Some((proxy.clone(), serial));
         for (id, partial_mode) in state.partial_objects.id_to_mode.drain() {
             let mode_proxy = partial_mode.proxy.clone();
             let mode = match partial_mode.try_into() {
@@ -221,97 +2965,156 @@ impl Dispatch<ZwlrOutputManagerV1, ()> for AppData {
             };
             state.id_to_mode.insert(id, mode);
         }
-        for (id, partial_head) in state.partial_objects.id_to_head.drain() {
+        let partial_heads = state.partial_objects.id_to_head.drain().collect::<Vec<_>>();
+        for (id, partial_head) in partial_heads {
             match state.id_to_head.entry(id.clone()) {
                 Entry::Vacant(entry) => {
-                    let head: HeadState =
-                        HeadState::create_from_partial(partial_head, &state.id_to_mode)
-                            .expect("Done is called, so the partial head should be well-defined");
-                    assert!(
-                        state
-                            .head_identity_to_id
-                            .insert(head.head.identity.clone(), id)
-                            .is_none(),
-                        "Head identities should be unique."
+                    let proxy = partial_head.proxy.clone();
+                    let mode_ids = partial_head.head.modes.clone();
+                    let read_edid = state
+                        .layout_data
+                        .match_fields
+                        .0
+                        .contains(&IdentityField::Edid);
+                    let head = match HeadState::create_from_partial(
+                        partial_head,
+                        &state.id_to_mode,
+                        read_edid,
+                    ) {
+                        Ok(head) => head,
+                        Err(err) => {
+                            // Done is called, so the head should be well-defined. If the
+                            // compositor got it wrong, give up on the head rather than keeping its
+                            // objects around forever.
+                            error!("Failed to create head {id:?}: {err}");
+                            state.release_modes(mode_ids);
+                            proxy.release();
+                            continue;
+                        }
+                    };
+                    info!(
+                        head = head.head.identity.name,
+                        action = "head-added",
+                        "Head added: \"{}\"",
+                        head.head.identity.description
                     );
+                    // Ignored heads are still tracked (so applying leaves them as they are), but
+                    // are left out of matching.
+                    if state.args.is_ignored(&head.head.identity) {
+                        info!("Ignoring head \"{}\"", head.head.identity.description);
+                    } else {
+                        assert!(
+                            state
+                                .head_identity_to_id
+                                .insert(head.head.identity.clone(), id)
+                                .is_none(),
+                            "Head identities should be unique."
+                        );
+                    }
                     entry.insert(head);
                 }
                 Entry::Occupied(mut entry) => {
-                    entry
+                    if let Err(err) = entry
                         .get_mut()
                         .head
                         .apply_partial(partial_head.head, &state.id_to_mode)
-                        .expect("Failed to apply partial to existing head.");
+                    {
+                        error!("Failed to apply partial to existing head {id:?}: {err}");
+                    }
                 }
             }
         }
 
-        let current_layout = state
+        let watched_heads = state
             .id_to_head
             .values()
             .map(|head| {
                 (
                     head.head.identity.clone(),
-                    head.head.configuration.as_ref().map(|configuration| {
-                        SavedConfiguration::from_config(&configuration, &state.id_to_mode)
-                    }),
+                    WatchedHead {
+                        configuration: head.head.configuration.as_ref().map(|configuration| {
+                            SavedConfiguration::from_config(
+                                configuration,
+                                &state.id_to_mode,
+                                Property::ALL,
+                            )
+                        }),
+                        mode_count: head.head.mode_to_id.len(),
+                    },
                 )
             })
-            .collect::<HashMap<_, _>>();
-        let layout_match = state
-            .layout_data
-            .find_layout_match(&(current_layout.keys().cloned().collect()));
-        match (
-            layout_match,
-            // If save_and_exit is set, then we don't want to apply the layout at all.
-            if state.args.save_and_exit {
-                DoneAction::Update
-            } else {
-                state.done_action
-            },
-        ) {
-            (None, DoneAction::Update | DoneAction::Apply) => {
-                info!(
-                    "Saved layout: {:?}",
-                    current_layout
-                        .keys()
-                        .map(|head_identity| head_identity.description.as_str())
-                        .collect::<HashSet<_>>()
-                );
-                state.layout_data.layouts.push(current_layout);
-                state.save_layouts();
-                if state.args.save_and_exit {
-                    // Bail out after the save.
-                    std::process::exit(0);
+            .collect();
+        if matches!(state.args.command, Some(Command::Watch)) {
+            watch::print_changes(serial, &state.watched_heads, &watched_heads);
+            state.watched_heads = watched_heads;
+            return;
+        }
+        for event in watch::events(&state.watched_heads, &watched_heads) {
+            match &event {
+                Event::HeadRemoved { head } => {
+                    state.disconnected_heads.insert(head.clone());
                 }
-                // Ensure we go back to updating.
-                state.done_action = DoneAction::Update;
+                Event::HeadAdded { head, .. } if state.disconnected_heads.remove(head) => {
+                    state.stats.reconnects += 1;
+                }
+                _ => {}
             }
-            (None, DoneAction::ApplyResult) => {
-                panic!("We applied a layout, but then that layout didn't match?");
+            state.publish(event);
+        }
+        state.watched_heads = watched_heads;
+
+        if let Some(Command::Apply { layout, only, .. }) = state.args.command.clone() {
+            if !matches!(state.done_action, DoneAction::ApplyResult) {
+                let index = match layout {
+                    Some(selector) => state.find_layout_or_exit(&selector),
+                    None => state.find_matching_layout_or_exit(),
+                };
+                state.apply_layout_one_shot(Some(index), only.as_deref(), proxy, qhandle, serial);
             }
-            (Some((layout_index, _)), DoneAction::Update) => {
-                info!(
-                    "Update layout: {:?}",
-                    current_layout
-                        .keys()
-                        .map(|head_identity| head_identity.description.as_str())
-                        .collect::<HashSet<_>>()
-                );
-                state.layout_data.layouts[layout_index] = current_layout;
-                state.save_layouts();
-                if state.args.save_and_exit {
-                    // Bail out after the save.
-                    std::process::exit(0);
-                }
+            return;
+        }
+
+        if let Some(Command::Switch { profile }) = state.args.command.clone() {
+            if !matches!(state.done_action, DoneAction::ApplyResult) {
+                let index = state.find_profile_layout_or_exit(&profile);
+                state.apply_layout_one_shot(Some(index), None, proxy, qhandle, serial);
+            }
+            return;
+        }
+
+        if let Some(Command::ApplyFile { .. }) = state.args.command {
+            if !matches!(state.done_action, DoneAction::ApplyResult) {
+                state.apply_layout_one_shot(None, None, proxy, qhandle, serial);
+            }
+            return;
+        }
+
+        if let Some(Command::Arrange) = state.args.command {
+            if !matches!(state.done_action, DoneAction::ApplyResult) {
+                state.arrange_heads(proxy, qhandle, serial);
             }
-            (Some((layout_index, layout_head_to_query_head)), DoneAction::Apply) => {
+            return;
+        }
+
+        if state.suspended {
+            debug!("Ignored the Done event since the machine is suspended");
+            return;
+        }
+
+        if std::mem::take(&mut state.startup) && matches!(state.done_action, DoneAction::Apply) {
+            // On startup, the heads are usually a set we already know, so apply its layout right
+            // away instead of waiting for the heads to settle and snapshotting the current
+            // configuration.
+            let heads = state.head_identity_to_id.keys().cloned().collect();
+            let layout_match = state.layout_data.find_layout_to_apply(&heads);
+            if let Some(Some((layout_index, _, layout_head_to_query_head))) =
+                state.pick_layout_match(&heads, layout_match)
+            {
                 info!(
-                    "Apply layout: {:?}",
-                    state.layout_data.layouts[layout_index]
-                        .keys()
-                        .map(|head_identity| head_identity.description.as_str())
-                        .collect::<HashSet<_>>()
+                    layout = state.layout_label(layout_index),
+                    action = "apply",
+                    "Apply layout {} on startup",
+                    state.describe_layout(layout_index)
                 );
                 state.apply_layout(
                     layout_index,
@@ -320,11 +3123,114 @@ impl Dispatch<ZwlrOutputManagerV1, ()> for AppData {
                     qhandle,
                     serial,
                 );
+                return;
             }
-            (Some(_), DoneAction::ApplyResult) => {
-                debug!("Ignored the Done event since this is the result of an Apply");
+        }
+
+        if state.settle_heads(qhandle) {
+            return;
+        }
+
+        let current_layout = state.current_layout();
+
+        if let Some(Command::Current) = state.args.command {
+            let layout = Layout::new(current_layout);
+            println!(
+                "{}",
+                layout
+                    .to_json()
+                    .expect("Failed to serialize the current layout")
+            );
+            std::process::exit(0);
+        }
+
+        if let Some(Command::Which) = state.args.command {
+            let layout_match = state
+                .layout_data
+                .find_layout_to_apply(&(current_layout.keys().cloned().collect()));
+            let Some((index, score, layout_head_to_query_head)) = layout_match else {
+                eprintln!("No layout matches the current heads");
+                std::process::exit(2);
+            };
+            cli::print_match(
+                state.args.format,
+                &state.layout_data,
+                index,
+                score,
+                &layout_head_to_query_head,
+            );
+            std::process::exit(0);
+        }
+
+        if let Some(Command::Export { to, .. }) = state.args.command {
+            let layout_match = state
+                .layout_data
+                .find_layout_to_apply(&(current_layout.keys().cloned().collect()));
+            let Some((index, _, _)) = layout_match else {
+                eprintln!("No layout matches the current heads");
+                std::process::exit(2);
+            };
+            cli::print_export(to, &state.layout_data, index);
+            std::process::exit(0);
+        }
+
+        if let Some(Command::Forget) = state.args.command {
+            let layout_match = state
+                .layout_data
+                .find_layout_match(&(current_layout.keys().cloned().collect()));
+            let Some((index, _, _)) = layout_match else {
+                eprintln!("No layout matches the current heads");
+                std::process::exit(2);
+            };
+            if state.layout_data.layouts[index].read_only {
+                eprintln!("Layout {index} is read-only, so it can't be forgotten");
+                std::process::exit(1);
+            }
+            let layout = state.layout_data.layouts.remove(index);
+            state.save_layouts(None);
+            match layout.name {
+                Some(name) => println!("Forgot layout {index} (\"{name}\")"),
+                None => println!("Forgot layout {index}"),
+            }
+            std::process::exit(0);
+        }
+
+        if let Some(Command::Diff) = state.args.command {
+            let layout_match = state
+                .layout_data
+                .find_layout_match(&(current_layout.keys().cloned().collect()));
+            let Some((index, _, layout_head_to_query_head)) = layout_match else {
+                eprintln!("No layout matches the current heads");
+                std::process::exit(2);
+            };
+            cli::print_diff(
+                state.args.format,
+                &state.layout_data.layouts[index],
+                &layout_head_to_query_head,
+                &current_layout,
+            );
+            std::process::exit(0);
+        }
+
+        if let Some(Command::Init) = state.args.command {
+            let result =
+                init::run_wizard(&state.args.config_path, &state.args.layouts, current_layout);
+            if let Err(err) = result {
+                eprintln!("Failed to set up wl-distore: {err}");
+                std::process::exit(1);
             }
+            std::process::exit(0);
+        }
+
+        if let Some(Command::Import { from, path, name }) = state.args.command.clone() {
+            state.import_layouts(from, &path, name);
+        }
+
+        if let Some(Command::SaveCurrent { into: Some(name) }) = state.args.command.clone() {
+            state.save_current_into(name, current_layout);
         }
+
+        state.handle_heads(current_layout, proxy, qhandle, serial);
     }
 
     event_created_child!(AppData, ZwlrOutputHeadV1, [
@@ -353,16 +3259,33 @@ impl Dispatch<ZwlrOutputHeadV1, ()> for AppData {
         debug!("Received Head event for head={:?}: {event:?}", proxy.id());
         match event {
             zwlr_output_head_v1::Event::Finished => {
-                state.partial_objects.id_to_head.remove(&proxy.id());
+                state.stats.heads_removed += 1;
+                let mut mode_ids = state
+                    .partial_objects
+                    .id_to_head
+                    .remove(&proxy.id())
+                    .map(|partial_head| partial_head.head.modes)
+                    .unwrap_or_default();
                 if let Some(head) = state.id_to_head.remove(&proxy.id()) {
+                    mode_ids.extend(head.head.mode_to_id.values().cloned());
+                    info!(
+                        head = head.head.identity.name,
+                        action = "head-removed",
+                        "Head removed: \"{}\"",
+                        head.head.identity.description
+                    );
                     assert!(
-                        state
-                            .head_identity_to_id
-                            .remove(&head.head.identity)
-                            .is_some(),
+                        state.args.is_ignored(&head.head.identity)
+                            || state
+                                .head_identity_to_id
+                                .remove(&head.head.identity)
+                                .is_some(),
                         "Missing HeadIdentity for existing head"
                     );
                 }
+                // Compositors should finish the head's modes first, but release any that are left
+                // so they don't leak.
+                state.release_modes(mode_ids);
                 proxy.release();
                 // This head was removed, so try to apply a layout on the next `Done` event.
                 state.done_action = DoneAction::Apply;
@@ -382,6 +3305,9 @@ impl Dispatch<ZwlrOutputHeadV1, ()> for AppData {
             zwlr_output_head_v1::Event::SerialNumber { serial_number } => {
                 partial_head.serial_number = Some(serial_number);
             }
+            zwlr_output_head_v1::Event::PhysicalSize { width, height } => {
+                partial_head.physical_size = Some((width as u32, height as u32));
+            }
             zwlr_output_head_v1::Event::Mode { mode } => {
                 partial_head.modes.push(mode.id());
                 state.partial_objects.id_to_mode.insert(
@@ -459,6 +3385,14 @@ impl Dispatch<ZwlrOutputModeV1, ()> for AppData {
                     .expect("The mode was previously reported and not finished.");
                 partial_mode.mode.refresh = Some(refresh as u32);
             }
+            zwlr_output_mode_v1::Event::Preferred => {
+                let partial_mode = state
+                    .partial_objects
+                    .id_to_mode
+                    .get_mut(&id)
+                    .expect("The mode was previously reported and not finished.");
+                partial_mode.mode.preferred = true;
+            }
             zwlr_output_mode_v1::Event::Finished => {
                 state.partial_objects.id_to_mode.remove(&id);
                 state.id_to_mode.remove(&id);
@@ -475,14 +3409,15 @@ impl Dispatch<ZwlrOutputModeV1, ()> for AppData {
     }
 }
 
-impl Dispatch<ZwlrOutputConfigurationV1, ()> for AppData {
+/// The user data of a configuration is the serial it was created with.
+impl Dispatch<ZwlrOutputConfigurationV1, u32> for AppData {
     fn event(
         state: &mut Self,
         proxy: &ZwlrOutputConfigurationV1,
         event: zwlr_output_configuration_v1::Event,
-        _data: &(),
+        serial: &u32,
         _conn: &Connection,
-        _qhandle: &wayland_client::QueueHandle<Self>,
+        qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         debug!(
             "Received Configuration event for config={:?}: {event:?}",
@@ -490,24 +3425,109 @@ impl Dispatch<ZwlrOutputConfigurationV1, ()> for AppData {
         );
         match event {
             zwlr_output_configuration_v1::Event::Succeeded => {
+                state.stats.applies += 1;
                 // We've applied the configuration! We can now get back to updating.
                 state.done_action = DoneAction::Update;
-                if let Some(apply_command) = state.args.apply_command.clone() {
-                    run_command(apply_command);
+                // Compositors may send the resulting `Done` event before this one, in which case
+                // it was already ignored as the result of the apply.
+                let result_received = state
+                    .output_manager
+                    .as_ref()
+                    .is_some_and(|(_, latest_serial)| latest_serial != serial);
+                state.applied_layout_pending = (!result_received).then_some(*serial);
+                state.failed_applies = 0;
+                let applied = state.applying_layout.take();
+                if let Some(index) = applied {
+                    info!(
+                        layout = state.layout_label(index),
+                        action = "apply",
+                        result = "succeeded",
+                        "Applied layout {}",
+                        state.describe_layout(index)
+                    );
+                    let metadata = &mut state.layout_data.layouts[index].metadata;
+                    metadata.last_applied = Some(unix_now());
+                    metadata.apply_count += 1;
+                    if let Err(err) = state.save_state() {
+                        error!("{err}");
+                    }
+                }
+                state.publish(Event::Applied {
+                    layout: applied,
+                    name: applied.and_then(|index| state.layout_data.layouts[index].name.clone()),
+                });
+                if std::mem::take(&mut state.applying_safe_mode) {
+                    error!("Applied the safe configuration since the saved layout could not be applied");
+                    state.safe_mode_result_pending = true;
+                }
+                let context = state.hook_context("applied", applied, None);
+                let head_commands = std::mem::take(&mut state.pending_head_commands);
+                // Run the head commands one after another, since later ones may depend on earlier
+                // ones.
+                let head_commands_thread = (!head_commands.is_empty()).then(|| {
+                    run_command(
+                        CommandLine::Shell(head_commands.join("\n"))
+                            .to_command(&state.args.command_shell),
+                        &context,
+                    )
+                });
+                if let Some(arranged) = state.arranged_layout.take() {
+                    state.save_arranged_layout(arranged);
+                }
+                if state.exits_after_applying() {
+                    // Wait for the commands so they aren't killed when we exit.
+                    if let Some(thread) = head_commands_thread {
+                        let _ = thread.join();
+                    }
+                    std::process::exit(0);
                 }
             }
             zwlr_output_configuration_v1::Event::Cancelled => {
-                // Try to apply the layout again.
-                state.done_action = DoneAction::Apply;
+                state.stats.apply_failures += 1;
+                let layout = state.applying_layout.map(|index| state.layout_label(index));
+                info!(
+                    layout,
+                    action = "apply",
+                    result = "cancelled",
+                    "Applying the layout was cancelled"
+                );
+                state.publish_apply_failed(true);
+                if state.exits_after_applying() {
+                    eprintln!("Applying the output configuration was cancelled");
+                    std::process::exit(1);
+                }
+                if !state.retry_apply(qhandle) {
+                    state.done_action = DoneAction::Update;
+                }
             }
             zwlr_output_configuration_v1::Event::Failed => {
-                eprintln!("Failed to apply output configuration");
-                // Try to apply the layout again.
-                state.done_action = DoneAction::Apply;
+                state.stats.apply_failures += 1;
+                let layout = state.applying_layout.map(|index| state.layout_label(index));
+                error!(
+                    layout,
+                    action = "apply",
+                    result = "failed",
+                    "Failed to apply output configuration"
+                );
+                state.publish_apply_failed(false);
+                if state.exits_after_applying() {
+                    eprintln!("Failed to apply the output configuration");
+                    std::process::exit(1);
+                }
+                if std::mem::take(&mut state.applying_safe_mode) {
+                    error!(
+                        "Failed to apply the safe configuration. Giving up until the heads change"
+                    );
+                    state.failed_applies = 0;
+                    state.done_action = DoneAction::Update;
+                } else if !state.retry_apply(qhandle) {
+                    state.apply_safe_mode(qhandle);
+                }
             }
             _ => {}
         }
         proxy.destroy();
+        state.live_configurations = state.live_configurations.saturating_sub(1);
     }
 }
 
@@ -524,9 +3544,59 @@ impl Dispatch<ZwlrOutputConfigurationHeadV1, ()> for AppData {
     }
 }
 
-fn run_command(command: Arc<str>) {
-    std::thread::spawn(
-        move || match Command::new("sh").arg("-c").arg(command.as_ref()).output() {
+/// Whether `save-current` created a new layout or updated an existing one.
+#[derive(::serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SaveOutcome {
+    Created,
+    Updated,
+}
+
+/// The result of `save-current`, printed as JSON so scripts can inspect it.
+#[derive(::serde::Serialize)]
+struct SaveResult<'a> {
+    result: SaveOutcome,
+    index: usize,
+    name: Option<&'a str>,
+    heads: Vec<&'a HeadIdentity>,
+}
+
+/// Prints the result of `save-current` for the layout at `index`.
+fn print_save_result(result: SaveOutcome, index: usize, layout_data: &LayoutData) {
+    let layout = &layout_data.layouts[index];
+    let mut heads = layout.heads.keys().collect::<Vec<_>>();
+    heads.sort_by(|a, b| a.name.cmp(&b.name));
+    let save_result = SaveResult {
+        result,
+        index,
+        name: layout.name.as_deref(),
+        heads,
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&save_result).expect("Failed to serialize the save result")
+    );
+}
+
+/// Runs `command` in the background, passing it `context`.
+fn run_command(mut command: std::process::Command, context: &HookContext) -> JoinHandle<()> {
+    context.set_env(&mut command);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let stdin = context.stdin();
+    std::thread::spawn(move || {
+        let output = command.spawn().and_then(|mut child| {
+            // The command may not read its stdin, so failing to write all of it is fine.
+            let _ = child
+                .stdin
+                .take()
+                .expect("stdin is piped")
+                .write_all(stdin.as_bytes());
+            child.wait_with_output()
+        });
+        match output {
             Ok(output) => {
                 if output.status.success() {
                     debug!(
@@ -545,6 +3615,6 @@ fn run_command(command: Arc<str>) {
             Err(err) => {
                 error!("Failed to run post_exec command: {err}");
             }
-        },
-    );
+        }
+    })
 }
@@ -0,0 +1,141 @@
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{complete::HeadIdentity, config::CommandLine, serde::Layout};
+
+/// How long the matcher has to pick a layout before it is killed.
+const MATCHER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An external command that picks which of the matching layouts to apply.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub command: CommandLine,
+    /// The shell (and its arguments) to run the command with, if it is a command line.
+    pub shell: Vec<String>,
+}
+
+/// What the matcher decided for some heads (see [`crate::serde::LayoutData::ask_matcher`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatcherPick {
+    /// The built-in match is used, since the matcher wasn't run (or failed).
+    Builtin,
+    /// The matcher picked the layout at this index.
+    Layout(usize),
+    /// The matcher picked no layout, so none is applied, and the configuration isn't saved as a
+    /// new layout either.
+    Nothing,
+}
+
+/// What the matcher is given on stdin.
+#[derive(Serialize)]
+struct MatcherInput<'a> {
+    /// The current heads, sorted by name.
+    heads: Vec<&'a HeadIdentity>,
+    /// The layouts matching the heads.
+    layouts: Vec<Candidate<'a>>,
+    /// The index of the layout wl-distore would apply on its own, if any.
+    builtin: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Candidate<'a> {
+    index: usize,
+    name: Option<&'a str>,
+    profile: &'a str,
+    /// The layout in the format of an entry of the layouts file.
+    layout: serde_json::Value,
+}
+
+impl Matcher {
+    /// Runs the matcher to pick one of the `candidates` (pairs of an index and a layout matching
+    /// `heads`). `builtin` is the index of the layout wl-distore would pick on its own. Returns
+    /// [`None`] if the matcher picked no layout.
+    ///
+    /// The matcher prints the index or the name of the layout to apply, or `none`.
+    pub fn pick(
+        &self,
+        heads: &HashSet<HeadIdentity>,
+        candidates: &[(usize, &Layout)],
+        builtin: Option<usize>,
+    ) -> Result<Option<usize>, std::io::Error> {
+        let mut sorted_heads = heads.iter().collect::<Vec<_>>();
+        sorted_heads.sort_by(|a, b| a.name.cmp(&b.name));
+        let input = MatcherInput {
+            heads: sorted_heads,
+            layouts: candidates
+                .iter()
+                .map(|(index, layout)| {
+                    Ok(Candidate {
+                        index: *index,
+                        name: layout.name.as_deref(),
+                        profile: layout.profile(),
+                        layout: serde_json::from_str(&layout.to_json()?)?,
+                    })
+                })
+                .collect::<Result<_, serde_json::Error>>()?,
+            builtin,
+        };
+        let input = serde_json::to_vec(&input)?;
+
+        let mut child = self
+            .command
+            .to_command(&self.shell)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // Write on another thread, so a matcher that doesn't read all of its input can't block us.
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+        let deadline = Instant::now() + MATCHER_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("the matcher didn't exit within {MATCHER_TIMEOUT:?}"),
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "the matcher failed with {status}"
+            )));
+        }
+        let mut output = String::new();
+        child
+            .stdout
+            .take()
+            .expect("stdout is piped")
+            .read_to_string(&mut output)?;
+
+        let picked = output.trim();
+        if picked == "none" {
+            return Ok(None);
+        }
+        candidates
+            .iter()
+            .find(|(index, layout)| {
+                picked.parse() == Ok(*index) || layout.name.as_deref() == Some(picked)
+            })
+            .map(|(index, _)| Some(*index))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("the matcher picked \"{picked}\", which isn't a matching layout"),
+                )
+            })
+    }
+}
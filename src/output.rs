@@ -0,0 +1,61 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How read-only subcommands print their results.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// An aligned table with a header, for humans.
+    #[default]
+    Table,
+    /// JSON, for scripts.
+    Json,
+    /// Tab-separated rows without a header.
+    Plain,
+}
+
+/// Prints `value` as JSON, or `rows` as a table or plain rows, depending on `format`.
+pub fn print(
+    format: OutputFormat,
+    value: &impl Serialize,
+    headers: &[&str],
+    rows: Vec<Vec<String>>,
+) {
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(value).expect("Output can be serialized")
+        ),
+        OutputFormat::Table => print_table(headers, &rows),
+        OutputFormat::Plain => {
+            for row in rows {
+                println!("{}", row.join("\t"));
+            }
+        }
+    }
+}
+
+/// Prints `rows` with each column aligned under `headers`.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers
+        .iter()
+        .map(|header| header.len())
+        .collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: &mut dyn Iterator<Item = &str>| {
+        cells
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", format_row(&mut headers.iter().copied()));
+    for row in rows {
+        println!("{}", format_row(&mut row.iter().map(String::as_str)));
+    }
+}
@@ -14,6 +14,7 @@ pub struct PartialHead {
     pub make: Option<String>,
     pub model: Option<String>,
     pub serial_number: Option<String>,
+    pub physical_size: Option<(u32, u32)>,
     pub enabled: Option<bool>,
     pub modes: Vec<ObjectId>,
     pub current_mode: Option<ObjectId>,
@@ -35,6 +36,8 @@ impl PartialHead {
             Some(ImmutableProperty::Model)
         } else if self.serial_number.is_some() {
             Some(ImmutableProperty::SerialNumber)
+        } else if self.physical_size.is_some() {
+            Some(ImmutableProperty::PhysicalSize)
         } else {
             None
         }
@@ -65,6 +68,7 @@ pub enum ImmutableProperty {
     Make,
     Model,
     SerialNumber,
+    PhysicalSize,
 }
 
 /// A property about the configuration of an enabled head. Note we intentionally exclude Enabled.
@@ -86,6 +90,7 @@ pub struct PartialHeadState {
 pub struct PartialMode {
     pub size: Option<(u32, u32)>,
     pub refresh: Option<u32>,
+    pub preferred: bool,
 }
 
 pub struct PartialModeState {
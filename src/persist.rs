@@ -0,0 +1,238 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, error};
+
+/// Writes files, either on a background thread (so slow disks or network homes can't delay
+/// handling Wayland events) or immediately.
+pub struct Persister {
+    /// The sender for the background worker. [`None`] if files are written immediately.
+    sender: Option<Sender<Job>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Work for the background worker.
+enum Job {
+    /// Write the contents to the file at the path.
+    Write(PathBuf, Vec<u8>),
+    /// Run some other file operation, identified by the key.
+    Run(
+        &'static str,
+        Box<dyn FnOnce() -> Result<(), std::io::Error> + Send>,
+    ),
+    /// Drop the queued writes to the paths and the queued jobs with the keys, then acknowledge on
+    /// the sender.
+    Cancel(Vec<PathBuf>, Vec<&'static str>, Sender<()>),
+}
+
+impl Persister {
+    /// Creates a persister that writes files on a background thread if `background` is true, or
+    /// immediately otherwise. One-shot subcommands exit right after saving, so they should write
+    /// immediately. In the background, jobs wait until none have been queued for `save_delay`, so
+    /// bursts of changes are only written once, and at least `min_save_interval` passes between
+    /// two writes, so constant changes are only written once per interval.
+    pub fn new(background: bool, save_delay: Duration, min_save_interval: Duration) -> Self {
+        if !background {
+            return Self {
+                sender: None,
+                worker: None,
+            };
+        }
+        let (sender, receiver) = mpsc::channel();
+        let worker = std::thread::Builder::new()
+            .name("persist".into())
+            .spawn(move || run_worker(receiver, save_delay, min_save_interval))
+            .expect("Failed to spawn the persistence thread");
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Returns whether files are written on a background thread.
+    pub fn is_background(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Writes `contents` to the file at `path`, creating its parent directories. In the
+    /// background, writes to the same path that haven't started yet are replaced by this one, and
+    /// errors are logged rather than returned.
+    pub fn write(&self, path: PathBuf, contents: Vec<u8>) -> Result<(), std::io::Error> {
+        let Some(sender) = self.sender.as_ref() else {
+            return write_file(&path, &contents);
+        };
+        if let Err(mpsc::SendError(Job::Write(path, contents))) =
+            sender.send(Job::Write(path, contents))
+        {
+            // The worker is gone (it should never panic), so fall back to writing here.
+            log_error(write_file(&path, &contents));
+        }
+        Ok(())
+    }
+
+    /// Runs `job`, in order with the writes. In the background, jobs with the same `key` that
+    /// haven't started yet are replaced by this one, and errors are logged rather than returned.
+    pub fn run(
+        &self,
+        key: &'static str,
+        job: impl FnOnce() -> Result<(), std::io::Error> + Send + 'static,
+    ) -> Result<(), std::io::Error> {
+        let Some(sender) = self.sender.as_ref() else {
+            return job();
+        };
+        if let Err(mpsc::SendError(Job::Run(_, job))) = sender.send(Job::Run(key, Box::new(job))) {
+            log_error(job());
+        }
+        Ok(())
+    }
+
+    /// Drops the writes to `paths` and the jobs with `keys` that haven't started yet, e.g. because
+    /// another program changed the files and its changes should win. Waits for the worker, so once
+    /// this returns, none of them will run.
+    pub fn cancel(&self, paths: Vec<PathBuf>, keys: Vec<&'static str>) {
+        let Some(sender) = self.sender.as_ref() else {
+            return;
+        };
+        let (ack_sender, ack) = mpsc::channel();
+        if sender.send(Job::Cancel(paths, keys, ack_sender)).is_ok() {
+            let _ = ack.recv();
+        }
+    }
+}
+
+impl Drop for Persister {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker finish the pending writes and exit.
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Runs the jobs sent on `receiver` until it is closed. Once a job arrives, waits until no more
+/// have arrived for `save_delay`. Jobs that queue up in the meantime (or while a job is in
+/// progress) are coalesced, so only the latest contents of each file are written. Then waits
+/// (still coalescing) until `min_save_interval` has passed since the jobs were last run.
+fn run_worker(receiver: Receiver<Job>, save_delay: Duration, min_save_interval: Duration) {
+    let mut last_run: Option<Instant> = None;
+    while let Ok(job) = receiver.recv() {
+        let mut jobs = vec![];
+        queue(&mut jobs, job);
+        // Stops once nothing arrives for the delay, or the channel closes (so pending jobs are
+        // still flushed before exiting).
+        while let Ok(job) = receiver.recv_timeout(save_delay) {
+            queue(&mut jobs, job);
+        }
+        if let Some(last_run) = last_run {
+            let next_run = last_run + min_save_interval;
+            loop {
+                let remaining = next_run.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                match receiver.recv_timeout(remaining) {
+                    Ok(job) => queue(&mut jobs, job),
+                    Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        }
+        if jobs.is_empty() {
+            // Everything was cancelled.
+            continue;
+        }
+        for job in jobs {
+            log_error(match job {
+                Job::Write(path, contents) => write_file(&path, &contents),
+                Job::Run(_, job) => job(),
+                Job::Cancel(..) => unreachable!("cancels are never queued"),
+            });
+        }
+        last_run = Some(Instant::now());
+    }
+}
+
+/// Adds `job` to `jobs`, replacing the queued job for the same file or key if there is one. A
+/// cancel instead removes the queued jobs it is for.
+fn queue(jobs: &mut Vec<Job>, job: Job) {
+    if let Job::Cancel(paths, keys, ack) = job {
+        jobs.retain(|queued| match queued {
+            Job::Write(path, _) => !paths.contains(path),
+            Job::Run(key, _) => !keys.contains(key),
+            Job::Cancel(..) => true,
+        });
+        let _ = ack.send(());
+        return;
+    }
+    let queued = jobs.iter_mut().find(|queued| match (&**queued, &job) {
+        (Job::Write(queued_path, _), Job::Write(path, _)) => queued_path == path,
+        (Job::Run(queued_key, _), Job::Run(key, _)) => queued_key == key,
+        _ => false,
+    });
+    match queued {
+        Some(queued) => {
+            if let Job::Write(path, _) = &job {
+                debug!("Coalescing writes to {}", path.display());
+            }
+            *queued = job;
+        }
+        None => jobs.push(job),
+    }
+}
+
+/// Writes `contents` to the file at `path`, saying which file failed to save in any error.
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    write_atomically(path, contents).map_err(|err| {
+        std::io::Error::new(
+            err.kind(),
+            format!("Failed to save {}: {err}", path.display()),
+        )
+    })
+}
+
+/// Logs the error of a job that ran in the background, where nobody is waiting for it.
+fn log_error(result: Result<(), std::io::Error>) {
+    if let Err(err) = result {
+        error!("{err}");
+    }
+}
+
+/// Distinguishes the temporary files of concurrent writes within this process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `contents` to the file at `path`, creating its parent directories. The contents are
+/// written to a temporary file next to `path` which then replaces it, so readers (and crashes)
+/// never see a partially written file. The temporary file is named after the process and a
+/// counter, so concurrent writes (even from other instances) don't clobber each other's.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
@@ -0,0 +1,102 @@
+use std::{path::Path, time::Duration};
+
+use calloop::channel::Sender;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use zbus::blocking::{Connection, Proxy};
+
+/// The directory the kernel lists power supplies in.
+const POWER_SUPPLIES: &str = "/sys/class/power_supply";
+
+/// How often the power supplies are read when UPower isn't available.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The source of the machine's power, which layouts can be kept for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+impl PowerSource {
+    fn from_on_battery(on_battery: bool) -> Self {
+        if on_battery {
+            Self::Battery
+        } else {
+            Self::Ac
+        }
+    }
+}
+
+/// Reads the power source from UPower over D-Bus.
+pub struct PowerWatcher {
+    proxy: Proxy<'static>,
+}
+
+impl PowerWatcher {
+    /// Connects to UPower on the system bus.
+    pub fn connect() -> zbus::Result<Self> {
+        let connection = Connection::system()?;
+        let proxy = Proxy::new(
+            &connection,
+            "org.freedesktop.UPower",
+            "/org/freedesktop/UPower",
+            "org.freedesktop.UPower",
+        )?;
+        Ok(Self { proxy })
+    }
+
+    /// Returns the current power source.
+    pub fn state(&self) -> zbus::Result<PowerSource> {
+        self.proxy
+            .get_property("OnBattery")
+            .map(PowerSource::from_on_battery)
+    }
+
+    /// Sends the power source to `sender` every time it changes, from another thread. Stops once
+    /// the receiving end is dropped.
+    pub fn watch(self, sender: Sender<PowerSource>) {
+        std::thread::spawn(move || {
+            for change in self.proxy.receive_property_changed::<bool>("OnBattery") {
+                let source = match change.get() {
+                    Ok(on_battery) => PowerSource::from_on_battery(on_battery),
+                    Err(err) => {
+                        warn!("Failed to read the power source: {err}");
+                        continue;
+                    }
+                };
+                if sender.send(source).is_err() {
+                    return;
+                }
+            }
+            error!("Stopped receiving changes to the power source from UPower");
+        });
+    }
+}
+
+/// Reads the power source from the power supplies in sysfs, for when UPower isn't available. The
+/// machine is on battery when it has mains supplies (e.g., an AC adapter) and none of them are
+/// online. Machines without any are taken to be on AC.
+pub fn read_power_supplies() -> std::io::Result<PowerSource> {
+    let mut has_mains = false;
+    for entry in std::fs::read_dir(POWER_SUPPLIES)? {
+        let path = entry?.path();
+        if read_attribute(&path, "type").as_deref() != Some("Mains") {
+            continue;
+        }
+        has_mains = true;
+        if read_attribute(&path, "online").as_deref() == Some("1") {
+            return Ok(PowerSource::Ac);
+        }
+    }
+    Ok(PowerSource::from_on_battery(has_mains))
+}
+
+/// Reads the attribute `name` of the power supply at `path`, without the trailing newline.
+fn read_attribute(path: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(path.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
@@ -0,0 +1,36 @@
+use crate::{formats::ConnectedHead, scale::logical_size, serde::Transform};
+
+/// Returns whether `heads` look like the compositor reset them to its defaults (which some
+/// compositors momentarily do, e.g. when a display reconnects): every head is enabled at its
+/// preferred mode, with no transform and a scale of 1, and they are packed left to right from the
+/// origin (in any order). Heads without a preferred mode can't be told apart from a reset, so they
+/// never are.
+pub fn is_reset(heads: &[ConnectedHead]) -> bool {
+    let mut configurations = Vec::with_capacity(heads.len());
+    for head in heads {
+        let Some(configuration) = head.configuration.as_ref() else {
+            return false;
+        };
+        if head.preferred_mode.is_none()
+            || configuration.mode != head.preferred_mode
+            || configuration.transform != Transform::Normal
+            || configuration.scale != 1.0
+            || configuration.position.1 != 0
+        {
+            return false;
+        }
+        configurations.push(configuration);
+    }
+    configurations.sort_by_key(|configuration| configuration.position.0);
+    let mut next_x = 0.0;
+    for configuration in configurations {
+        let Some((width, _)) = logical_size(configuration) else {
+            return false;
+        };
+        if configuration.position.0 as f64 != next_x {
+            return false;
+        }
+        next_x += width;
+    }
+    !heads.is_empty()
+}
@@ -0,0 +1,135 @@
+use tracing::warn;
+
+use crate::{
+    complete::HeadIdentity,
+    serde::{SavedConfiguration, Transform},
+};
+
+/// How a compositor constrains the scales it accepts.
+#[derive(Clone, Copy, Debug)]
+pub struct ScaleQuirks {
+    /// Scales are rounded to a multiple of `1 / granularity`.
+    pub granularity: u32,
+    /// Whether the compositor rejects scales which don't result in an integer logical size (rather
+    /// than just rounding the logical size).
+    pub requires_integer_logical_size: bool,
+}
+
+/// The quirks for compositors we know about, keyed by `XDG_CURRENT_DESKTOP`.
+const KNOWN_QUIRKS: &[(&str, ScaleQuirks)] = &[(
+    "Hyprland",
+    ScaleQuirks {
+        granularity: 120,
+        requires_integer_logical_size: true,
+    },
+)];
+
+impl Default for ScaleQuirks {
+    fn default() -> Self {
+        // Most wlroots compositors send scales through the fractional scale protocol, which uses
+        // multiples of 1/120.
+        Self {
+            granularity: 120,
+            requires_integer_logical_size: false,
+        }
+    }
+}
+
+impl ScaleQuirks {
+    /// Looks up the quirks of the current compositor.
+    pub fn detect() -> Self {
+        let Ok(desktop) = std::env::var("XDG_CURRENT_DESKTOP") else {
+            return Self::default();
+        };
+        KNOWN_QUIRKS
+            .iter()
+            .find(|(name, _)| desktop.split(':').any(|desktop| desktop == *name))
+            .map(|(_, quirks)| *quirks)
+            .unwrap_or_default()
+    }
+
+    /// Returns whether `scale` gives an integer logical size for a mode of `size`.
+    fn is_valid(&self, size: (u32, u32), scale: f64) -> bool {
+        let is_integer = |value: f64| (value - value.round()).abs() < 1e-6;
+        let on_grid = is_integer(scale * self.granularity as f64);
+        on_grid && is_integer(size.0 as f64 / scale) && is_integer(size.1 as f64 / scale)
+    }
+
+    /// Finds the scale closest to `scale` that gives an integer logical size for a mode of `size`.
+    fn nearest_valid_scale(&self, size: (u32, u32), scale: f64) -> Option<f64> {
+        let granularity = self.granularity as f64;
+        let center = (scale * granularity).round() as i64;
+        // Search up to one whole scale step in each direction.
+        for offset in 0..=self.granularity as i64 {
+            for step in [center - offset, center + offset] {
+                if step <= 0 {
+                    continue;
+                }
+                let candidate = step as f64 / granularity;
+                if self.is_valid(size, candidate) {
+                    return Some(candidate);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Returns the logical size of a head using `configuration`, if the mode is known.
+pub fn logical_size(configuration: &SavedConfiguration) -> Option<(f64, f64)> {
+    let mode = configuration.mode?;
+    let (width, height) = match configuration.transform {
+        Transform::Normal | Transform::_180 | Transform::Flipped | Transform::Flipped180 => {
+            mode.size
+        }
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+            (mode.size.1, mode.size.0)
+        }
+    };
+    Some((
+        width as f64 / configuration.scale,
+        height as f64 / configuration.scale,
+    ))
+}
+
+/// Warns if the scale of `configuration` results in a fractional logical size, which the
+/// compositor may reject or round. If `snap` is set, the scale is replaced with the nearest one
+/// that results in an integer logical size.
+pub fn validate_scale(
+    identity: &HeadIdentity,
+    configuration: &mut SavedConfiguration,
+    quirks: &ScaleQuirks,
+    snap: bool,
+) {
+    let Some(mode) = configuration.mode else {
+        return;
+    };
+    if quirks.is_valid(mode.size, configuration.scale) {
+        return;
+    }
+    let (width, height) = logical_size(configuration).expect("The mode is known");
+    let consequence = if quirks.requires_integer_logical_size {
+        "the compositor is likely to reject it"
+    } else {
+        "the compositor may round it"
+    };
+    let Some(nearest) = quirks.nearest_valid_scale(mode.size, configuration.scale) else {
+        warn!(
+            "Scale {} on \"{}\" gives a fractional logical size of {width}x{height}, so {consequence}",
+            configuration.scale, identity.description
+        );
+        return;
+    };
+    if snap {
+        warn!(
+            "Scale {} on \"{}\" gives a fractional logical size of {width}x{height}, so snapping it to {nearest}",
+            configuration.scale, identity.description
+        );
+        configuration.scale = nearest;
+    } else {
+        warn!(
+            "Scale {} on \"{}\" gives a fractional logical size of {width}x{height}, so {consequence}. The nearest valid scale is {nearest}",
+            configuration.scale, identity.description
+        );
+    }
+}
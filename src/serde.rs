@@ -1,21 +1,35 @@
 use std::{
-    collections::{HashMap, HashSet},
-    io::{BufReader, BufWriter, ErrorKind},
-    path::Path,
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
 };
 
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::error::Category;
+use sha2::{Digest, Sha256};
 
 use thiserror::Error;
+use tracing::{error, info, warn};
 use wayland_client::{backend::ObjectId, protocol::wl_output::Transform as wayland_Transform};
 use wayland_protocols_wlr::output_management::v1::client::{
     zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1,
     zwlr_output_head_v1::AdaptiveSyncState,
 };
 
-use crate::complete::{HeadConfiguration, HeadIdentity, Mode, ModeState};
+use crate::{
+    complete::{HeadConfiguration, HeadIdentity, Mode, ModeState},
+    config::{glob_matches, ModePolicy, ModePreference},
+    lid::LidState,
+    matcher::{Matcher, MatcherPick},
+    persist::write_atomically,
+    power::PowerSource,
+    state::{self, LayoutMetadata},
+};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub enum Transform {
     Normal,
     _90,
@@ -45,69 +59,221 @@ impl TryFrom<wayland_Transform> for Transform {
     }
 }
 
+impl Transform {
+    const NAMES: [(Self, &'static str); 8] = [
+        (Self::Normal, "normal"),
+        (Self::_90, "90"),
+        (Self::_180, "180"),
+        (Self::_270, "270"),
+        (Self::Flipped, "flipped"),
+        (Self::Flipped90, "flipped-90"),
+        (Self::Flipped180, "flipped-180"),
+        (Self::Flipped270, "flipped-270"),
+    ];
+
+    /// The name of this transform as used by most other tools, e.g. "90" or "flipped-90".
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(transform, _)| *transform == self)
+            .map(|(_, name)| *name)
+            .expect("Every transform has a name")
+    }
+
+    /// Parses a transform from its [`Self::name`], ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, transform_name)| transform_name.eq_ignore_ascii_case(name))
+            .map(|(transform, _)| *transform)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum TransformConversionError {
     #[error("An unknown Transform variant was received: {0:?}")]
     UnknownVariant(wayland_Transform),
 }
 
-impl Into<wayland_Transform> for Transform {
-    fn into(self) -> wayland_Transform {
-        match self {
-            Self::Normal => wayland_Transform::Normal,
-            Self::_90 => wayland_Transform::_90,
-            Self::_180 => wayland_Transform::_180,
-            Self::_270 => wayland_Transform::_270,
-            Self::Flipped => wayland_Transform::Flipped,
-            Self::Flipped90 => wayland_Transform::Flipped90,
-            Self::Flipped180 => wayland_Transform::Flipped180,
-            Self::Flipped270 => wayland_Transform::Flipped270,
+impl From<Transform> for wayland_Transform {
+    fn from(value: Transform) -> Self {
+        match value {
+            Transform::Normal => Self::Normal,
+            Transform::_90 => Self::_90,
+            Transform::_180 => Self::_180,
+            Transform::_270 => Self::_270,
+            Transform::Flipped => Self::Flipped,
+            Transform::Flipped90 => Self::Flipped90,
+            Transform::Flipped180 => Self::Flipped180,
+            Transform::Flipped270 => Self::Flipped270,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A configuration of an enabled head. A [`None`] mode or adaptive sync state is left to the
+/// compositor, as are any properties in `dont_care`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(from = "SavedConfigurationFormat", into = "SavedConfigurationFormat")]
 pub struct SavedConfiguration {
+    pub mode: Option<Mode>,
+    pub position: (u32, u32),
+    pub transform: Transform,
+    pub scale: f64,
+    pub adaptive_sync: Option<bool>,
+    /// The properties to leave as the compositor chose. These are saved as nulls.
+    pub dont_care: DontCare,
+}
+
+/// The properties of a [`SavedConfiguration`] that aren't optional in the protocol, but which are
+/// left as the compositor chose them when set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DontCare {
+    pub position: bool,
+    pub transform: bool,
+    pub scale: bool,
+}
+
+/// A property of a head's configuration.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Property {
+    Mode,
+    Position,
+    Transform,
+    Scale,
+    AdaptiveSync,
+    /// Whether the head is enabled at all.
+    Enabled,
+}
+
+impl Property {
+    pub const ALL: &[Property] = &[
+        Property::Mode,
+        Property::Position,
+        Property::Transform,
+        Property::Scale,
+        Property::AdaptiveSync,
+        Property::Enabled,
+    ];
+}
+
+/// How a [`SavedConfiguration`] is stored, where a null property is left to the compositor.
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct SavedConfigurationFormat {
     mode: Option<Mode>,
-    position: (u32, u32),
-    transform: Transform,
-    scale: f64,
+    position: Option<(u32, u32)>,
+    transform: Option<Transform>,
+    scale: Option<f64>,
     adaptive_sync: Option<bool>,
 }
 
+impl From<SavedConfigurationFormat> for SavedConfiguration {
+    fn from(value: SavedConfigurationFormat) -> Self {
+        Self {
+            mode: value.mode,
+            position: value.position.unwrap_or_default(),
+            transform: value.transform.unwrap_or(Transform::Normal),
+            scale: value.scale.unwrap_or(1.0),
+            adaptive_sync: value.adaptive_sync,
+            dont_care: DontCare {
+                position: value.position.is_none(),
+                transform: value.transform.is_none(),
+                scale: value.scale.is_none(),
+            },
+        }
+    }
+}
+
+impl From<SavedConfiguration> for SavedConfigurationFormat {
+    fn from(value: SavedConfiguration) -> Self {
+        Self {
+            mode: value.mode,
+            position: (!value.dont_care.position).then_some(value.position),
+            transform: (!value.dont_care.transform).then_some(value.transform),
+            scale: (!value.dont_care.scale).then_some(value.scale),
+            adaptive_sync: value.adaptive_sync,
+        }
+    }
+}
+
 impl SavedConfiguration {
+    /// Creates the configuration of a head from its current `configuration`, leaving every
+    /// property not in `properties` to the compositor.
     pub fn from_config(
         configuration: &HeadConfiguration,
         id_to_mode: &HashMap<ObjectId, ModeState>,
+        properties: &[Property],
     ) -> Self {
-        SavedConfiguration {
+        let mut saved = SavedConfiguration {
             mode: configuration.current_mode.as_ref().map(|mode| {
                 id_to_mode
-                    .get(&mode)
+                    .get(mode)
                     .expect("The current mode doesn't exist.")
                     .mode
-                    .clone()
             }),
             position: configuration.position,
             transform: configuration.transform,
             scale: configuration.scale,
             adaptive_sync: configuration.adaptive_sync,
+            dont_care: Default::default(),
+        };
+        saved.retain_properties(properties);
+        saved
+    }
+
+    /// Leaves every property not in `properties` to the compositor.
+    pub fn retain_properties(&mut self, properties: &[Property]) {
+        if !properties.contains(&Property::Mode) {
+            self.mode = None;
         }
+        if !properties.contains(&Property::AdaptiveSync) {
+            self.adaptive_sync = None;
+        }
+        self.dont_care.position |= !properties.contains(&Property::Position);
+        self.dont_care.transform |= !properties.contains(&Property::Transform);
+        self.dont_care.scale |= !properties.contains(&Property::Scale);
     }
 
+    /// Returns whether `current` is what applying this configuration would give, ignoring the
+    /// properties left to the compositor and those not in `properties`.
+    pub fn is_satisfied_by(&self, current: &SavedConfiguration, properties: &[Property]) -> bool {
+        let mut configuration = self.clone();
+        configuration.retain_properties(properties);
+        (configuration.mode.is_none() || configuration.mode == current.mode)
+            && (configuration.dont_care.position || configuration.position == current.position)
+            && (configuration.dont_care.transform || configuration.transform == current.transform)
+            && (configuration.dont_care.scale || configuration.scale == current.scale)
+            && (configuration.adaptive_sync.is_none()
+                || configuration.adaptive_sync == current.adaptive_sync)
+    }
+
+    /// Applies the properties of this configuration in `properties` to `new_configuration_head`.
+    /// If the saved mode isn't available exactly, a mode with the same size is picked using
+    /// `mode_preference`.
     pub fn apply(
         &self,
-        new_configuration_head: &mut ZwlrOutputConfigurationHeadV1,
+        new_configuration_head: &mut impl ConfigurationHead,
         mode_to_id: &HashMap<Mode, ObjectId>,
         id_to_mode: &HashMap<ObjectId, ModeState>,
+        mode_preference: ModePreference,
+        properties: &[Property],
     ) {
-        if let Some(mode) = self.mode {
-            if let Some(id) = mode_to_id.get(&mode).cloned() {
-                let proxy = &id_to_mode
-                    .get(&id)
-                    .expect("Missing mode for existing id")
-                    .proxy;
-                new_configuration_head.set_mode(proxy);
+        let mut configuration = self.clone();
+        configuration.retain_properties(properties);
+        if let Some(mode) = configuration.mode {
+            let mode_state = match mode_to_id.get(&mode) {
+                Some(id) => Some(id_to_mode.get(id).expect("Missing mode for existing id")),
+                None => pick_mode(
+                    mode_to_id
+                        .iter()
+                        .filter(|(candidate, _)| candidate.size == mode.size)
+                        .filter_map(|(_, id)| id_to_mode.get(id)),
+                    mode.refresh,
+                    mode_preference,
+                ),
+            };
+            if let Some(mode_state) = mode_state {
+                new_configuration_head.set_mode(mode_state);
             } else {
                 new_configuration_head.set_custom_mode(
                     mode.size.0 as i32,
@@ -116,89 +282,1585 @@ impl SavedConfiguration {
                 );
             }
         }
-        new_configuration_head.set_position(self.position.0 as i32, self.position.1 as i32);
-        new_configuration_head.set_scale(self.scale);
-        new_configuration_head.set_transform(self.transform.into());
-        if let Some(adaptive_sync) = self.adaptive_sync {
-            new_configuration_head.set_adaptive_sync(if adaptive_sync {
+        if !configuration.dont_care.position {
+            new_configuration_head.set_position(
+                configuration.position.0 as i32,
+                configuration.position.1 as i32,
+            );
+        }
+        if !configuration.dont_care.scale {
+            new_configuration_head.set_scale(configuration.scale);
+        }
+        if !configuration.dont_care.transform {
+            new_configuration_head.set_transform(configuration.transform);
+        }
+        if let Some(adaptive_sync) = configuration.adaptive_sync {
+            new_configuration_head.set_adaptive_sync(adaptive_sync);
+        }
+    }
+}
+
+/// The requests [`SavedConfiguration::apply`] makes to configure a head. This is implemented by
+/// the real configuration head, and by [`crate::dry_run::DryRunHead`] to log the requests instead.
+pub trait ConfigurationHead {
+    fn set_mode(&mut self, mode_state: &ModeState);
+    fn set_custom_mode(&mut self, width: i32, height: i32, refresh: i32);
+    fn set_position(&mut self, x: i32, y: i32);
+    fn set_scale(&mut self, scale: f64);
+    fn set_transform(&mut self, transform: Transform);
+    fn set_adaptive_sync(&mut self, adaptive_sync: bool);
+}
+
+impl ConfigurationHead for ZwlrOutputConfigurationHeadV1 {
+    fn set_mode(&mut self, mode_state: &ModeState) {
+        ZwlrOutputConfigurationHeadV1::set_mode(self, &mode_state.proxy);
+    }
+
+    fn set_custom_mode(&mut self, width: i32, height: i32, refresh: i32) {
+        ZwlrOutputConfigurationHeadV1::set_custom_mode(self, width, height, refresh);
+    }
+
+    fn set_position(&mut self, x: i32, y: i32) {
+        ZwlrOutputConfigurationHeadV1::set_position(self, x, y);
+    }
+
+    fn set_scale(&mut self, scale: f64) {
+        ZwlrOutputConfigurationHeadV1::set_scale(self, scale);
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        ZwlrOutputConfigurationHeadV1::set_transform(self, transform.into());
+    }
+
+    fn set_adaptive_sync(&mut self, adaptive_sync: bool) {
+        ZwlrOutputConfigurationHeadV1::set_adaptive_sync(
+            self,
+            if adaptive_sync {
                 AdaptiveSyncState::Enabled
             } else {
                 AdaptiveSyncState::Disabled
-            });
-        }
+            },
+        );
+    }
+}
+
+/// Picks one of `candidates` using `mode_preference`. `refresh` is the desired refresh rate, if
+/// known.
+pub fn pick_mode<'a>(
+    candidates: impl Iterator<Item = &'a ModeState>,
+    refresh: Option<u32>,
+    mode_preference: ModePreference,
+) -> Option<&'a ModeState> {
+    let candidates = candidates.collect::<Vec<_>>();
+    let refresh_of = |mode_state: &&ModeState| mode_state.mode.refresh.unwrap_or(0);
+    let highest_refresh = || candidates.iter().copied().max_by_key(refresh_of);
+    // A preferred refresh rate takes precedence over the policy.
+    let (policy, refresh) = match mode_preference.preferred_refresh {
+        Some(preferred_refresh) => (ModePolicy::ClosestRefresh, Some(preferred_refresh)),
+        None => (mode_preference.policy, refresh),
+    };
+    match policy {
+        ModePolicy::ClosestRefresh => match refresh {
+            Some(refresh) => candidates
+                .iter()
+                .copied()
+                .min_by_key(|mode_state| refresh_of(mode_state).abs_diff(refresh)),
+            None => highest_refresh(),
+        },
+        ModePolicy::HighestRefresh => highest_refresh(),
+        ModePolicy::LowestRefresh => candidates.iter().copied().min_by_key(refresh_of),
+        ModePolicy::Preferred => candidates
+            .iter()
+            .copied()
+            .find(|mode_state| mode_state.preferred)
+            .or_else(highest_refresh),
     }
 }
 
 pub struct LayoutData {
-    pub layouts: Vec<HashMap<HeadIdentity, Option<SavedConfiguration>>>,
+    pub layouts: Vec<Layout>,
+    /// Incremented every time the layouts are saved. Stored in the state file.
+    pub generation: u64,
+    /// The profile whose layouts are preferred when several layouts match the same heads, unless
+    /// another profile was chosen for those heads (see [`LayoutData::profile_choices`]). If
+    /// [`None`], the most recently applied matching layout is preferred (see
+    /// [`LayoutData::find_layout_match`]).
+    pub active_profile: Option<String>,
+    /// The profile last switched to for each set of heads (see [`head_set_key`]), which is
+    /// preferred whenever those heads are connected. Stored in the state file, so the choice
+    /// survives restarts.
+    pub profile_choices: HashMap<Vec<HeadIdentity>, String>,
+    /// The fields compared when matching heads against the layouts.
+    pub match_fields: MatchFields,
+    /// The command that picks between the matching layouts, if any.
+    pub matcher: Option<Matcher>,
+    /// The host whose layouts are matched, if layouts are scoped to hosts (so machines sharing a
+    /// layouts file keep to their own layouts). Layouts saved on other hosts are never matched,
+    /// while layouts without a host are matched on every host.
+    pub host: Option<String>,
+    /// The compositor new layouts are saved under, if it is known.
+    pub compositor: Option<String>,
+    /// Whether layouts saved under other compositors are never matched, since compositors may
+    /// describe and scale the same monitors differently. Layouts saved under an unknown compositor
+    /// are matched under every compositor.
+    pub scope_to_compositor: bool,
+    /// Whether a layout is applied to the heads it has when more heads are connected and no layout
+    /// matches them all (see [`LayoutData::find_partial_layout_match`]).
+    pub match_supersets: bool,
+    /// Whether a layout is applied to the heads that are connected when some of its heads are
+    /// missing and no layout matches the connected heads (see
+    /// [`LayoutData::find_partial_layout_match`]).
+    pub match_subsets: bool,
+    /// The current state of the laptop's lid, if it is watched. New layouts saved while the lid is
+    /// closed are kept for the closed lid, so they don't replace the layouts for the open lid.
+    pub lid: Option<LidState>,
+    /// The current power source, if it is watched.
+    pub power_source: Option<PowerSource>,
+    /// The names of the `.json` files in the layouts directory that hold layouts, because they were
+    /// loaded from it or saved to it. Only these are removed once their layout is gone, so other
+    /// files in the directory are left alone.
+    pub layout_files: HashSet<String>,
+    /// The names of the files in the layouts directory that couldn't be loaded. Layouts aren't
+    /// saved under these names, so the files are never overwritten.
+    pub skipped_layout_files: HashSet<String>,
+}
+
+/// What to do when the layouts file was damaged (see [`LayoutData::load_or_recover`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// Fail instead of recovering, e.g. when reloading a file another program may be writing.
+    Never,
+    /// Use the layouts from the backup, but leave the damaged file where it is, for subcommands
+    /// that only read the layouts.
+    InPlace,
+    /// Use the layouts from the backup, and move the damaged file aside so it isn't overwritten.
+    MoveAside,
+}
+
+/// Returns whether a layouts file with `contents` was damaged (e.g., truncated by a crash) rather
+/// than edited by hand: its `backup` is intact (its checksum matches its layouts, so wl-distore
+/// wrote it and nobody edited it since), and `contents` are a cut-off copy of it, ignoring any NUL
+/// bytes a crash may have left at the end.
+fn is_damaged(contents: &[u8], backup: &[u8]) -> bool {
+    let backup_intact = serde_json::from_slice::<SavedLayoutData>(backup)
+        .is_ok_and(|backup| backup.checksum.as_ref() == Some(&backup.compute_checksum()));
+    let end = contents
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |index| index + 1);
+    backup_intact && backup.starts_with(&contents[..end]) && end < backup.len()
+}
+
+/// The profile of layouts that don't name one.
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Clone, Debug)]
+pub struct Layout {
+    /// A human-readable name for the layout.
+    pub name: Option<String>,
+    /// The profile the layout belongs to, which tells apart layouts for the same heads (e.g.,
+    /// "gaming" and "work"). [`None`] is the [`DEFAULT_PROFILE`].
+    pub profile: Option<String>,
+    /// Whether the layout is automatically applied when its heads are connected. Layouts that
+    /// aren't are only applied explicitly, but changes to their heads are still saved into them.
+    pub auto_apply: bool,
+    /// Whether the layout was loaded from the read-only layouts file. Read-only layouts are never
+    /// modified or saved.
+    pub read_only: bool,
+    /// Whether the layout is kept as it is. Pinned layouts are still applied, but changes to the
+    /// configuration aren't saved over them (only explicit saves are), and they are never pruned.
+    pub pinned: bool,
+    /// Whether the layout is a hand-written template, whose head identities are glob patterns (see
+    /// [`MatchFields::matches_pattern`]). Templates are only matched when no other layout matches,
+    /// and are never saved over: saving their heads saves a new layout instead.
+    pub template: bool,
+    /// Breaks ties between layouts that match the same heads equally well: the layout with the
+    /// highest priority is preferred, then the most recently applied, then the earliest. Defaults
+    /// to 0.
+    pub priority: i32,
+    /// The host the layout was saved on, if layouts are scoped to hosts (see
+    /// [`LayoutData::host`]).
+    pub host: Option<String>,
+    /// The compositor the layout was saved under (see [`LayoutData::compositor`]).
+    pub compositor: Option<String>,
+    /// The state of the lid the layout is for, if any (see [`LayoutData::lid`]). Layouts for a lid
+    /// state are preferred over layouts for any state while the lid is in it, and are never matched
+    /// otherwise.
+    pub lid: Option<LidState>,
+    /// The power source the layout is for, if any (see [`LayoutData::power_source`]). Like
+    /// [`Layout::lid`], layouts for a power source are preferred while the machine is on it, and
+    /// are never matched otherwise.
+    pub power_source: Option<PowerSource>,
+    pub heads: HashMap<HeadIdentity, Option<SavedConfiguration>>,
+    /// Extra commands to run after the layout is applied, keyed by the name of the head in this
+    /// layout. These are for settings the protocol can't express (e.g.,
+    /// `swaymsg output {connector} subpixel rgb`).
+    pub head_commands: BTreeMap<String, Vec<String>>,
+    /// A free-text note about the layout, e.g. "couch setup".
+    pub comment: Option<String>,
+    /// Free-text notes about heads, keyed by the name of the head in this layout.
+    pub head_comments: BTreeMap<String, String>,
+    /// Runtime metadata, stored in the state file rather than the layouts file.
+    pub metadata: LayoutMetadata,
+}
+
+impl Layout {
+    /// Creates an unnamed layout from `heads`.
+    pub fn new(heads: HashMap<HeadIdentity, Option<SavedConfiguration>>) -> Self {
+        Self {
+            name: None,
+            profile: None,
+            auto_apply: true,
+            read_only: false,
+            pinned: false,
+            template: false,
+            priority: 0,
+            host: None,
+            compositor: None,
+            lid: None,
+            power_source: None,
+            heads,
+            head_commands: Default::default(),
+            comment: None,
+            head_comments: Default::default(),
+            metadata: Default::default(),
+        }
+    }
+
+    /// The name of the profile the layout belongs to.
+    pub fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Returns whether this layout and `other` set their name, comment, or the commands or comment
+    /// of a head differently, so merging them would lose one of the values.
+    fn conflicts_with(&self, other: &Layout) -> bool {
+        fn differ<T: PartialEq + ?Sized>(a: Option<&T>, b: Option<&T>) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+        differ(self.name.as_deref(), other.name.as_deref())
+            || differ(self.comment.as_deref(), other.comment.as_deref())
+            || self
+                .head_commands
+                .iter()
+                .any(|(head, commands)| differ(Some(commands), other.head_commands.get(head)))
+            || self
+                .head_comments
+                .iter()
+                .any(|(head, comment)| differ(Some(comment), other.head_comments.get(head)))
+    }
+
+    /// Parses a single layout in the format it is saved in the layouts file.
+    pub fn from_json(contents: &str) -> Result<Self, serde_json::Error> {
+        let layout: SavedLayoutFormat = serde_json::from_str(contents)?;
+        Ok((&layout).into())
+    }
+
+    /// Serializes this layout in the format it is saved in the layouts file.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&SavedLayoutFormat::Current(Box::new(self.into())))
+    }
+
+    /// Returns whether this layout can be applied to `query_layout`, either exactly or by
+    /// remapping heads.
+    pub fn matches(&self, query_layout: &HashSet<HeadIdentity>, fields: &MatchFields) -> bool {
+        LayoutMatchScore::score(self, query_layout, fields, false).is_some()
+    }
+
+    /// Returns the commands in `head_commands` for the enabled heads accepted by `include`, with
+    /// `{connector}` replaced by the name of the head it was applied to.
+    /// `layout_head_to_query_head` remaps heads in this layout to the current heads.
+    pub fn resolve_head_commands(
+        &self,
+        layout_head_to_query_head: &HashMap<HeadIdentity, HeadIdentity>,
+        include: impl Fn(&HeadIdentity) -> bool,
+    ) -> Vec<String> {
+        let mut resolved = vec![];
+        for (name, commands) in &self.head_commands {
+            let Some((identity, Some(_))) = self
+                .heads
+                .iter()
+                .find(|(identity, _)| &identity.name == name)
+            else {
+                continue;
+            };
+            let identity = layout_head_to_query_head.get(identity).unwrap_or(identity);
+            if !include(identity) {
+                continue;
+            }
+            resolved.extend(
+                commands
+                    .iter()
+                    .map(|command| command.replace("{connector}", &identity.name)),
+            );
+        }
+        resolved
+    }
+
+    /// Returns whether any head's configuration in `current_layout` differs from this layout in
+    /// `properties`. `layout_head_to_query_head` remaps heads in this layout to heads in
+    /// `current_layout`.
+    pub fn differs_from(
+        &self,
+        layout_head_to_query_head: &HashMap<HeadIdentity, HeadIdentity>,
+        current_layout: &HashMap<HeadIdentity, Option<SavedConfiguration>>,
+        properties: &[Property],
+    ) -> bool {
+        self.heads.iter().any(|(identity, configuration)| {
+            let identity = layout_head_to_query_head.get(identity).unwrap_or(identity);
+            match (configuration, current_layout.get(identity)) {
+                (None, Some(None)) => false,
+                (Some(configuration), Some(Some(current))) => {
+                    !configuration.is_satisfied_by(current, properties)
+                }
+                (_, Some(_)) => properties.contains(&Property::Enabled),
+                _ => true,
+            }
+        })
+    }
 }
 
 impl LayoutData {
     /// Loads an instance from `path`. Returns an empty instance if the file is not found (since
-    /// that indicates this is the first run).
+    /// that indicates this is the first run). A file that isn't valid JSON (e.g., it was truncated
+    /// by a crash) gives an [`ErrorKind::InvalidData`] error. A file that is valid JSON but doesn't
+    /// fit the format (e.g., a typo in a hand edit) gives an [`ErrorKind::Other`] error, since it
+    /// must be fixed rather than recovered from the backup.
     pub fn load(path: &Path) -> Result<Self, std::io::Error> {
-        let file = match std::fs::File::open(path) {
-            Ok(file) => file,
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
             Err(err) => {
                 return if err.kind() == ErrorKind::NotFound {
                     Ok(Self {
                         layouts: Default::default(),
+                        generation: 0,
+                        active_profile: None,
+                        profile_choices: Default::default(),
+                        match_fields: Default::default(),
+                        matcher: None,
+                        host: None,
+                        compositor: None,
+                        scope_to_compositor: false,
+                        match_supersets: false,
+                        match_subsets: false,
+                        lid: None,
+                        power_source: None,
+                        layout_files: Default::default(),
+                        skipped_layout_files: Default::default(),
                     })
                 } else {
                     Err(err)
                 }
             }
         };
-        let saved_layout_data: SavedLayoutData = serde_json::from_reader(BufReader::new(file))?;
+        let value: serde_json::Value = match serde_json::from_slice(&contents) {
+            Ok(value) => value,
+            Err(err) if matches!(err.classify(), Category::Syntax | Category::Eof) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "The layouts file {} isn't valid JSON: {err}",
+                        path.display()
+                    ),
+                ));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let version = format_version(&value);
+        if version > LAYOUTS_VERSION {
+            // Not InvalidData, since the file must not be treated as corrupted and replaced.
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "The layouts file {} is in format version {version}, but this version of \
+                     wl-distore only supports up to version {LAYOUTS_VERSION}. Upgrade wl-distore \
+                     to use it",
+                    path.display()
+                ),
+            ));
+        }
+        if version < LAYOUTS_VERSION {
+            info!(
+                "The layouts file {} is in format version {version}, so it will be upgraded to \
+                 version {LAYOUTS_VERSION} when it is next saved",
+                path.display()
+            );
+        }
+        let saved_layout_data: SavedLayoutData =
+            match serde_json::from_value(migrate(value, version)) {
+                Ok(saved_layout_data) => saved_layout_data,
+                Err(err) => {
+                    // Errors from a value don't say where they are, so parse the file again for
+                    // one. Migrated files are parsed in their new format, so they can't.
+                    let err = match version {
+                        LAYOUTS_VERSION => serde_json::from_slice::<SavedLayoutData>(&contents)
+                            .err()
+                            .unwrap_or(err),
+                        _ => err,
+                    };
+                    return Err(std::io::Error::other(format!(
+                        "The layouts file {} has an error: {err}. Fix it (see `wl-distore \
+                         validate`) to continue",
+                        path.display()
+                    )));
+                }
+            };
+        if version == LAYOUTS_VERSION
+            && saved_layout_data
+                .checksum
+                .as_ref()
+                .is_some_and(|checksum| *checksum != saved_layout_data.compute_checksum())
+        {
+            // The file is still valid, so someone edited it on purpose. The checksum is fixed the
+            // next time the layouts are saved.
+            info!(
+                "The layouts file {} was edited by hand, so accepting the changes",
+                path.display()
+            );
+        }
         Ok((&saved_layout_data).into())
     }
 
-    /// Saves self to the file at `path`.
-    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+    /// Like [`LayoutData::load`], but if the file at `path` was damaged (see [`is_damaged`]), the
+    /// layouts are recovered from the backup written next to it (see [`backup_path`]) as
+    /// `recovery` allows. Files that were edited by hand are never recovered, since that would
+    /// throw away the edits.
+    pub fn load_or_recover(path: &Path, recovery: Recovery) -> Result<Self, std::io::Error> {
+        let err = match Self::load(path) {
+            Err(err) if err.kind() == ErrorKind::InvalidData && recovery != Recovery::Never => err,
+            result => return result,
+        };
+        let backup_path = backup_path(path);
+        let backup = match std::fs::read(&backup_path) {
+            Ok(backup) => backup,
+            Err(backup_err) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{err}. There is no backup to recover from ({backup_err}), so fix or \
+                         remove the file to continue"
+                    ),
+                ));
+            }
+        };
+        let contents = std::fs::read(path)?;
+        if !is_damaged(&contents, &backup) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{err}. It isn't a damaged copy of the backup at {}, so it was probably edited \
+                     by hand. Fix it (see `wl-distore validate`) or remove it to continue",
+                    backup_path.display()
+                ),
+            ));
+        }
+        let layout_data = Self::load(&backup_path).map_err(|backup_err| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{err}. The backup at {} can't be used either ({backup_err}), so fix or remove \
+                     the file to continue",
+                    backup_path.display()
+                ),
+            )
+        })?;
+        match recovery {
+            Recovery::MoveAside => {
+                let corrupted_path = path.with_extension("corrupted.json");
+                std::fs::rename(path, &corrupted_path)?;
+                warn!(
+                    "{err}. Recovered the layouts from the backup at {}, and moved the corrupted \
+                     file to {}",
+                    backup_path.display(),
+                    corrupted_path.display()
+                );
+            }
+            _ => warn!(
+                "{err}. Using the layouts from the backup at {} instead",
+                backup_path.display()
+            ),
+        }
+        Ok(layout_data)
+    }
+
+    /// Loads the `seeds` (the read-only layouts from the config), the layouts from `read_only_path`
+    /// (if any) and the layouts from `path` (or from `dir` if there is one, see
+    /// [`LayoutData::load_dir`]), in that order, along with their metadata from the state file
+    /// next to `path`. The read-only layouts come first so they take precedence when matching.
+    pub fn load_with_read_only(
+        seeds: &[Layout],
+        read_only_path: Option<&Path>,
+        path: &Path,
+        dir: Option<&Path>,
+        recovery: Recovery,
+    ) -> Result<Self, std::io::Error> {
+        let mut layouts = seeds.to_vec();
+        layouts.extend(match read_only_path {
+            None => vec![],
+            Some(read_only_path) => Self::load(read_only_path)?
+                .layouts
+                .into_iter()
+                .map(|layout| Layout {
+                    read_only: true,
+                    ..layout
+                })
+                .collect(),
+        });
+        let mut layout_files = HashSet::new();
+        let mut skipped_layout_files = HashSet::new();
+        layouts.extend(match dir {
+            None => Self::load_or_recover(path, recovery)?.layouts,
+            Some(dir) => {
+                let loaded = Self::load_dir(dir)?;
+                layout_files = loaded.layout_files;
+                skipped_layout_files = loaded.skipped_layout_files;
+                loaded.layouts
+            }
+        });
+        let mut layout_data = Self {
+            layouts,
+            generation: 0,
+            active_profile: None,
+            profile_choices: Default::default(),
+            match_fields: Default::default(),
+            matcher: None,
+            host: None,
+            compositor: None,
+            scope_to_compositor: false,
+            match_supersets: false,
+            match_subsets: false,
+            lid: None,
+            power_source: None,
+            layout_files,
+            skipped_layout_files,
+        };
+        state::load(&state::state_path(path), &mut layout_data)?;
+        Ok(layout_data)
+    }
+
+    /// Loads the layouts stored one per file in `dir`, in the order of their file names. Layouts in
+    /// `.json` files are in the format of an entry of the layouts file. Layouts in `.toml` files
+    /// are in the same format, and are read-only since they're written by hand. A missing
+    /// directory has no layouts.
+    pub fn load_dir(dir: &Path) -> Result<Self, std::io::Error> {
+        let mut paths = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        paths.sort();
+        let mut layouts = vec![];
+        let mut layout_files = HashSet::new();
+        let mut skipped_layout_files = HashSet::new();
+        for path in paths {
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if !matches!(extension, Some("json" | "toml")) {
+                continue;
+            }
+            let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) else {
+                continue;
+            };
+            let layout = std::fs::read_to_string(&path).and_then(|contents| {
+                let invalid = |err: &dyn std::fmt::Display| {
+                    std::io::Error::new(ErrorKind::InvalidData, err.to_string())
+                };
+                if extension == Some("toml") {
+                    let layout: SavedLayoutFormat =
+                        toml::from_str(&contents).map_err(|err| invalid(&err))?;
+                    Ok(Layout {
+                        read_only: true,
+                        ..(&layout).into()
+                    })
+                } else {
+                    Layout::from_json(&contents).map_err(|err| invalid(&err))
+                }
+            });
+            match layout {
+                Ok(layout) => {
+                    if extension == Some("json") {
+                        layout_files.insert(file_name.to_string());
+                    }
+                    layouts.push(layout);
+                }
+                Err(err) => {
+                    error!(
+                        "Skipping the layout file {}, since it is invalid: {err}",
+                        path.display()
+                    );
+                    skipped_layout_files.insert(file_name.to_string());
+                }
+            }
+        }
+        Ok(Self {
+            layouts,
+            generation: 0,
+            active_profile: None,
+            profile_choices: Default::default(),
+            match_fields: Default::default(),
+            matcher: None,
+            host: None,
+            compositor: None,
+            scope_to_compositor: false,
+            match_supersets: false,
+            match_subsets: false,
+            lid: None,
+            power_source: None,
+            layout_files,
+            skipped_layout_files,
+        })
+    }
+
+    /// Returns the file name (see [`layout_file_name`]) and contents of each layout that isn't
+    /// read-only, for storing the layouts one per file in a directory. The names of the files that
+    /// couldn't be loaded aren't used.
+    pub fn to_layout_files(&self) -> Result<Vec<(String, Vec<u8>)>, serde_json::Error> {
+        let mut used_names = self.skipped_layout_files.clone();
+        self.layouts
+            .iter()
+            .filter(|layout| !layout.read_only)
+            .map(|layout| {
+                let file_name = layout_file_name(layout, &mut used_names);
+                Ok((file_name, layout.to_json()?.into_bytes()))
+            })
+            .collect()
+    }
+
+    /// Saves self to the file at `path` (see [`LayoutData::save`]), or to `dir` if there is one
+    /// (see [`write_layout_files`]).
+    pub fn save_to(&mut self, path: &Path, dir: Option<&Path>) -> Result<(), std::io::Error> {
+        match dir {
+            None => self.save(path),
+            Some(dir) => {
+                let files = self.to_layout_files()?;
+                self.layout_files
+                    .extend(files.iter().map(|(file_name, _)| file_name.clone()));
+                write_layout_files(dir, &files, &self.layout_files)
+            }
         }
-        let file = std::fs::File::create(path)?;
+    }
+
+    /// Saves self to the file at `path`, along with a backup (see [`backup_path`]). Read-only
+    /// layouts are skipped.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let contents = self.to_json()?;
+        write_atomically(path, &contents)?;
+        write_atomically(&backup_path(path), &contents)
+    }
+
+    /// Serializes self in the format of the layouts file. Read-only layouts are skipped.
+    pub fn to_json(&self) -> Result<Vec<u8>, serde_json::Error> {
         let saved_layout_data: SavedLayoutData = self.into();
-        serde_json::to_writer(BufWriter::new(file), &saved_layout_data)?;
-        Ok(())
+        serde_json::to_vec(&saved_layout_data)
+    }
+
+    /// Finds the index of a layout that matches the provided query. Layouts that aren't templates
+    /// are preferred, then layouts of the active profile, then exact matches, then layouts for the
+    /// current state of the lid, then layouts for the current power source, then layouts with a
+    /// higher [`Layout::priority`], then the most recently applied layouts (so a layout picked
+    /// among several for the same heads stays picked when they're reconnected), then earlier
+    /// layouts. Layouts that aren't automatically applied still match, so changes are saved into
+    /// them, see [`LayoutData::find_layout_to_apply`]. The [`Matcher`] isn't asked, see
+    /// [`LayoutData::ask_matcher`]. Returns the index along with how well the layout matched and
+    /// the mapping from its heads to the query's heads.
+    pub fn find_layout_match(&self, query_layout: &HashSet<HeadIdentity>) -> Option<LayoutMatch> {
+        self.find_best_match(query_layout, false)
     }
 
-    /// Finds the index of a layout that matches the provided query..
-    pub fn find_layout_match(
+    /// Finds the layout to apply automatically to the provided query, like
+    /// [`LayoutData::find_layout_match`] but skipping layouts that aren't automatically applied.
+    pub fn find_layout_to_apply(
         &self,
         query_layout: &HashSet<HeadIdentity>,
-    ) -> Option<(usize, HashMap<HeadIdentity, HeadIdentity>)> {
+    ) -> Option<LayoutMatch> {
+        self.find_best_match(query_layout, true)
+    }
+
+    /// Finds the best match for [`LayoutData::find_layout_match`], only among the layouts that are
+    /// automatically applied if `auto_apply_only` is set.
+    fn find_best_match(
+        &self,
+        query_layout: &HashSet<HeadIdentity>,
+        auto_apply_only: bool,
+    ) -> Option<LayoutMatch> {
+        let heads_key = head_set_key(query_layout.iter());
         let mut best_match = None;
         for (index, saved_layout) in self.layouts.iter().enumerate() {
-            let match_score = LayoutMatchScore::score(
-                saved_layout.keys().cloned().collect(),
-                query_layout.clone(),
-            );
+            if !self.matchable(saved_layout) || (auto_apply_only && !saved_layout.auto_apply) {
+                continue;
+            }
+            let match_score =
+                LayoutMatchScore::score(saved_layout, query_layout, &self.match_fields, false);
 
             let Some((match_score, layout_head_to_query_head)) = match_score else {
                 continue;
             };
-
-            if match_score == LayoutMatchScore::Exact {
-                return Some((index, HashMap::new()));
-            }
+            let layout_match = (index, match_score, layout_head_to_query_head);
+            let match_score = (
+                !saved_layout.template,
+                self.is_active_profile(saved_layout, &heads_key),
+                match_score,
+                self.for_lid(saved_layout),
+                self.for_power_source(saved_layout),
+                saved_layout.priority,
+                saved_layout.metadata.last_applied,
+            );
 
             let Some((best_score, _)) = best_match.as_ref() else {
-                best_match = Some((match_score, (index, layout_head_to_query_head)));
+                best_match = Some((match_score, layout_match));
                 continue;
             };
 
             if match_score > *best_score {
-                best_match = Some((match_score, (index, layout_head_to_query_head)));
+                best_match = Some((match_score, layout_match));
             }
         }
         best_match.map(|(_, match_)| match_)
     }
+
+    /// Asks the [`Matcher`] which of the layouts matching `query_layout` to apply, where `builtin`
+    /// is the index found by [`LayoutData::find_layout_match`]. The matcher isn't run if there is
+    /// none, or if no layout matches, since there is nothing to pick between.
+    pub fn ask_matcher(
+        &self,
+        query_layout: &HashSet<HeadIdentity>,
+        builtin: Option<usize>,
+    ) -> MatcherPick {
+        let Some(matcher) = self.matcher.as_ref() else {
+            return MatcherPick::Builtin;
+        };
+        let candidates = self
+            .layouts
+            .iter()
+            .enumerate()
+            .filter(|(_, layout)| {
+                self.matchable(layout)
+                    && layout.auto_apply
+                    && LayoutMatchScore::score(layout, query_layout, &self.match_fields, false)
+                        .is_some()
+            })
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return MatcherPick::Builtin;
+        }
+        match matcher.pick(query_layout, &candidates, builtin) {
+            Ok(Some(index)) => MatcherPick::Layout(index),
+            Ok(None) => MatcherPick::Nothing,
+            Err(err) => {
+                warn!("Failed to run the matcher, so using the built-in match: {err}");
+                MatcherPick::Builtin
+            }
+        }
+    }
+
+    /// Matches `query_layout` against the layout at `index` alone, e.g. the layout picked by the
+    /// [`Matcher`]. Returns the match like [`LayoutData::find_layout_match`].
+    pub fn match_layout(
+        &self,
+        index: usize,
+        query_layout: &HashSet<HeadIdentity>,
+    ) -> Option<LayoutMatch> {
+        let layout = self.layouts.get(index)?;
+        LayoutMatchScore::score(layout, query_layout, &self.match_fields, false)
+            .map(|(score, layout_head_to_query_head)| (index, score, layout_head_to_query_head))
+    }
+
+    /// Finds the index of a layout that only matches some of the heads, for when no layout matches
+    /// `query_layout` exactly. If [`LayoutData::match_supersets`] is set, the layout's heads may
+    /// all be among the heads of `query_layout` (e.g., a dock added a display). If
+    /// [`LayoutData::match_subsets`] is set, the heads of `query_layout` may all be among the
+    /// layout's heads (e.g., a monitor is switched off at the wall). Layouts that aren't templates
+    /// are preferred, then supersets over subsets, then layouts with the fewest extra or missing
+    /// heads, then layouts of the active profile, then layouts for the current state of the lid,
+    /// then layouts for the current power source, then layouts with a higher [`Layout::priority`],
+    /// then the most recently applied layouts, then earlier layouts. Layouts that aren't
+    /// automatically applied are skipped, and partial matches aren't given to the [`Matcher`].
+    /// Returns the match like [`LayoutData::find_layout_match`].
+    pub fn find_partial_layout_match(
+        &self,
+        query_layout: &HashSet<HeadIdentity>,
+    ) -> Option<LayoutMatch> {
+        if query_layout.is_empty() {
+            return None;
+        }
+        let heads_key = head_set_key(query_layout.iter());
+        self.layouts
+            .iter()
+            .enumerate()
+            .filter(|(_, layout)| {
+                self.matchable(layout) && layout.auto_apply && !layout.heads.is_empty()
+            })
+            .filter_map(|(index, layout)| {
+                match LayoutMatchScore::score(layout, query_layout, &self.match_fields, true)? {
+                    (LayoutMatchScore::Superset, layout_head_to_query_head)
+                        if self.match_supersets =>
+                    {
+                        Some((index, LayoutMatchScore::Superset, layout_head_to_query_head))
+                    }
+                    (LayoutMatchScore::Subset, layout_head_to_query_head) if self.match_subsets => {
+                        Some((index, LayoutMatchScore::Subset, layout_head_to_query_head))
+                    }
+                    _ => None,
+                }
+            })
+            .max_by_key(|(index, score, _)| {
+                let layout = &self.layouts[*index];
+                (
+                    !layout.template,
+                    *score,
+                    Reverse(layout.heads.len().abs_diff(query_layout.len())),
+                    self.is_active_profile(layout, &heads_key),
+                    self.for_lid(layout),
+                    self.for_power_source(layout),
+                    layout.priority,
+                    layout.metadata.last_applied,
+                    Reverse(*index),
+                )
+            })
+    }
+
+    /// Returns whether `layout` may be matched and changed on this host (see [`LayoutData::host`])
+    /// and under this compositor (see [`LayoutData::scope_to_compositor`]).
+    pub fn in_scope(&self, layout: &Layout) -> bool {
+        let same_host = self.host.is_none() || layout.host.is_none() || layout.host == self.host;
+        let same_compositor = !self.scope_to_compositor
+            || self.compositor.is_none()
+            || layout.compositor.is_none()
+            || layout.compositor == self.compositor;
+        same_host && same_compositor
+    }
+
+    /// Returns whether `layout` is automatically matched against the current heads: it is in scope
+    /// (see [`LayoutData::in_scope`]), and not for the other state of the lid or the other power
+    /// source.
+    fn matchable(&self, layout: &Layout) -> bool {
+        let same_lid = self.lid.is_none() || layout.lid.is_none() || layout.lid == self.lid;
+        let same_power_source = self.power_source.is_none()
+            || layout.power_source.is_none()
+            || layout.power_source == self.power_source;
+        self.in_scope(layout) && same_lid && same_power_source
+    }
+
+    /// Returns whether `layout` is for the current state of the lid.
+    fn for_lid(&self, layout: &Layout) -> bool {
+        layout.lid.is_some() && layout.lid == self.lid
+    }
+
+    /// Returns whether `layout` is for the current power source.
+    fn for_power_source(&self, layout: &Layout) -> bool {
+        layout.power_source.is_some() && layout.power_source == self.power_source
+    }
+
+    /// Returns `layout` scoped to this host and compositor, for saving it as a new layout. Layouts
+    /// saved while the lid is closed are kept for the closed lid.
+    pub fn scoped(&self, layout: Layout) -> Layout {
+        Layout {
+            host: self.host.clone(),
+            compositor: self.compositor.clone(),
+            lid: self.lid.filter(|lid| *lid == LidState::Closed),
+            ..layout
+        }
+    }
+
+    /// Returns whether saving the current configuration over `layout` saves it as a new layout
+    /// instead: templates are never saved over, and neither are layouts for any state of the lid
+    /// while it is closed (so the closed lid gets its own layout).
+    pub fn saves_as_new_layout(&self, layout: &Layout) -> bool {
+        layout.template || (self.lid == Some(LidState::Closed) && layout.lid.is_none())
+    }
+
+    /// Returns the profile preferred for the heads in `heads_key` (see [`head_set_key`]): the
+    /// profile chosen for them, or else the active profile.
+    pub fn preferred_profile(&self, heads_key: &[HeadIdentity]) -> Option<&str> {
+        self.profile_choices
+            .get(heads_key)
+            .or(self.active_profile.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Records `profile` as the profile chosen for `heads`, so it is preferred whenever they are
+    /// connected.
+    pub fn choose_profile(&mut self, heads: &HashSet<HeadIdentity>, profile: &str) {
+        self.profile_choices
+            .insert(head_set_key(heads.iter()), profile.to_string());
+    }
+
+    /// Returns whether `layout` belongs to the profile preferred for the heads in `heads_key`.
+    /// Always false if there is none.
+    fn is_active_profile(&self, layout: &Layout, heads_key: &[HeadIdentity]) -> bool {
+        self.preferred_profile(heads_key) == Some(layout.profile())
+    }
+
+    /// Returns whether any layout has a head that is the same as `identity` (or, for templates,
+    /// that `identity` fits).
+    pub fn knows_head(&self, identity: &HeadIdentity) -> bool {
+        self.layouts.iter().any(|layout| {
+            layout.heads.keys().any(|head| {
+                if layout.template {
+                    self.match_fields.matches_pattern(head, identity)
+                } else {
+                    self.match_fields.same_head(head, identity)
+                }
+            })
+        })
+    }
+
+    /// Returns the profiles of the layouts (that are automatically applied) matching
+    /// `query_layout`, in the order of their first layout.
+    pub fn matching_profiles(&self, query_layout: &HashSet<HeadIdentity>) -> Vec<&str> {
+        let mut profiles = vec![];
+        for layout in self.layouts.iter() {
+            if self.matchable(layout)
+                && layout.auto_apply
+                && layout.matches(query_layout, &self.match_fields)
+                && !profiles.contains(&layout.profile())
+            {
+                profiles.push(layout.profile());
+            }
+        }
+        profiles
+    }
+
+    /// Removes the least recently used layouts (see [`LayoutMetadata::last_used`]) until at most
+    /// `max_layouts` layouts that aren't read-only remain. Layouts that have never been used go
+    /// first. The layout at `keep`, read-only and pinned layouts, templates, and layouts out of
+    /// scope (see [`LayoutData::in_scope`]) are never removed. Returns the indices of the removed
+    /// layouts, in ascending order.
+    pub fn evict(&mut self, max_layouts: usize, keep: Option<usize>) -> Vec<usize> {
+        let stored = self
+            .layouts
+            .iter()
+            .filter(|layout| !layout.read_only)
+            .count();
+        let excess = stored.saturating_sub(max_layouts);
+        if excess == 0 {
+            return vec![];
+        }
+        let mut candidates = (0..self.layouts.len())
+            .filter(|index| {
+                let layout = &self.layouts[*index];
+                Some(*index) != keep
+                    && !layout.read_only
+                    && !layout.pinned
+                    && !layout.template
+                    && self.in_scope(layout)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|index| (self.layouts[*index].metadata.last_used(), *index));
+        let mut evicted = candidates.into_iter().take(excess).collect::<Vec<_>>();
+        evicted.sort();
+        let mut index = 0;
+        self.layouts.retain(|_| {
+            let kept = evicted.binary_search(&index).is_err();
+            index += 1;
+            kept
+        });
+        evicted
+    }
+
+    /// Merges layouts with the same heads, configurations, profile, host, compositor, lid, power
+    /// source, `auto_apply`, `pinned` and `priority` into the most recently used of them, filling
+    /// in any name, comments and head commands it is missing from the others. Layouts that set
+    /// these differently aren't merged (see [`Layout::conflicts_with`]). The merged layout takes
+    /// the place of the first of them, so it keeps its precedence when matching. Read-only layouts
+    /// and templates are left alone. Returns the index of each merged layout, along with
+    /// the indices (from before merging) of the layouts merged into it.
+    pub fn dedupe(&mut self) -> Vec<(usize, Vec<usize>)> {
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for (index, layout) in self.layouts.iter().enumerate() {
+            let group = groups.iter_mut().find(|group| {
+                let first = &self.layouts[group[0]];
+                !layout.read_only
+                    && !first.read_only
+                    && !layout.template
+                    && !first.template
+                    && first.auto_apply == layout.auto_apply
+                    && first.pinned == layout.pinned
+                    && first.priority == layout.priority
+                    && first.profile() == layout.profile()
+                    && first.host == layout.host
+                    && first.compositor == layout.compositor
+                    && first.lid == layout.lid
+                    && first.power_source == layout.power_source
+                    && first.heads == layout.heads
+                    && group
+                        .iter()
+                        .all(|member| !self.layouts[*member].conflicts_with(layout))
+            });
+            match group {
+                Some(group) => group.push(index),
+                None => groups.push(vec![index]),
+            }
+        }
+
+        let mut layouts = std::mem::take(&mut self.layouts)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let mut merges = vec![];
+        for group in groups {
+            // Reversed so the first of the most recently used layouts is kept.
+            let kept = *group
+                .iter()
+                .rev()
+                .max_by_key(|index| layouts[**index].as_ref().unwrap().metadata.last_used())
+                .expect("Groups are never empty");
+            let mut merged = layouts[kept].take().unwrap();
+            for index in group.iter().copied().filter(|index| *index != kept) {
+                let other = layouts[index].take().unwrap();
+                merged.name = merged.name.or(other.name);
+                merged.comment = merged.comment.or(other.comment);
+                for (head, commands) in other.head_commands {
+                    merged.head_commands.entry(head).or_insert(commands);
+                }
+                for (head, comment) in other.head_comments {
+                    merged.head_comments.entry(head).or_insert(comment);
+                }
+                merged.metadata.last_saved =
+                    merged.metadata.last_saved.max(other.metadata.last_saved);
+                merged.metadata.last_applied = merged
+                    .metadata
+                    .last_applied
+                    .max(other.metadata.last_applied);
+                merged.metadata.last_matched = merged
+                    .metadata
+                    .last_matched
+                    .max(other.metadata.last_matched);
+                merged.metadata.apply_count += other.metadata.apply_count;
+            }
+            if group.len() > 1 {
+                merges.push((self.layouts.len(), group));
+            }
+            self.layouts.push(merged);
+        }
+        merges
+    }
+
+    /// Finds the index of the layout named `name`.
+    pub fn find_layout_by_name(&self, name: &str) -> Option<usize> {
+        self.layouts
+            .iter()
+            .position(|layout| layout.name.as_deref() == Some(name))
+    }
+
+    /// Finds the index of the layout referred to by `selector`, which is either the name or the
+    /// index of a layout. Names take precedence.
+    pub fn find_layout(&self, selector: &str) -> Option<usize> {
+        self.find_layout_by_name(selector).or_else(|| {
+            selector
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index < self.layouts.len())
+        })
+    }
+}
+
+/// Returns the path of the backup of the layouts file at `path`, e.g. `layouts.bak.json` for
+/// `layouts.json`. The backup is written after the layouts file, so a crash while writing one of
+/// them leaves the other intact.
+pub fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("bak.json")
+}
+
+/// Returns the JSON Schema of the layouts file.
+pub fn json_schema() -> String {
+    serde_json::to_string_pretty(&schemars::schema_for!(SavedLayoutData))
+        .expect("The schema can be serialized")
+}
+
+/// Returns the format version of the contents of a layouts file.
+pub fn format_version(value: &serde_json::Value) -> u64 {
+    value
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0)
+}
+
+/// Upgrades the contents of a layouts file from format `version` to [`LAYOUTS_VERSION`], one
+/// version at a time.
+fn migrate(mut value: serde_json::Value, version: u64) -> serde_json::Value {
+    if version < 1 {
+        // Version 1 added the version, and layouts are no longer saved as just a list of heads.
+        let layouts = value
+            .get_mut("layouts")
+            .and_then(serde_json::Value::as_array_mut);
+        for layout in layouts.into_iter().flatten() {
+            if layout.is_array() {
+                *layout = serde_json::json!({ "heads": layout.take() });
+            }
+        }
+    }
+    value
+}
+
+/// Checks the contents of a layouts file, returning a description of each problem found. Schema
+/// errors name the field they are in, and then the layouts are checked for problems that would
+/// make them misbehave (e.g., a scale of zero).
+pub fn validate(contents: &[u8]) -> Vec<String> {
+    let value: serde_json::Value = match serde_json::from_slice(contents) {
+        Ok(value) => value,
+        Err(err) => return vec![err.to_string()],
+    };
+    let version = format_version(&value);
+    if version > LAYOUTS_VERSION {
+        return vec![format!(
+            "the file is in format version {version}, but only versions up to {LAYOUTS_VERSION} \
+             are supported"
+        )];
+    }
+    let legacy_layouts = value
+        .get("layouts")
+        .and_then(serde_json::Value::as_array)
+        .is_some_and(|layouts| layouts.iter().any(serde_json::Value::is_array));
+    let layouts = if legacy_layouts {
+        // Legacy layouts can only be told apart by their shape, so check each layout on its own.
+        // This loses the line numbers, but keeps the field paths.
+        let mut problems = vec![];
+        let mut layouts = vec![];
+        for (index, layout) in value["layouts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let result = if layout.is_array() {
+                serde_path_to_error::deserialize(layout).map(SavedLayoutFormat::Legacy)
+            } else {
+                serde_path_to_error::deserialize(layout)
+                    .map(|layout| SavedLayoutFormat::Current(Box::new(layout)))
+            };
+            match result {
+                Ok(layout) => layouts.push(layout),
+                Err(err) => {
+                    // Paths within a legacy layout start with an index, and the root is ".".
+                    let path = match err.path().to_string() {
+                        path if path == "." => String::new(),
+                        path if path.starts_with('[') => path,
+                        path => format!(".{path}"),
+                    };
+                    problems.push(format!("layouts[{index}]{path}: {}", err.inner()))
+                }
+            }
+        }
+        if !problems.is_empty() {
+            return problems;
+        }
+        layouts
+    } else {
+        #[derive(Deserialize)]
+        struct StrictSavedLayoutData {
+            layouts: Vec<SavedLayout>,
+        }
+        let deserializer = &mut serde_json::Deserializer::from_slice(contents);
+        match serde_path_to_error::deserialize::<_, StrictSavedLayoutData>(deserializer) {
+            Ok(layout_data) => layout_data
+                .layouts
+                .into_iter()
+                .map(|layout| SavedLayoutFormat::Current(Box::new(layout)))
+                .collect(),
+            Err(err) => return vec![format!("{}: {}", err.path(), err.inner())],
+        }
+    };
+
+    let mut problems = vec![];
+    let mut names = HashMap::new();
+    for (index, saved_layout) in layouts.iter().enumerate() {
+        // Duplicate heads are lost when converting to a `Layout`, so the heads are checked as
+        // they were saved.
+        let heads = match saved_layout {
+            SavedLayoutFormat::Current(layout) => &layout.heads,
+            SavedLayoutFormat::Legacy(heads) => heads,
+        };
+        let layout = Layout::from(saved_layout);
+        let label = match layout.name.as_deref() {
+            Some(name) => format!("layout {index} (\"{name}\")"),
+            None => format!("layout {index}"),
+        };
+        if let Some(name) = layout.name.as_deref() {
+            if let Some(other) = names.insert(name.to_string(), index) {
+                problems.push(format!("{label}: layout {other} has the same name"));
+            }
+        }
+        let mut identities = HashSet::new();
+        for (identity, configuration) in heads {
+            if !identities.insert(identity) {
+                problems.push(format!(
+                    "{label}: head {} appears more than once",
+                    identity.name
+                ));
+            }
+            let Some(configuration) = configuration else {
+                continue;
+            };
+            if configuration
+                .mode
+                .is_some_and(|mode| mode.size.0 == 0 || mode.size.1 == 0)
+            {
+                problems.push(format!(
+                    "{label}: head {} has a mode with a zero size",
+                    identity.name
+                ));
+            }
+            let valid_scale = configuration.scale.is_finite() && configuration.scale > 0.0;
+            if !configuration.dont_care.scale && !valid_scale {
+                problems.push(format!(
+                    "{label}: head {} has a scale of {}, which must be positive",
+                    identity.name, configuration.scale
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Returns the name of the file (in a layouts directory) to store `layout` in: its name, or its
+/// heads (and profile) if it has none. Names in `used_names` get a number appended, and the
+/// returned name is added to it.
+fn layout_file_name(layout: &Layout, used_names: &mut HashSet<String>) -> String {
+    let stem = match &layout.name {
+        Some(name) => name.clone(),
+        None => {
+            let mut stem = head_set_key(layout.heads.keys())
+                .iter()
+                .map(|head| head.name.as_str())
+                .collect::<Vec<_>>()
+                .join("+");
+            if let Some(profile) = &layout.profile {
+                stem = format!("{stem}@{profile}");
+            }
+            stem
+        }
+    };
+    // Keep the name to a single file that isn't hidden.
+    let mut stem = stem.replace(['/', '\0'], "_");
+    if stem.is_empty() || stem.starts_with('.') {
+        stem.insert(0, '_');
+    }
+    let mut file_name = format!("{stem}.json");
+    let mut number = 2;
+    while used_names.contains(&file_name) {
+        file_name = format!("{stem}-{number}.json");
+        number += 1;
+    }
+    used_names.insert(file_name.clone());
+    file_name
+}
+
+/// Writes each of `files` (pairs of a file name and its contents) to `dir`, skipping the files
+/// that are unchanged, and removes the other files in it that are among `layout_files` (see
+/// [`LayoutData::layout_files`]), which are for layouts that no longer exist.
+pub fn write_layout_files(
+    dir: &Path,
+    files: &[(String, Vec<u8>)],
+    layout_files: &HashSet<String>,
+) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dir)?;
+    for (file_name, contents) in files {
+        let path = dir.join(file_name);
+        if std::fs::read(&path).ok().as_ref() != Some(contents) {
+            write_atomically(&path, contents)?;
+        }
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) else {
+            continue;
+        };
+        let stale = layout_files.contains(file_name)
+            && !files
+                .iter()
+                .any(|(written_name, _)| written_name == file_name);
+        if stale {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `heads` in a canonical order, so equal sets of heads give equal keys.
+pub fn head_set_key<'a>(heads: impl Iterator<Item = &'a HeadIdentity>) -> Vec<HeadIdentity> {
+    let mut heads = heads.cloned().collect::<Vec<_>>();
+    heads.sort_by(|a, b| {
+        (
+            &a.name,
+            &a.description,
+            &a.make,
+            &a.model,
+            &a.serial_number,
+            &a.edid,
+            &a.physical_size,
+        )
+            .cmp(&(
+                &b.name,
+                &b.description,
+                &b.make,
+                &b.model,
+                &b.serial_number,
+                &b.edid,
+                &b.physical_size,
+            ))
+    });
+    heads
+}
+
+/// A field of a [`HeadIdentity`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityField {
+    Name,
+    Description,
+    Make,
+    Model,
+    SerialNumber,
+    Edid,
+    PhysicalSize,
+}
+
+impl IdentityField {
+    fn get(self, identity: &HeadIdentity) -> Option<Cow<'_, str>> {
+        match self {
+            Self::Name => Some(Cow::Borrowed(&identity.name)),
+            Self::Description => Some(Cow::Borrowed(&identity.description)),
+            Self::Make => identity.make.as_deref().map(Cow::Borrowed),
+            Self::Model => identity.model.as_deref().map(Cow::Borrowed),
+            Self::SerialNumber => identity.serial_number.as_deref().map(Cow::Borrowed),
+            Self::Edid => identity.edid.as_deref().map(Cow::Borrowed),
+            Self::PhysicalSize => identity
+                .physical_size
+                .map(|(width, height)| Cow::Owned(format!("{width}x{height}"))),
+        }
+    }
+
+    /// Returns whether `a` and `b` agree on this field. The EDID and physical size are only
+    /// compared if both heads have one, since not every head has them (nor do layouts saved before
+    /// they were recorded).
+    fn agrees(self, a: &HeadIdentity, b: &HeadIdentity) -> bool {
+        match (self, self.get(a), self.get(b)) {
+            (Self::Edid | Self::PhysicalSize, None, _)
+            | (Self::Edid | Self::PhysicalSize, _, None) => true,
+            (_, a, b) => a == b,
+        }
+    }
+}
+
+/// The fields of [`HeadIdentity`] compared when matching heads. Heads that agree on all of them
+/// are the same head. Heads that only agree on the make, model and serial number among them are
+/// the same monitor on another connector, so layouts can still be applied to them (by remapping
+/// heads). If the EDID is compared, heads whose EDIDs are both known are the same monitor exactly
+/// when those agree. If the physical size is compared, the same monitor must also have the same
+/// size. By default, every field but the EDID and the physical size is compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchFields(pub Vec<IdentityField>);
+
+impl Default for MatchFields {
+    fn default() -> Self {
+        Self(vec![
+            IdentityField::Name,
+            IdentityField::Description,
+            IdentityField::Make,
+            IdentityField::Model,
+            IdentityField::SerialNumber,
+        ])
+    }
+}
+
+impl MatchFields {
+    /// Returns whether `a` and `b` are the same head.
+    fn same_head(&self, a: &HeadIdentity, b: &HeadIdentity) -> bool {
+        self.0.iter().all(|field| field.agrees(a, b))
+    }
+
+    /// Returns whether the equal heads `a` and `b` also agree on the compared fields that aren't
+    /// part of [`HeadIdentity`]'s equality (the EDID and physical size).
+    fn same_beyond_equality(&self, a: &HeadIdentity, b: &HeadIdentity) -> bool {
+        self.0
+            .iter()
+            .filter(|field| matches!(field, IdentityField::Edid | IdentityField::PhysicalSize))
+            .all(|field| field.agrees(a, b))
+    }
+
+    /// Returns whether `query_head` is the same monitor as `layout_head`, possibly on another
+    /// connector.
+    fn same_monitor(&self, layout_head: &HeadIdentity, query_head: &HeadIdentity) -> bool {
+        if self.0.contains(&IdentityField::Edid) {
+            if let (Some(a), Some(b)) = (&layout_head.edid, &query_head.edid) {
+                return a == b;
+            }
+        }
+        let fields = self
+            .0
+            .iter()
+            .filter(|field| {
+                matches!(
+                    field,
+                    IdentityField::Make | IdentityField::Model | IdentityField::SerialNumber
+                )
+            })
+            .collect::<Vec<_>>();
+        // In-exact matches don't make sense if we don't have the make/model (or anything at all).
+        fields.iter().any(|field| field.get(layout_head).is_some())
+            && fields.iter().all(|field| {
+                **field == IdentityField::SerialNumber || field.get(layout_head).is_some()
+            })
+            && fields
+                .iter()
+                .all(|field| field.get(layout_head) == field.get(query_head))
+            && (!self.0.contains(&IdentityField::PhysicalSize)
+                || IdentityField::PhysicalSize.agrees(layout_head, query_head))
+    }
+
+    /// Maps the heads in `layout` to the heads in `query_layout`, first by [`Self::same_head`] and
+    /// then by [`Self::same_monitor`]. Heads that are exactly equal must already be removed from
+    /// both. Matched heads are removed from `layout`. Returns the mapping, and whether any head was
+    /// only matched as the same monitor.
+    ///
+    /// Identical monitors (e.g., two panels of the same model without serial numbers) are told
+    /// apart by their connectors: a head keeps the connector it had if it can, and otherwise heads
+    /// are paired in the order of their connector names, so the same monitor always gets the same
+    /// configuration.
+    fn map_heads(
+        &self,
+        layout: &mut HashSet<HeadIdentity>,
+        query_layout: HashSet<HeadIdentity>,
+    ) -> (HashMap<HeadIdentity, HeadIdentity>, Vec<HeadIdentity>, bool) {
+        let mut query_layout = query_layout.into_iter().collect::<Vec<_>>();
+        query_layout.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut layout_head_to_query_head = HashMap::new();
+        let unmatched_query_heads = pair_heads(
+            layout,
+            query_layout,
+            |layout_head, query_head| self.same_head(layout_head, query_head),
+            &mut layout_head_to_query_head,
+        );
+        let matched = layout_head_to_query_head.len();
+        let unmatched = pair_heads(
+            layout,
+            unmatched_query_heads,
+            |layout_head, query_head| self.same_monitor(layout_head, query_head),
+            &mut layout_head_to_query_head,
+        );
+        let remapped = layout_head_to_query_head.len() > matched;
+        (layout_head_to_query_head, unmatched, remapped)
+    }
+
+    /// Returns whether `head` fits `pattern`, a head of a template layout. The compared fields of
+    /// `pattern` are glob patterns (see [`glob_matches`]), and the optional fields it leaves out
+    /// match any value. The EDID and physical size are compared as usual.
+    pub fn matches_pattern(&self, pattern: &HeadIdentity, head: &HeadIdentity) -> bool {
+        self.0.iter().all(|field| match field {
+            IdentityField::Edid | IdentityField::PhysicalSize => field.agrees(pattern, head),
+            _ => field.get(pattern).is_none_or(|pattern| {
+                glob_matches(&pattern, field.get(head).as_deref().unwrap_or_default())
+            }),
+        })
+    }
+
+    /// Maps the heads of a template layout in `layout` to the heads in `query_layout` that fit them
+    /// (see [`Self::matches_pattern`]), like [`Self::map_heads`]. Heads are never considered
+    /// remapped, since fitting the patterns is what the template asks for.
+    fn map_pattern_heads(
+        &self,
+        layout: &mut HashSet<HeadIdentity>,
+        query_layout: HashSet<HeadIdentity>,
+    ) -> (HashMap<HeadIdentity, HeadIdentity>, Vec<HeadIdentity>, bool) {
+        let mut query_layout = query_layout.into_iter().collect::<Vec<_>>();
+        query_layout.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut layout_head_to_query_head = HashMap::new();
+        let unmatched = pair_heads(
+            layout,
+            query_layout,
+            |pattern, query_head| self.matches_pattern(pattern, query_head),
+            &mut layout_head_to_query_head,
+        );
+        (layout_head_to_query_head, unmatched, false)
+    }
+}
+
+/// Pairs each of `query_heads` (sorted by name) with a head in `layout` accepted by `matches`,
+/// moving the pairs from `layout` to `layout_head_to_query_head`. Heads on the same connector are
+/// paired first, then the rest in the order of their connector names. Returns the query heads
+/// that weren't paired.
+fn pair_heads(
+    layout: &mut HashSet<HeadIdentity>,
+    query_heads: Vec<HeadIdentity>,
+    matches: impl Fn(&HeadIdentity, &HeadIdentity) -> bool,
+    layout_head_to_query_head: &mut HashMap<HeadIdentity, HeadIdentity>,
+) -> Vec<HeadIdentity> {
+    let mut unpaired = vec![];
+    for query_head in query_heads {
+        let same_connector = layout
+            .iter()
+            .find(|layout_head| {
+                layout_head.name == query_head.name && matches(layout_head, &query_head)
+            })
+            .cloned();
+        match same_connector {
+            Some(layout_head) => {
+                layout.remove(&layout_head);
+                layout_head_to_query_head.insert(layout_head, query_head);
+            }
+            None => unpaired.push(query_head),
+        }
+    }
+    let mut unmatched = vec![];
+    for query_head in unpaired {
+        let first = layout
+            .iter()
+            .filter(|layout_head| matches(layout_head, &query_head))
+            .min_by(|a, b| a.name.cmp(&b.name))
+            .cloned();
+        match first {
+            Some(layout_head) => {
+                layout.remove(&layout_head);
+                layout_head_to_query_head.insert(layout_head, query_head);
+            }
+            None => unmatched.push(query_head),
+        }
+    }
+    unmatched
+}
+
+/// Maps each head of `layout` to the head in `query_layout` it corresponds to. Heads are first
+/// matched exactly, then by `fields`, and then as the same monitor on another connector (see
+/// [`MatchFields`]). The heads of templates are matched as patterns instead (see
+/// [`MatchFields::matches_pattern`]). Unlike [`LayoutData::find_layout_match`], heads may be
+/// missing from either side, in which case they are left out of the mapping.
+pub fn map_layout_heads(
+    layout: &Layout,
+    query_layout: &HashSet<HeadIdentity>,
+    fields: &MatchFields,
+) -> HashMap<HeadIdentity, HeadIdentity> {
+    let mut unmatched_query_heads = query_layout.clone();
+    let mut unmatched_layout_heads = HashSet::new();
+    let mut layout_head_to_query_head = HashMap::new();
+    for layout_head in layout.heads.keys() {
+        if unmatched_query_heads.remove(layout_head) {
+            layout_head_to_query_head.insert(layout_head.clone(), layout_head.clone());
+        } else {
+            unmatched_layout_heads.insert(layout_head.clone());
+        }
+    }
+    let (remapped, _, _) = if layout.template {
+        fields.map_pattern_heads(&mut unmatched_layout_heads, unmatched_query_heads)
+    } else {
+        fields.map_heads(&mut unmatched_layout_heads, unmatched_query_heads)
+    };
+    layout_head_to_query_head.extend(remapped);
+    layout_head_to_query_head
 }
 
+/// A layout matching some heads: its index, how well it matched, and the mapping from its heads
+/// to the heads they were matched to (see [`LayoutMatchScore::score`]).
+pub type LayoutMatch = (usize, LayoutMatchScore, HashMap<HeadIdentity, HeadIdentity>);
+
+/// How well a layout matches some heads. Later variants are better matches.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
-enum LayoutMatchScore {
+pub enum LayoutMatchScore {
+    /// Some heads of the layout are missing, but every head present is in the layout.
+    Subset,
+    /// Every head of the layout is present, along with heads the layout doesn't have.
+    Superset,
     /// The layout doesn't match exactly, but all the same heads are present.
     SameHeads,
     /// The layout matches all heads exactly.
@@ -207,87 +1869,735 @@ enum LayoutMatchScore {
 
 impl LayoutMatchScore {
     /// Compute the score between `layout` and `query_layout`. For in-exact matches, also returns a
-    /// mapping from the query head to the "fuzzy-matched" layout head.
+    /// mapping from the query head to the "fuzzy-matched" layout head. The heads of templates are
+    /// matched as patterns (see [`MatchFields::matches_pattern`]). If `allow_partial` is set,
+    /// either side may also have heads the other doesn't (but not both), which are left out of the
+    /// mapping.
     fn score(
-        mut layout: HashSet<HeadIdentity>,
-        mut query_layout: HashSet<HeadIdentity>,
+        layout: &Layout,
+        query_layout: &HashSet<HeadIdentity>,
+        fields: &MatchFields,
+        allow_partial: bool,
     ) -> Option<(Self, HashMap<HeadIdentity, HeadIdentity>)> {
+        let mut query_layout = query_layout.clone();
+        let template = layout.template;
+        let mut layout = layout.heads.keys().cloned().collect::<HashSet<_>>();
         // If the number of heads is different, immediately consider this a non-match.
-        if layout.len() != query_layout.len() {
+        if layout.len() != query_layout.len() && !allow_partial {
             return None;
         }
 
         // Remove any heads that match exactly.
-        query_layout.retain(|head_identity| !layout.remove(head_identity));
+        query_layout.retain(|head_identity| {
+            let exact = layout
+                .get(head_identity)
+                .is_some_and(|layout_head| fields.same_beyond_equality(layout_head, head_identity));
+            !(exact && layout.remove(head_identity))
+        });
 
         // If there are no outstanding heads, this is a match!
-        if query_layout.is_empty() {
+        if query_layout.is_empty() && layout.is_empty() {
             return Some((Self::Exact, Default::default()));
         }
 
-        // Bail out if any head has no make/model. In-exact matches don't make
-        // sense if we don't have this information.
-        for layout in layout.iter() {
-            if layout.make.is_none() || layout.model.is_none() {
-                return None;
-            }
-        }
+        let (layout_head_to_query_head, unmatched, remapped) = if template {
+            fields.map_pattern_heads(&mut layout, query_layout)
+        } else {
+            fields.map_heads(&mut layout, query_layout)
+        };
+        // Heads that only differ in fields that aren't compared are still an exact match.
+        let score = match (layout.is_empty(), unmatched.is_empty()) {
+            (true, true) if remapped => Self::SameHeads,
+            (true, true) => Self::Exact,
+            (true, false) => Self::Superset,
+            (false, true) => Self::Subset,
+            // Both sides have heads the other doesn't, so this layout doesn't match.
+            (false, false) => return None,
+        };
+        Some((score, layout_head_to_query_head))
+    }
+}
 
-        let mut layout_head_to_query_head = HashMap::new();
-        for query_head in query_layout {
-            let Some(matched_layout_head) = layout
-                .iter()
-                .find(|&layout_head| {
-                    query_head.make == layout_head.make
-                        && query_head.model == layout_head.model
-                        && query_head.serial_number == layout_head.serial_number
-                })
-                .cloned()
-            else {
-                // The query head had no match, so this layout doesn't match.
-                return None;
-            };
+/// The version of the format of the layouts file. Files from older versions are migrated when
+/// they're loaded (see [`migrate`]), and files from newer versions are refused.
+pub const LAYOUTS_VERSION: u64 = 1;
 
-            layout.remove(&matched_layout_head);
-            assert!(layout_head_to_query_head
-                .insert(matched_layout_head, query_head)
-                .is_none());
-        }
+/// The layouts file of wl-distore.
+#[derive(Default, Serialize, Deserialize, JsonSchema)]
+#[schemars(title = "wl-distore layouts")]
+struct SavedLayoutData {
+    /// The version of the format this was saved in. Files from before versioning are version 0.
+    #[serde(default)]
+    version: u64,
+    /// The SHA-256 of `layouts` when wl-distore last saved them, to tell hand edits apart from
+    /// corruption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    layouts: Vec<SavedLayoutFormat>,
+}
 
-        Some((Self::SameHeads, layout_head_to_query_head))
+impl SavedLayoutData {
+    /// Computes the checksum of `layouts` as a hex string.
+    fn compute_checksum(&self) -> String {
+        let layouts =
+            serde_json::to_vec(&self.layouts).expect("Serializing layouts should not fail");
+        Sha256::digest(layouts)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
-struct SavedLayoutData {
-    layouts: Vec<Vec<(HeadIdentity, Option<SavedConfiguration>)>>,
+/// The formats a layout can be saved in.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SavedLayoutFormat {
+    Current(Box<SavedLayout>),
+    /// Layouts used to be saved as just the list of heads.
+    Legacy(Vec<(HeadIdentity, Option<SavedConfiguration>)>),
+}
+
+/// A saved layout. See [`Layout`] for what the fields mean.
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct SavedLayout {
+    /// A human-readable name for the layout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// The profile the layout belongs to, which tells apart layouts for the same heads. Defaults to
+    /// "default".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    /// Whether the layout is applied automatically when its heads are connected.
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    auto_apply: bool,
+    /// Whether changes to the configuration are kept from being saved over the layout.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pinned: bool,
+    /// Whether the layout is a template, whose head identities are glob patterns that match any
+    /// heads they fit.
+    #[serde(default, skip_serializing_if = "is_false")]
+    template: bool,
+    /// Breaks ties between layouts that match the same heads equally well. Higher is preferred.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    priority: i32,
+    /// The host the layout was saved on, if layouts are scoped to hosts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    /// The compositor the layout was saved under.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compositor: Option<String>,
+    /// The state of the lid ("open" or "closed") the layout is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lid: Option<LidState>,
+    /// The power source ("ac" or "battery") the layout is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    power_source: Option<PowerSource>,
+    /// Each head and its configuration, or null if it is disabled.
+    heads: Vec<(HeadIdentity, Option<SavedConfiguration>)>,
+    /// Shell commands to run after the layout is applied, keyed by the name of the head.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    head_commands: BTreeMap<String, Vec<String>>,
+    /// A note about the layout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    /// Notes about heads, keyed by the name of the head.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    head_comments: BTreeMap<String, String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+impl From<&SavedLayoutFormat> for Layout {
+    fn from(value: &SavedLayoutFormat) -> Self {
+        match value {
+            SavedLayoutFormat::Current(layout) => Layout {
+                name: layout.name.clone(),
+                profile: layout.profile.clone(),
+                auto_apply: layout.auto_apply,
+                read_only: false,
+                pinned: layout.pinned,
+                template: layout.template,
+                priority: layout.priority,
+                host: layout.host.clone(),
+                compositor: layout.compositor.clone(),
+                lid: layout.lid,
+                power_source: layout.power_source,
+                heads: layout.heads.iter().cloned().collect(),
+                head_commands: layout.head_commands.clone(),
+                comment: layout.comment.clone(),
+                head_comments: layout.head_comments.clone(),
+                metadata: Default::default(),
+            },
+            SavedLayoutFormat::Legacy(heads) => Layout::new(heads.iter().cloned().collect()),
+        }
+    }
+}
+
+/// Deserializes read-only layouts in the format of the entries of the layouts file, for the
+/// `seed_layouts` config option.
+pub fn deserialize_seed_layouts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<Layout>>, D::Error> {
+    let layouts = Option::<Vec<SavedLayoutFormat>>::deserialize(deserializer)?;
+    Ok(layouts.map(|layouts| {
+        layouts
+            .iter()
+            .map(|layout| Layout {
+                read_only: true,
+                ..layout.into()
+            })
+            .collect()
+    }))
 }
 
 impl From<&SavedLayoutData> for LayoutData {
     fn from(value: &SavedLayoutData) -> Self {
         Self {
-            layouts: value
-                .layouts
-                .iter()
-                .map(|entries| entries.iter().cloned().collect())
-                .collect(),
+            layouts: value.layouts.iter().map(Layout::from).collect(),
+            generation: 0,
+            active_profile: None,
+            profile_choices: Default::default(),
+            match_fields: Default::default(),
+            matcher: None,
+            host: None,
+            compositor: None,
+            scope_to_compositor: false,
+            match_supersets: false,
+            match_subsets: false,
+            lid: None,
+            power_source: None,
+            layout_files: Default::default(),
+            skipped_layout_files: Default::default(),
+        }
+    }
+}
+
+impl From<&Layout> for SavedLayout {
+    fn from(value: &Layout) -> Self {
+        // Sort the heads so saving the same layout always gives the same file.
+        let heads = head_set_key(value.heads.keys())
+            .into_iter()
+            .map(|identity| {
+                let configuration = value.heads[&identity].clone();
+                (identity, configuration)
+            })
+            .collect();
+        Self {
+            name: value.name.clone(),
+            profile: value.profile.clone(),
+            auto_apply: value.auto_apply,
+            pinned: value.pinned,
+            template: value.template,
+            priority: value.priority,
+            host: value.host.clone(),
+            compositor: value.compositor.clone(),
+            lid: value.lid,
+            power_source: value.power_source,
+            heads,
+            head_commands: value.head_commands.clone(),
+            comment: value.comment.clone(),
+            head_comments: value.head_comments.clone(),
         }
     }
 }
 
 impl From<&LayoutData> for SavedLayoutData {
     fn from(value: &LayoutData) -> Self {
-        Self {
+        let mut saved_layout_data = Self {
+            version: LAYOUTS_VERSION,
+            checksum: None,
             layouts: value
                 .layouts
                 .iter()
-                .map(|entries| {
-                    entries
-                        .iter()
-                        .map(|(k, v)| (k.clone(), v.clone()))
-                        .collect()
+                .filter(|layout| !layout.read_only)
+                .map(|layout| SavedLayoutFormat::Current(Box::new(layout.into())))
+                .collect(),
+        };
+        saved_layout_data.checksum = Some(saved_layout_data.compute_checksum());
+        saved_layout_data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes up the identity of a head on `connector` for the monitor `model` with `serial`.
+    fn identity(connector: &str, model: &str, serial: Option<&str>) -> HeadIdentity {
+        HeadIdentity {
+            name: connector.to_string(),
+            description: format!("Acme {model} ({connector})"),
+            make: Some("Acme".into()),
+            model: Some(model.into()),
+            serial_number: serial.map(str::to_string),
+            edid: None,
+            physical_size: None,
+        }
+    }
+
+    /// A configuration at `position` with a 1920x1080 mode.
+    fn configuration(position: (u32, u32)) -> SavedConfiguration {
+        SavedConfiguration {
+            mode: Some(Mode {
+                size: (1920, 1080),
+                refresh: Some(60000),
+            }),
+            position,
+            transform: Transform::Normal,
+            scale: 1.0,
+            adaptive_sync: None,
+            dont_care: Default::default(),
+        }
+    }
+
+    /// A layout with each of `heads` enabled, side by side.
+    fn layout(heads: &[&HeadIdentity]) -> Layout {
+        Layout::new(
+            heads
+                .iter()
+                .enumerate()
+                .map(|(index, identity)| {
+                    let position = (index as u32 * 1920, 0);
+                    ((*identity).clone(), Some(configuration(position)))
                 })
                 .collect(),
+        )
+    }
+
+    /// Layout data holding `layouts`, with the default settings.
+    fn layout_data(layouts: Vec<Layout>) -> LayoutData {
+        LayoutData {
+            layouts,
+            ..(&SavedLayoutData::default()).into()
+        }
+    }
+
+    #[test]
+    fn checksum_survives_saving_and_loading() {
+        let a = identity("DP-1", "A", Some("1"));
+        let b = identity("DP-2", "B", Some("2"));
+        let mut desk = layout(&[&a, &b]);
+        for name in ["DP-1", "DP-2", "HDMI-A-1", "eDP-1"] {
+            desk.head_commands
+                .insert(name.into(), vec![format!("echo {{connector}} {name}")]);
+            desk.head_comments
+                .insert(name.into(), format!("{name} note"));
         }
+        let contents = layout_data(vec![desk]).to_json().unwrap();
+
+        let saved = serde_json::from_slice::<SavedLayoutData>(&contents).unwrap();
+        assert_eq!(saved.checksum, Some(saved.compute_checksum()));
+        // Saving what was loaded gives the same file.
+        let reloaded = LayoutData::from(&saved);
+        assert_eq!(reloaded.to_json().unwrap(), contents);
+    }
+
+    #[test]
+    fn is_damaged_detects_truncated_copies_of_intact_backups() {
+        let a = identity("DP-1", "A", Some("1"));
+        let mut desk = layout(&[&a]);
+        desk.head_commands
+            .insert("DP-1".into(), vec!["echo one".into(), "echo two".into()]);
+        desk.head_commands
+            .insert("DP-2".into(), vec!["echo three".into()]);
+        let backup = layout_data(vec![desk]).to_json().unwrap();
+
+        let mut truncated = backup[..backup.len() / 2].to_vec();
+        assert!(is_damaged(&truncated, &backup));
+        truncated.extend([0; 16]);
+        assert!(is_damaged(&truncated, &backup));
+        assert!(!is_damaged(&backup, &backup));
+
+        // A backup that doesn't match its checksum was edited, so it can't be trusted.
+        let edited = String::from_utf8(backup)
+            .unwrap()
+            .replace("echo three", "echo four")
+            .into_bytes();
+        assert!(!is_damaged(&edited[..edited.len() / 2], &edited));
+    }
+
+    #[test]
+    fn map_layout_heads_matches_template_heads_as_patterns() {
+        let mut template = layout(&[&HeadIdentity {
+            name: "eDP-*".into(),
+            description: "*".into(),
+            make: None,
+            model: None,
+            serial_number: None,
+            edid: None,
+            physical_size: None,
+        }]);
+        template.template = true;
+        let laptop = identity("eDP-1", "Panel", None);
+        let external = identity("DP-1", "A", Some("1"));
+
+        let mapping = map_layout_heads(
+            &template,
+            &HashSet::from([laptop.clone(), external]),
+            &MatchFields::default(),
+        );
+        let pattern = template.heads.keys().next().unwrap();
+        assert_eq!(mapping, HashMap::from([(pattern.clone(), laptop)]));
+
+        // The same heads aren't fit as patterns by layouts that aren't templates.
+        template.template = false;
+        let mapping = map_layout_heads(
+            &template,
+            &HashSet::from([identity("eDP-1", "Panel", None)]),
+            &MatchFields::default(),
+        );
+        assert!(mapping.is_empty());
+    }
+
+    #[test]
+    fn dedupe_merges_same_layouts_into_the_most_recently_used() {
+        let a = identity("DP-1", "A", Some("1"));
+        let b = identity("DP-2", "B", Some("2"));
+        let mut named = layout(&[&a, &b]);
+        named.name = Some("desk".into());
+        named.metadata.last_applied = Some(10);
+        named.metadata.apply_count = 1;
+        let other = layout(&[&a]);
+        let mut recent = layout(&[&a, &b]);
+        recent.comment = Some("recent".into());
+        recent.metadata.last_applied = Some(20);
+        recent.metadata.apply_count = 2;
+        let mut data = layout_data(vec![named, other, recent]);
+
+        assert_eq!(data.dedupe(), vec![(0, vec![0, 2])]);
+        assert_eq!(data.layouts.len(), 2);
+        let merged = &data.layouts[0];
+        assert_eq!(merged.comment.as_deref(), Some("recent"));
+        assert_eq!(merged.name.as_deref(), Some("desk"));
+        assert_eq!(merged.metadata.last_applied, Some(20));
+        assert_eq!(merged.metadata.apply_count, 3);
+        assert_eq!(data.layouts[1].heads.len(), 1);
+    }
+
+    #[test]
+    fn dedupe_keeps_layouts_with_conflicting_names_or_comments_apart() {
+        let a = identity("DP-1", "A", Some("1"));
+        let mut work = layout(&[&a]);
+        work.name = Some("work".into());
+        let mut home = layout(&[&a]);
+        home.name = Some("home".into());
+        let mut commented = layout(&[&a]);
+        commented.head_comments.insert("DP-1".into(), "left".into());
+        let mut other_comment = layout(&[&a]);
+        other_comment
+            .head_comments
+            .insert("DP-1".into(), "right".into());
+        let mut data = layout_data(vec![work, home, commented, other_comment]);
+
+        // The unnamed layouts can each join a named one, but not one another.
+        assert_eq!(data.dedupe(), vec![(0, vec![0, 2]), (1, vec![1, 3])]);
+        let names = data
+            .layouts
+            .iter()
+            .map(|layout| layout.name.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [Some("work"), Some("home")]);
+        assert_eq!(data.layouts[0].head_comments["DP-1"], "left");
+        assert_eq!(data.layouts[1].head_comments["DP-1"], "right");
+    }
+
+    #[test]
+    fn score_ranks_partial_matches_below_full_matches() {
+        let a = identity("DP-1", "A", Some("1"));
+        let b = identity("DP-2", "B", Some("2"));
+        let c = identity("DP-3", "C", Some("3"));
+        let moved_a = identity("DP-3", "A", Some("1"));
+        let fields = MatchFields::default();
+
+        let cases = [
+            (
+                layout(&[&a, &b]),
+                vec![&a, &b],
+                Some(LayoutMatchScore::Exact),
+            ),
+            (
+                layout(&[&a, &b]),
+                vec![&moved_a, &b],
+                Some(LayoutMatchScore::SameHeads),
+            ),
+            (
+                layout(&[&a]),
+                vec![&a, &b],
+                Some(LayoutMatchScore::Superset),
+            ),
+            (layout(&[&a, &b]), vec![&a], Some(LayoutMatchScore::Subset)),
+            (layout(&[&a, &b]), vec![&a, &c], None),
+        ];
+        for (layout, query, expected) in cases {
+            let query = query.into_iter().cloned().collect::<HashSet<_>>();
+            let score = LayoutMatchScore::score(&layout, &query, &fields, true);
+            assert_eq!(score.map(|(score, _)| score), expected, "{query:?}");
+        }
+
+        assert!(LayoutMatchScore::Subset < LayoutMatchScore::Superset);
+        assert!(LayoutMatchScore::Superset < LayoutMatchScore::SameHeads);
+        assert!(LayoutMatchScore::SameHeads < LayoutMatchScore::Exact);
+    }
+
+    #[test]
+    fn subset_matches_need_every_head_in_the_layout_and_never_beat_full_matches() {
+        let a = identity("DP-1", "A", Some("1"));
+        let b = identity("DP-2", "B", Some("2"));
+        let c = identity("DP-3", "C", Some("3"));
+        let mut data = layout_data(vec![layout(&[&a, &b])]);
+        data.match_subsets = true;
+
+        // A connected head the layout doesn't have rules the subset out.
+        assert!(data
+            .find_partial_layout_match(&HashSet::from([a.clone(), c.clone()]))
+            .is_none());
+        let query = HashSet::from([a.clone()]);
+        let (index, score, _) = data.find_partial_layout_match(&query).unwrap();
+        assert_eq!((index, score), (0, LayoutMatchScore::Subset));
+        // Full matches never consider subsets.
+        assert!(data.find_layout_to_apply(&query).is_none());
+
+        // Once a layout has exactly the connected heads, it is the match instead.
+        data.layouts.push(layout(&[&a]));
+        let (index, score, _) = data.find_layout_to_apply(&query).unwrap();
+        assert_eq!((index, score), (1, LayoutMatchScore::Exact));
+    }
+
+    #[test]
+    fn identical_heads_are_paired_in_connector_order() {
+        let left = identity("DP-1", "A", None);
+        let right = identity("DP-2", "A", None);
+        let fields = MatchFields::default();
+
+        let cases = [
+            (["DP-4", "DP-3"], [("DP-1", "DP-3"), ("DP-2", "DP-4")]),
+            // A head that stays on its connector keeps its configuration.
+            (["DP-3", "DP-2"], [("DP-1", "DP-3"), ("DP-2", "DP-2")]),
+        ];
+        for (connectors, expected) in cases {
+            let expected = expected
+                .map(|(from, to)| (identity(from, "A", None), identity(to, "A", None)))
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            // Hash sets and maps iterate in a different order each time, which mustn't change the
+            // pairing.
+            for _ in 0..16 {
+                let desk = layout(&[&left, &right]);
+                let query = connectors
+                    .iter()
+                    .map(|connector| identity(connector, "A", None))
+                    .collect::<HashSet<_>>();
+                assert_eq!(map_layout_heads(&desk, &query, &fields), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn find_best_match_breaks_ties_in_order() {
+        let a = identity("DP-1", "A", Some("1"));
+        let moved_a = identity("DP-3", "A", Some("1"));
+        // Wins every tie-break, so the other layout of each case only wins by the one it tests.
+        let favoured = |heads: &[&HeadIdentity]| Layout {
+            profile: Some("work".into()),
+            lid: Some(LidState::Closed),
+            power_source: Some(PowerSource::Ac),
+            priority: 10,
+            metadata: LayoutMetadata {
+                last_applied: Some(100),
+                ..Default::default()
+            },
+            ..layout(heads)
+        };
+        let in_profile = |heads: &[&HeadIdentity]| Layout {
+            profile: Some("work".into()),
+            ..layout(heads)
+        };
+
+        let cases = [
+            (
+                "template",
+                Layout {
+                    template: true,
+                    ..favoured(&[&a])
+                },
+                layout(&[&moved_a]),
+            ),
+            (
+                "active profile",
+                Layout {
+                    profile: None,
+                    ..favoured(&[&a])
+                },
+                in_profile(&[&moved_a]),
+            ),
+            ("score", favoured(&[&moved_a]), in_profile(&[&a])),
+            (
+                "lid",
+                Layout {
+                    lid: None,
+                    ..favoured(&[&a])
+                },
+                Layout {
+                    lid: Some(LidState::Closed),
+                    ..in_profile(&[&a])
+                },
+            ),
+            (
+                "power source",
+                Layout {
+                    power_source: None,
+                    ..favoured(&[&a])
+                },
+                Layout {
+                    lid: Some(LidState::Closed),
+                    power_source: Some(PowerSource::Ac),
+                    ..in_profile(&[&a])
+                },
+            ),
+            (
+                "priority",
+                Layout {
+                    priority: 0,
+                    ..favoured(&[&a])
+                },
+                Layout {
+                    metadata: Default::default(),
+                    ..favoured(&[&a])
+                },
+            ),
+            (
+                "last applied",
+                Layout {
+                    metadata: Default::default(),
+                    ..favoured(&[&a])
+                },
+                favoured(&[&a]),
+            ),
+        ];
+        let query = HashSet::from([a.clone()]);
+        for (tie_break, loser, winner) in cases {
+            let mut data = layout_data(vec![loser, winner]);
+            data.active_profile = Some("work".into());
+            data.lid = Some(LidState::Closed);
+            data.power_source = Some(PowerSource::Ac);
+            let (index, _, _) = data.find_layout_match(&query).unwrap();
+            assert_eq!(index, 1, "{tie_break}");
+        }
+
+        // When nothing else tells them apart, the earlier layout wins.
+        let data = layout_data(vec![favoured(&[&a]), favoured(&[&a])]);
+        let (index, _, _) = data.find_layout_match(&query).unwrap();
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn evict_removes_the_least_recently_used_layouts() {
+        let a = identity("DP-1", "A", Some("1"));
+        let named = |name: &str, last_used: Option<u64>| Layout {
+            name: Some(name.into()),
+            metadata: LayoutMetadata {
+                last_saved: last_used,
+                ..Default::default()
+            },
+            ..layout(&[&a])
+        };
+        let mut data = layout_data(vec![
+            Layout {
+                pinned: true,
+                ..named("pinned", None)
+            },
+            Layout {
+                read_only: true,
+                ..named("read-only", None)
+            },
+            Layout {
+                template: true,
+                ..named("template", None)
+            },
+            named("kept", None),
+            named("recent", Some(50)),
+            named("never used", None),
+            named("oldest", Some(10)),
+            named("old", Some(30)),
+        ]);
+
+        // Seven layouts are stored (read-only ones don't count), so three must go.
+        assert_eq!(data.evict(4, Some(3)), vec![5, 6, 7]);
+        let names = data
+            .layouts
+            .iter()
+            .map(|layout| layout.name.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["pinned", "read-only", "template", "kept", "recent"]);
+        assert!(data.evict(4, None).is_empty());
+    }
+
+    #[test]
+    fn migrate_upgrades_legacy_layout_lists() {
+        // Layouts files from before version 1 saved each layout as just a list of heads.
+        let legacy = r#"{
+            "layouts": [
+                [
+                    [
+                        {
+                            "name": "DP-1",
+                            "description": "Acme A (DP-1)",
+                            "make": "Acme",
+                            "model": "A",
+                            "serial_number": "1"
+                        },
+                        {
+                            "mode": { "size": [1920, 1080], "refresh": 60000 },
+                            "position": [0, 0],
+                            "transform": "Normal",
+                            "scale": 1.0,
+                            "adaptive_sync": null
+                        }
+                    ],
+                    [
+                        {
+                            "name": "DP-2",
+                            "description": "Acme B (DP-2)",
+                            "make": "Acme",
+                            "model": "B",
+                            "serial_number": "2"
+                        },
+                        null
+                    ]
+                ]
+            ]
+        }"#;
+        let value = serde_json::from_str::<serde_json::Value>(legacy).unwrap();
+        assert_eq!(format_version(&value), 0);
+        let saved = serde_json::from_value::<SavedLayoutData>(migrate(value, 0)).unwrap();
+        let data = LayoutData::from(&saved);
+
+        let a = identity("DP-1", "A", Some("1"));
+        let b = identity("DP-2", "B", Some("2"));
+        assert_eq!(data.layouts.len(), 1);
+        assert_eq!(
+            data.layouts[0].heads,
+            HashMap::from([(a, Some(configuration((0, 0)))), (b, None)])
+        );
+
+        // Saving writes the current version, which loads as it is.
+        let json = data.to_json().unwrap();
+        let value = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+        assert_eq!(format_version(&value), LAYOUTS_VERSION);
+        assert!(value["layouts"][0]["heads"].is_array());
     }
 }
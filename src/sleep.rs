@@ -0,0 +1,55 @@
+use calloop::channel::Sender;
+use tracing::{error, warn};
+use zbus::blocking::{Connection, Proxy};
+
+/// A change to whether the machine is suspended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SleepEvent {
+    /// The machine is about to suspend.
+    Suspending,
+    /// The machine resumed from suspend.
+    Resumed,
+}
+
+/// Listens for the machine suspending and resuming through systemd-logind over D-Bus.
+pub struct SleepWatcher {
+    proxy: Proxy<'static>,
+}
+
+impl SleepWatcher {
+    /// Connects to systemd-logind on the system bus.
+    pub fn connect() -> zbus::Result<Self> {
+        let connection = Connection::system()?;
+        let proxy = Proxy::new(
+            &connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )?;
+        Ok(Self { proxy })
+    }
+
+    /// Sends a [`SleepEvent`] to `sender` every time the machine suspends or resumes, from another
+    /// thread. Stops once the receiving end is dropped.
+    pub fn watch(self, sender: Sender<SleepEvent>) -> zbus::Result<()> {
+        // Subscribe before returning, so suspends right after starting aren't missed.
+        let signals = self.proxy.receive_signal("PrepareForSleep")?;
+        std::thread::spawn(move || {
+            for message in signals {
+                let event = match message.body().deserialize::<bool>() {
+                    Ok(true) => SleepEvent::Suspending,
+                    Ok(false) => SleepEvent::Resumed,
+                    Err(err) => {
+                        warn!("Failed to read the PrepareForSleep signal: {err}");
+                        continue;
+                    }
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+            error!("Stopped receiving suspends from systemd-logind");
+        });
+        Ok(())
+    }
+}
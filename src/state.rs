@@ -0,0 +1,171 @@
+use std::{
+    collections::HashSet,
+    io::{BufReader, ErrorKind},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    complete::HeadIdentity,
+    lid::LidState,
+    power::PowerSource,
+    serde::{head_set_key, LayoutData},
+};
+
+/// Runtime metadata about a layout. This is kept out of the layouts file so that it stays
+/// hand-editable and doesn't change every time a layout is applied.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LayoutMetadata {
+    /// When the layout was last saved, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_saved: Option<u64>,
+    /// When the layout was last applied, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_applied: Option<u64>,
+    /// How many times the layout has been applied.
+    #[serde(default)]
+    pub apply_count: u64,
+    /// When the layout last matched the connected heads, in seconds since the Unix epoch. This is
+    /// only recorded to the nearest [`MATCH_RESOLUTION_SECS`], to avoid rewriting the state file
+    /// on every change to the heads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_matched: Option<u64>,
+}
+
+/// How stale [`LayoutMetadata::last_matched`] may get before it is updated.
+pub const MATCH_RESOLUTION_SECS: u64 = 60 * 60;
+
+impl LayoutMetadata {
+    /// When the layout was last saved, applied or matched, whichever is latest.
+    pub fn last_used(&self) -> Option<u64> {
+        self.last_saved
+            .max(self.last_applied)
+            .max(self.last_matched)
+    }
+}
+
+/// The contents of the state file.
+#[derive(Default, Serialize, Deserialize)]
+struct SavedState {
+    /// Incremented every time the layouts file is saved.
+    #[serde(default)]
+    generation: u64,
+    #[serde(default)]
+    layouts: Vec<SavedLayoutMetadata>,
+    /// The profile chosen for each set of heads (see [`LayoutData::profile_choices`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    profile_choices: Vec<SavedProfileChoice>,
+}
+
+/// The metadata for one layout. Layouts are identified by their name, host, compositor, lid, power
+/// source and heads, since indices change when the layouts file is edited by hand.
+#[derive(Serialize, Deserialize)]
+struct SavedLayoutMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compositor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lid: Option<LidState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    power_source: Option<PowerSource>,
+    heads: Vec<HeadIdentity>,
+    #[serde(flatten)]
+    metadata: LayoutMetadata,
+}
+
+/// The profile chosen for a set of heads.
+#[derive(Serialize, Deserialize)]
+struct SavedProfileChoice {
+    heads: Vec<HeadIdentity>,
+    profile: String,
+}
+
+/// Returns the path of the state file that accompanies the layouts file at `layouts_path`, e.g.
+/// `layouts.state.json` for `layouts.json`.
+pub fn state_path(layouts_path: &Path) -> PathBuf {
+    layouts_path.with_extension("state.json")
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Loads the state file at `path` into the layouts and profile choices of `layout_data`. A missing
+/// or corrupt file leaves the metadata empty, since it is rebuilt as layouts are used.
+pub fn load(path: &Path, layout_data: &mut LayoutData) -> Result<(), std::io::Error> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let saved_state: SavedState = match serde_json::from_reader(BufReader::new(file)) {
+        Ok(saved_state) => saved_state,
+        Err(err) => {
+            warn!(
+                "Ignoring the state file {} since it is invalid: {err}",
+                path.display()
+            );
+            return Ok(());
+        }
+    };
+    layout_data.generation = saved_state.generation;
+    for layout in layout_data.layouts.iter_mut() {
+        let heads = layout.heads.keys().collect::<HashSet<_>>();
+        if let Some(saved) = saved_state.layouts.iter().find(|saved| {
+            saved.name == layout.name
+                && saved.host == layout.host
+                && saved.compositor == layout.compositor
+                && saved.lid == layout.lid
+                && saved.power_source == layout.power_source
+                && saved.heads.iter().collect::<HashSet<_>>() == heads
+        }) {
+            layout.metadata = saved.metadata.clone();
+        }
+    }
+    layout_data.profile_choices = saved_state
+        .profile_choices
+        .into_iter()
+        .map(|choice| (head_set_key(choice.heads.iter()), choice.profile))
+        .collect();
+    Ok(())
+}
+
+/// Serializes the metadata of every layout in `layout_data`, along with its profile choices, in the
+/// format of the state file.
+pub fn to_json(layout_data: &LayoutData) -> Result<Vec<u8>, serde_json::Error> {
+    let saved_state = SavedState {
+        generation: layout_data.generation,
+        layouts: layout_data
+            .layouts
+            .iter()
+            .map(|layout| SavedLayoutMetadata {
+                name: layout.name.clone(),
+                host: layout.host.clone(),
+                compositor: layout.compositor.clone(),
+                lid: layout.lid,
+                power_source: layout.power_source,
+                heads: layout.heads.keys().cloned().collect(),
+                metadata: layout.metadata.clone(),
+            })
+            .collect(),
+        profile_choices: layout_data
+            .profile_choices
+            .iter()
+            .map(|(heads, profile)| SavedProfileChoice {
+                heads: heads.clone(),
+                profile: profile.clone(),
+            })
+            .collect(),
+    };
+    serde_json::to_vec(&saved_state)
+}
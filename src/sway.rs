@@ -0,0 +1,102 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+};
+
+use calloop::channel::Sender;
+use serde::Deserialize;
+use tracing::{error, warn};
+
+/// The magic string that starts every message of sway's IPC protocol.
+const MAGIC: &[u8] = b"i3-ipc";
+
+/// The type of the request that subscribes to events.
+const SUBSCRIBE: u32 = 2;
+
+/// The type of workspace events, which sway also sends (with the change "reload") after reloading
+/// its config.
+const WORKSPACE_EVENT: u32 = 0x8000_0000;
+
+/// The reply to a subscribe request.
+#[derive(Deserialize)]
+struct SubscribeReply {
+    success: bool,
+}
+
+/// A workspace event. Only the change is needed.
+#[derive(Deserialize)]
+struct WorkspaceEvent {
+    change: String,
+}
+
+/// Listens for sway reloading its config over sway's IPC socket.
+pub struct SwayIpc {
+    stream: UnixStream,
+}
+
+impl SwayIpc {
+    /// Connects to sway's IPC socket, if `SWAYSOCK` is set (i.e., this is running under sway).
+    pub fn connect() -> Option<std::io::Result<Self>> {
+        let path = std::env::var_os("SWAYSOCK")?;
+        Some(UnixStream::connect(path).map(|stream| Self { stream }))
+    }
+
+    /// Sends a message to `sender` every time sway reloads its config, from another thread. Stops
+    /// once the receiving end is dropped.
+    pub fn watch_reloads(mut self, sender: Sender<()>) -> std::io::Result<()> {
+        self.send(SUBSCRIBE, br#"["workspace"]"#)?;
+        let (_, payload) = self.receive()?;
+        let reply: SubscribeReply = serde_json::from_slice(&payload)?;
+        if !reply.success {
+            return Err(std::io::Error::other("sway refused the subscription"));
+        }
+        std::thread::spawn(move || loop {
+            let (message_type, payload) = match self.receive() {
+                Ok(message) => message,
+                Err(err) => {
+                    error!("Stopped receiving events from sway: {err}");
+                    return;
+                }
+            };
+            if message_type != WORKSPACE_EVENT {
+                continue;
+            }
+            match serde_json::from_slice::<WorkspaceEvent>(&payload) {
+                Ok(event) if event.change == "reload" => {
+                    if sender.send(()).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => warn!("Failed to read a workspace event from sway: {err}"),
+            }
+        });
+        Ok(())
+    }
+
+    /// Sends a message of `message_type` with `payload`.
+    fn send(&mut self, message_type: u32, payload: &[u8]) -> std::io::Result<()> {
+        let mut message = MAGIC.to_vec();
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(payload);
+        self.stream.write_all(&message)
+    }
+
+    /// Receives the next message, returning its type and payload.
+    fn receive(&mut self) -> std::io::Result<(u32, Vec<u8>)> {
+        let mut header = [0; MAGIC.len() + 8];
+        self.stream.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "The message from sway doesn't start with the magic string",
+            ));
+        }
+        let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+        let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        let mut payload = vec![0; length as usize];
+        self.stream.read_exact(&mut payload)?;
+        Ok((message_type, payload))
+    }
+}
@@ -0,0 +1,352 @@
+use std::process::Command;
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table, Wrap},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    cli,
+    config::Args,
+    lid::LidState,
+    power::PowerSource,
+    serde::{LayoutData, Recovery},
+};
+
+/// The keys, shown at the bottom.
+const HELP: &str =
+    "up/down/jk: select  enter/a: apply  r: rename  d: delete  p: pin/unpin  q: quit";
+
+/// What the keys currently do.
+enum InputMode {
+    Browse,
+    /// Editing the new name of the selected layout.
+    Rename(String),
+    /// Asking whether to delete the selected layout.
+    ConfirmDelete,
+}
+
+struct Ui<'a> {
+    args: &'a Args,
+    layout_data: LayoutData,
+    list_state: ListState,
+    mode: InputMode,
+    /// The result of the last action.
+    status: String,
+}
+
+/// Browses the saved layouts in the terminal, letting the user rename, delete, pin and apply them.
+/// Like the other subcommands that edit the layouts file, changes are written right away.
+pub fn run(args: &Args) {
+    let mut ui = Ui {
+        args,
+        layout_data: cli::load_layouts(args),
+        list_state: ListState::default(),
+        mode: InputMode::Browse,
+        status: String::new(),
+    };
+    if !ui.layout_data.layouts.is_empty() {
+        ui.list_state.select(Some(0));
+    }
+    let result = ratatui::try_init().and_then(|mut terminal| {
+        let result = ui.run_loop(&mut terminal);
+        ratatui::restore();
+        result
+    });
+    if let Err(err) = result {
+        eprintln!("Failed to run the terminal UI: {err}");
+        std::process::exit(1);
+    }
+}
+
+impl Ui<'_> {
+    /// Draws the layouts and handles keys until the user quits.
+    fn run_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<(), std::io::Error> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+            if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+                return Ok(());
+            }
+            match &mut self.mode {
+                InputMode::Rename(name) => match code {
+                    KeyCode::Enter => {
+                        let name = std::mem::take(name);
+                        self.mode = InputMode::Browse;
+                        self.rename(name);
+                    }
+                    KeyCode::Esc => self.mode = InputMode::Browse,
+                    KeyCode::Backspace => {
+                        name.pop();
+                    }
+                    KeyCode::Char(c) => name.push(c),
+                    _ => {}
+                },
+                InputMode::ConfirmDelete => {
+                    self.mode = InputMode::Browse;
+                    if code == KeyCode::Char('y') {
+                        self.delete();
+                    } else {
+                        self.status = "Not deleted".into();
+                    }
+                }
+                InputMode::Browse => match code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Down | KeyCode::Char('j') => self.list_state.select_next(),
+                    KeyCode::Up | KeyCode::Char('k') => self.list_state.select_previous(),
+                    KeyCode::Enter | KeyCode::Char('a') => self.apply(),
+                    KeyCode::Char('p') => self.toggle_pinned(),
+                    KeyCode::Char('r') => {
+                        if let Some(index) = self.editable_selection("renamed") {
+                            self.mode = InputMode::Rename(
+                                self.layout_data.layouts[index]
+                                    .name
+                                    .clone()
+                                    .unwrap_or_default(),
+                            );
+                        }
+                    }
+                    KeyCode::Char('d') if self.editable_selection("deleted").is_some() => {
+                        self.mode = InputMode::ConfirmDelete;
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// The index of the selected layout, if there is one.
+    fn selection(&self) -> Option<usize> {
+        self.list_state
+            .selected()
+            .filter(|index| *index < self.layout_data.layouts.len())
+    }
+
+    /// The index of the selected layout if it can be edited. Otherwise, says why it can't be
+    /// `action` (e.g. "renamed").
+    fn editable_selection(&mut self, action: &str) -> Option<usize> {
+        let index = self.selection()?;
+        if self.layout_data.layouts[index].read_only {
+            self.status = format!("Layout {index} is read-only, so it can't be {action}");
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Saves the layouts, reporting `message` if that worked.
+    fn save(&mut self, message: String) {
+        self.status = match cli::write_layouts(self.args, &mut self.layout_data) {
+            Ok(()) => message,
+            Err(err) => format!("Failed to save layouts: {err}"),
+        };
+    }
+
+    fn rename(&mut self, name: String) {
+        let Some(index) = self.editable_selection("renamed") else {
+            return;
+        };
+        if let Err(err) = cli::check_name(&self.layout_data, index, &name) {
+            self.status = err;
+            return;
+        }
+        self.layout_data.layouts[index].name = Some(name.clone());
+        self.save(format!("Renamed layout {index} to \"{name}\""));
+    }
+
+    fn delete(&mut self) {
+        let Some(index) = self.editable_selection("deleted") else {
+            return;
+        };
+        self.layout_data.layouts.remove(index);
+        if self.layout_data.layouts.is_empty() {
+            self.list_state.select(None);
+        } else if index == self.layout_data.layouts.len() {
+            self.list_state.select(Some(index - 1));
+        }
+        self.save(format!("Deleted layout {index}"));
+    }
+
+    fn toggle_pinned(&mut self) {
+        let Some(index) = self.editable_selection("pinned") else {
+            return;
+        };
+        let layout = &mut self.layout_data.layouts[index];
+        layout.pinned = !layout.pinned;
+        let message = if layout.pinned {
+            format!("Pinned layout {index}")
+        } else {
+            format!("Unpinned layout {index}")
+        };
+        self.save(message);
+    }
+
+    /// Applies the selected layout with `wl-distore apply`, then reloads the layouts since applying
+    /// updates their metadata. The layout is passed by name if it has one, so it is still found if
+    /// the layouts file changed since it was loaded.
+    fn apply(&mut self) {
+        let Some(index) = self.selection() else {
+            return;
+        };
+        let layout = match &self.layout_data.layouts[index].name {
+            Some(name) => name.clone(),
+            None => index.to_string(),
+        };
+        let output = std::env::current_exe().and_then(|exe| {
+            let mut command = Command::new(exe);
+            command
+                .arg("--config")
+                .arg(&self.args.config_path)
+                .arg("--layouts")
+                .arg(&self.args.layouts);
+            if self.args.read_only {
+                command.arg("--read-only");
+            }
+            if self.args.dry_run {
+                command.arg("--dry-run");
+            }
+            command.args(["apply", &layout]).output()
+        });
+        self.status = match output {
+            Ok(output) if output.status.success() => format!("Applied layout {index}"),
+            Ok(output) => format!(
+                "Failed to apply layout {index}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => format!("Failed to run wl-distore apply: {err}"),
+        };
+        match LayoutData::load_with_read_only(
+            &self.args.seed_layouts,
+            self.args.layouts_ro.as_deref(),
+            &self.args.layouts,
+            self.args.layouts_dir.as_deref(),
+            Recovery::Never,
+        ) {
+            Ok(layout_data) => self.layout_data = layout_data,
+            Err(err) => self.status = format!("Failed to reload layouts: {err}"),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, status_area, help_area] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, details_area] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main_area);
+
+        let items = self
+            .layout_data
+            .layouts
+            .iter()
+            .enumerate()
+            .map(|(index, layout)| {
+                let mut flags = vec![];
+                if layout.profile.is_some() {
+                    flags.push(layout.profile());
+                }
+                if layout.pinned {
+                    flags.push("pinned");
+                }
+                if layout.read_only {
+                    flags.push("read-only");
+                }
+                if layout.template {
+                    flags.push("template");
+                }
+                match layout.lid {
+                    Some(LidState::Open) => flags.push("lid open"),
+                    Some(LidState::Closed) => flags.push("lid closed"),
+                    None => {}
+                }
+                match layout.power_source {
+                    Some(PowerSource::Ac) => flags.push("on AC"),
+                    Some(PowerSource::Battery) => flags.push("on battery"),
+                    None => {}
+                }
+                if !layout.auto_apply {
+                    flags.push("manual");
+                }
+                let mut label = format!("{index} {}", layout.name.as_deref().unwrap_or("-"));
+                if !flags.is_empty() {
+                    label.push_str(&format!(" [{}]", flags.join(", ")));
+                }
+                ListItem::new(label)
+            });
+        let list = List::new(items)
+            .block(Block::bordered().title(" Layouts "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        if let Some(index) = self.selection() {
+            let layout = &self.layout_data.layouts[index];
+            let mut heads = layout.heads.iter().collect::<Vec<_>>();
+            heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+            let rows = heads.into_iter().map(|(identity, configuration)| {
+                let mut row = vec![identity.name.clone(), identity.description.clone()];
+                row.extend(cli::describe_configuration(configuration.as_ref()));
+                Row::new(row)
+            });
+            let widths = [
+                Constraint::Length(10),
+                Constraint::Fill(1),
+                Constraint::Length(22),
+                Constraint::Length(11),
+                Constraint::Length(5),
+                Constraint::Length(11),
+            ];
+            let [table_area, comment_area] =
+                Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).areas(details_area);
+            let table = Table::new(rows, widths)
+                .header(
+                    Row::new([
+                        "HEAD",
+                        "DESCRIPTION",
+                        "MODE",
+                        "POSITION",
+                        "SCALE",
+                        "TRANSFORM",
+                    ])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+                )
+                .block(Block::bordered().title(format!(" Layout {index} ")));
+            frame.render_widget(table, table_area);
+            let comment = Paragraph::new(layout.comment.as_deref().unwrap_or("-"))
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().title(" Comment "));
+            frame.render_widget(comment, comment_area);
+        } else {
+            frame.render_widget(
+                Paragraph::new("There are no saved layouts").block(Block::bordered()),
+                details_area,
+            );
+        }
+
+        let status = match &self.mode {
+            InputMode::Browse => Line::from(self.status.as_str()),
+            InputMode::Rename(name) => {
+                Line::from(format!("New name (enter to save, esc to cancel): {name}"))
+            }
+            InputMode::ConfirmDelete => Line::from("Delete this layout? (y/n)"),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::default().add_modifier(Modifier::DIM)),
+            help_area,
+        );
+    }
+}
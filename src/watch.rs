@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use crate::{complete::HeadIdentity, ipc::Event, serde::SavedConfiguration};
+
+/// The state of a head as last printed by the `watch` subcommand, or sent to subscribers.
+#[derive(Clone, PartialEq)]
+pub struct WatchedHead {
+    pub configuration: Option<SavedConfiguration>,
+    pub mode_count: usize,
+}
+
+/// Prints a line for every head that was added, removed, or changed between `previous` and
+/// `current`.
+pub fn print_changes(
+    serial: u32,
+    previous: &HashMap<HeadIdentity, WatchedHead>,
+    current: &HashMap<HeadIdentity, WatchedHead>,
+) {
+    let mut added = current
+        .iter()
+        .filter(|(identity, _)| !previous.contains_key(identity))
+        .collect::<Vec<_>>();
+    added.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+    for (identity, head) in added {
+        println!(
+            "[{serial}] head added: {} \"{}\" ({} modes) {}",
+            identity.name,
+            identity.description,
+            head.mode_count,
+            describe_configuration(head.configuration.as_ref()),
+        );
+    }
+
+    let mut removed = previous
+        .keys()
+        .filter(|identity| !current.contains_key(identity))
+        .collect::<Vec<_>>();
+    removed.sort_by(|a, b| a.name.cmp(&b.name));
+    for identity in removed {
+        println!("[{serial}] head removed: {}", identity.name);
+    }
+
+    let mut changed = current
+        .iter()
+        .filter_map(|(identity, head)| {
+            let previous_head = previous.get(identity)?;
+            (previous_head != head).then_some((identity, previous_head, head))
+        })
+        .collect::<Vec<_>>();
+    changed.sort_by(|(a, _, _), (b, _, _)| a.name.cmp(&b.name));
+    for (identity, previous_head, head) in changed {
+        for change in describe_head_changes(previous_head, head) {
+            println!("[{serial}] head changed: {} {change}", identity.name);
+        }
+    }
+}
+
+/// Returns an event for every head that was added, removed, or whose configuration changed
+/// between `previous` and `current`.
+pub fn events(
+    previous: &HashMap<HeadIdentity, WatchedHead>,
+    current: &HashMap<HeadIdentity, WatchedHead>,
+) -> Vec<Event> {
+    let mut events = previous
+        .keys()
+        .filter(|identity| !current.contains_key(identity))
+        .map(|identity| Event::HeadRemoved {
+            head: identity.clone(),
+        })
+        .collect::<Vec<_>>();
+    let mut heads = current.iter().collect::<Vec<_>>();
+    heads.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+    for (identity, head) in heads {
+        match previous.get(identity) {
+            None => events.push(Event::HeadAdded {
+                head: identity.clone(),
+                configuration: head.configuration.clone(),
+            }),
+            Some(previous_head) if previous_head.configuration != head.configuration => events
+                .push(Event::HeadChanged {
+                    head: identity.clone(),
+                    configuration: head.configuration.clone(),
+                }),
+            Some(_) => {}
+        }
+    }
+    events
+}
+
+/// Describes `configuration` on a single line.
+fn describe_configuration(configuration: Option<&SavedConfiguration>) -> String {
+    let Some(configuration) = configuration else {
+        return "disabled".into();
+    };
+    format!(
+        "enabled mode={} position={},{} transform={:?} scale={} adaptive_sync={}",
+        describe_mode(configuration),
+        configuration.position.0,
+        configuration.position.1,
+        configuration.transform,
+        configuration.scale,
+        describe_adaptive_sync(configuration.adaptive_sync),
+    )
+}
+
+/// Describes each property that differs between `previous` and `current`.
+fn describe_head_changes(previous: &WatchedHead, current: &WatchedHead) -> Vec<String> {
+    let mut changes = Vec::new();
+    if previous.mode_count != current.mode_count {
+        changes.push(format!(
+            "modes: {} -> {}",
+            previous.mode_count, current.mode_count
+        ));
+    }
+    let (previous, current) = match (&previous.configuration, &current.configuration) {
+        (Some(previous), Some(current)) => (previous, current),
+        (previous, current) => {
+            if previous != current {
+                changes.push(format!(
+                    "{} -> {}",
+                    describe_configuration(previous.as_ref()),
+                    describe_configuration(current.as_ref())
+                ));
+            }
+            return changes;
+        }
+    };
+    if previous.mode != current.mode {
+        changes.push(format!(
+            "mode: {} -> {}",
+            describe_mode(previous),
+            describe_mode(current)
+        ));
+    }
+    if previous.position != current.position {
+        changes.push(format!(
+            "position: {},{} -> {},{}",
+            previous.position.0, previous.position.1, current.position.0, current.position.1
+        ));
+    }
+    if previous.transform != current.transform {
+        changes.push(format!(
+            "transform: {:?} -> {:?}",
+            previous.transform, current.transform
+        ));
+    }
+    if previous.scale != current.scale {
+        changes.push(format!("scale: {} -> {}", previous.scale, current.scale));
+    }
+    if previous.adaptive_sync != current.adaptive_sync {
+        changes.push(format!(
+            "adaptive_sync: {} -> {}",
+            describe_adaptive_sync(previous.adaptive_sync),
+            describe_adaptive_sync(current.adaptive_sync)
+        ));
+    }
+    changes
+}
+
+fn describe_mode(configuration: &SavedConfiguration) -> String {
+    configuration
+        .mode
+        .map(|mode| mode.to_string())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+fn describe_adaptive_sync(adaptive_sync: Option<bool>) -> &'static str {
+    match adaptive_sync {
+        None => "unknown",
+        Some(true) => "enabled",
+        Some(false) => "disabled",
+    }
+}